@@ -17,6 +17,9 @@ pub(crate) struct Args {
     #[argh(option, short = 'b', default = r#"String::from("0.0.0.0:7878")"#)]
     pub(crate) bind: String,
 
+    #[argh(option)]
+    pub(crate) public_url: Option<String>,
+
     #[argh(option, short = 'a', default = r#"String::from("")"#)]
     pub(crate) address: String,
 
@@ -24,10 +27,22 @@ pub(crate) struct Args {
     pub(crate) interface: Option<String>,
 
     #[argh(option)]
-    pub(crate) extra_playlist: Option<String>,
+    pub(crate) extra_playlist: Vec<String>,
+
+    #[argh(option)]
+    pub(crate) extra_playlist_include: Option<String>,
+
+    #[argh(option)]
+    pub(crate) extra_playlist_exclude: Option<String>,
+
+    #[argh(switch)]
+    pub(crate) extra_playlist_proxy: bool,
+
+    #[argh(option)]
+    pub(crate) extra_xmltv: Vec<String>,
 
     #[argh(option)]
-    pub(crate) extra_xmltv: Option<String>,
+    pub(crate) xmltv_mapping: Option<String>,
 
     #[argh(option)]
     pub(crate) channel_mapping: Option<String>,
@@ -37,4 +52,58 @@ pub(crate) struct Args {
 
     #[argh(switch)]
     pub(crate) rtsp_proxy: bool,
+
+    #[argh(option, default = "5")]
+    pub(crate) failover_timeout: u64,
+
+    #[argh(option, default = r#"String::from("append")"#)]
+    pub(crate) catchup_style: String,
+
+    #[argh(option, default = "7")]
+    pub(crate) catchup_days: u32,
+
+    #[argh(option, default = r#"String::from("+08:00")"#)]
+    pub(crate) timezone: String,
+
+    #[argh(option, default = "7")]
+    pub(crate) epg_history_days: u32,
+
+    #[argh(option, default = "2")]
+    pub(crate) epg_concurrency: usize,
+
+    #[argh(option, default = "5.0")]
+    pub(crate) epg_rate: f64,
+
+    #[argh(option)]
+    pub(crate) epg_gap_fill: Option<String>,
+
+    #[argh(switch)]
+    pub(crate) dummy_epg: bool,
+
+    #[argh(option, default = "60")]
+    pub(crate) dummy_epg_minutes: u32,
+
+    #[argh(option, default = r#"String::from("{name}")"#)]
+    pub(crate) dummy_epg_title: String,
+
+    #[argh(option, default = "16")]
+    pub(crate) logo_cache_mb: usize,
+
+    #[argh(option)]
+    pub(crate) logo_dir: Option<String>,
+
+    #[argh(option)]
+    pub(crate) logo_url_template: Option<String>,
+
+    #[argh(option)]
+    pub(crate) logo_size: Option<String>,
+
+    #[argh(option, default = "String::from(\"original\")")]
+    pub(crate) logo_format: String,
+
+    #[argh(option)]
+    pub(crate) geoip_db: Option<String>,
+
+    #[argh(switch)]
+    pub(crate) geoip_online: bool,
 }
@@ -0,0 +1,47 @@
+use std::{
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use log::warn;
+
+use crate::args::Args;
+use crate::iptv::{get_upstream_channels, Channel};
+
+// 频道目录缓存，保存上游原始地址，供按频道ID播放时解析
+static CATALOG: LazyLock<Mutex<Option<Catalog>>> = LazyLock::new(|| Mutex::new(None));
+const CATALOG_TTL: Duration = Duration::from_secs(600);
+
+type Catalog = (Instant, Vec<Channel>);
+
+pub(crate) async fn channels(args: &Args) -> Result<Vec<Channel>> {
+    if let Ok(catalog) = CATALOG.try_lock() {
+        if let Some((fetched, ref channels)) = *catalog {
+            if fetched.elapsed() < CATALOG_TTL {
+                return Ok(channels.clone());
+            }
+        }
+    }
+
+    match get_upstream_channels(args, false).await {
+        Ok(channels) => {
+            if let Ok(mut catalog) = CATALOG.try_lock() {
+                *catalog = Some((Instant::now(), channels.clone()));
+            }
+            Ok(channels)
+        }
+        Err(e) => {
+            // 上游暂时不可用时使用过期的目录
+            if let Some((_, ref channels)) = *CATALOG.lock().unwrap_or_else(|e| e.into_inner()) {
+                warn!("Failed to refresh channel catalog, using stale copy: {}", e);
+                return Ok(channels.clone());
+            }
+            Err(e)
+        }
+    }
+}
+
+pub(crate) async fn channel(args: &Args, id: u64) -> Result<Option<Channel>> {
+    Ok(channels(args).await?.into_iter().find(|c| c.id == id))
+}
@@ -0,0 +1,170 @@
+use std::{str::FromStr, sync::OnceLock};
+
+use anyhow::{anyhow, Result};
+use chrono::{FixedOffset, NaiveDateTime, TimeZone, Utc};
+
+use crate::iptv::Channel;
+
+// 上游回看和XMLTV使用的时区，启动时由 --timezone 设置
+static LOCAL_OFFSET: OnceLock<FixedOffset> = OnceLock::new();
+
+const PLAYSEEK_FORMAT: &str = "%Y%m%d%H%M%S";
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum CatchupStyle {
+    // catchup-source 追加到直播地址后
+    Append,
+    // catchup-source 为完整地址
+    Default,
+    // 播放器在直播地址后追加 ?utc=&lutc=
+    Shift,
+    // 播放器把 /mpegts 替换为 /timeshift_abs-{start}.ts
+    Flussonic,
+    // Xtream Codes 的 /timeshift/{user}/{pass}/{duration}/{start}/{id}.ts
+    Xtream,
+}
+
+impl FromStr for CatchupStyle {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "append" => Ok(CatchupStyle::Append),
+            "default" => Ok(CatchupStyle::Default),
+            "shift" => Ok(CatchupStyle::Shift),
+            "flussonic" | "fs" => Ok(CatchupStyle::Flussonic),
+            "xtream" | "xc" => Ok(CatchupStyle::Xtream),
+            _ => Err(anyhow!("Unknown catchup style: {}", s)),
+        }
+    }
+}
+
+// 解析 "+08:00"、"+0800"、"8"、"-5"、"UTC" 这样的时区
+pub(crate) fn parse_offset(s: &str) -> Option<FixedOffset> {
+    let s = s.trim();
+    if s.eq_ignore_ascii_case("utc") || s.eq_ignore_ascii_case("z") {
+        return FixedOffset::east_opt(0);
+    }
+    let (sign, rest) = match s.as_bytes().first()? {
+        b'-' => (-1, &s[1..]),
+        b'+' => (1, &s[1..]),
+        _ => (1, s),
+    };
+    let digits = rest.replace(':', "");
+    let (hours, minutes) = match digits.len() {
+        1 | 2 => (digits.parse::<i32>().ok()?, 0),
+        4 => (digits[..2].parse::<i32>().ok()?, digits[2..].parse::<i32>().ok()?),
+        _ => return None,
+    };
+    if minutes >= 60 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+pub(crate) fn init(timezone: &str) -> Result<()> {
+    let offset = parse_offset(timezone).ok_or_else(|| anyhow!("Invalid timezone: {}", timezone))?;
+    LOCAL_OFFSET.set(offset).map_err(|_| anyhow!("Timezone already set"))
+}
+
+pub(crate) fn local_offset() -> FixedOffset {
+    *LOCAL_OFFSET.get_or_init(|| FixedOffset::east_opt(8 * 3600).unwrap())
+}
+
+// 上游 zoneoffset 参数，单位为分钟
+pub(crate) fn zone_offset_minutes() -> i32 {
+    local_offset().local_minus_utc() / 60
+}
+
+// XMLTV 时区后缀，如 +0800
+pub(crate) fn offset_suffix() -> String {
+    local_offset().to_string().replace(':', "")
+}
+
+// 毫秒时间戳转为上游使用的本地时间 yyyyMMddHHmmss
+pub(crate) fn format_local(unix_ms: i64) -> Result<String> {
+    match Utc.timestamp_millis_opt(unix_ms) {
+        chrono::LocalResult::Single(t) => Ok(t.with_timezone(&local_offset()).format(PLAYSEEK_FORMAT).to_string()),
+        _ => Err(anyhow!("fail to parse time")),
+    }
+}
+
+// 回看时间：14位本地时间 yyyyMMddHHmmss，或正的Unix时间戳（秒/毫秒）
+pub(crate) fn parse_time(time: &str) -> Option<i64> {
+    if time.len() == 14 {
+        let naive = NaiveDateTime::parse_from_str(time, PLAYSEEK_FORMAT).ok()?;
+        return Some(local_offset().from_local_datetime(&naive).single()?.timestamp_millis());
+    }
+    let value = time.parse::<i64>().ok().filter(|&v| v > 0)?;
+    if value < 100_000_000_000 {
+        value.checked_mul(1000)
+    } else {
+        Some(value)
+    }
+}
+
+// Xtream 的开始时间 YYYY-MM-DD:HH-MM
+pub(crate) fn parse_xtream_time(time: &str) -> Option<i64> {
+    let naive = NaiveDateTime::parse_from_str(time, "%Y-%m-%d:%H-%M").ok()?;
+    Some(local_offset().from_local_datetime(&naive).single()?.timestamp_millis())
+}
+
+// 在上游RTSP地址上加 playseek 参数
+pub(crate) fn playseek_url(rtsp: &str, start: i64, end: Option<i64>) -> Result<String> {
+    let playseek = match end {
+        Some(end) => format!("{}-{}", format_local(start)?, format_local(end)?),
+        None => format!("{}-", format_local(start)?),
+    };
+    let separator = if rtsp.contains('?') { '&' } else { '?' };
+    Ok(format!("{}{}playseek={}", rtsp, separator, playseek))
+}
+
+// 生成播放列表中频道的直播地址和回看属性
+pub(crate) fn playlist_entry(
+    style: CatchupStyle,
+    channel: &Channel,
+    base: &str,
+    stream_url: &str,
+) -> (String, String) {
+    let proxied = stream_url.starts_with(base);
+    let channel_url = format!("{}/channel/{}", base, channel.id);
+    let stream_url = match style {
+        CatchupStyle::Flussonic if channel.catchup => format!("{}/mpegts", channel_url),
+        CatchupStyle::Xtream if channel.catchup => format!("{}/live/iptv/iptv/{}.ts", base, channel.id),
+        CatchupStyle::Shift if channel.catchup => channel_url.clone(),
+        _ => stream_url.to_string(),
+    };
+    if !channel.catchup {
+        return (String::new(), stream_url);
+    }
+
+    let range = "start=${(b)yyyyMMddHHmmss}&end=${(e)yyyyMMddHHmmss}";
+    let playseek = "playseek=${(b)yyyyMMddHHmmss}-${(e)yyyyMMddHHmmss}";
+    let (kind, source) = match style {
+        // HLS 播放列表地址后不能追加路径，改用完整的回看地址
+        CatchupStyle::Append if proxied && stream_url.ends_with(".m3u8") => {
+            ("default", format!("{}/catchup?{}", channel_url, range))
+        }
+        CatchupStyle::Append if proxied => ("append", format!("/catchup?{}", range)),
+        CatchupStyle::Append => {
+            let separator = if stream_url.contains('?') { '&' } else { '?' };
+            ("append", format!("{}{}", separator, playseek))
+        }
+        CatchupStyle::Default if proxied => ("default", format!("{}/catchup?{}", channel_url, range)),
+        CatchupStyle::Default => {
+            let separator = if channel.rtsp.contains('?') { '&' } else { '?' };
+            ("default", format!("{}{}{}", channel.rtsp, separator, playseek))
+        }
+        CatchupStyle::Shift => ("shift", String::new()),
+        CatchupStyle::Flussonic => ("flussonic", format!("{}/timeshift_abs-${{start}}.ts", channel_url)),
+        CatchupStyle::Xtream => (
+            "xc",
+            format!("{}/timeshift/iptv/iptv/{{duration:60}}/{{Y}}-{{m}}-{{d}}:{{H}}-{{M}}/{}.ts", base, channel.id),
+        ),
+    };
+    let mut attrs = format!(r#" catchup="{}" catchup-days="{}""#, kind, channel.catchup_days);
+    if !source.is_empty() {
+        attrs += &format!(r#" catchup-source="{}""#, source);
+    }
+    (attrs, stream_url)
+}
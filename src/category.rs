@@ -0,0 +1,168 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    path::Path,
+    sync::{LazyLock, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use regex_lite::Regex;
+use serde::{Deserialize, Serialize};
+
+const CATEGORY_FILE: &str = "category_rules.json";
+
+// 默认规则不含正则，不会失败
+static CATEGORIES: LazyLock<Mutex<Categorizer>> =
+    LazyLock::new(|| Mutex::new(Categorizer::new(CategoryConfig::default()).unwrap()));
+
+// 匹配频道名：{"keyword": "卫视"}、{"regex": "^CCTV-?\\d+"}
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Pattern {
+    Keyword(String),
+    Regex(String),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct CategoryRule {
+    #[serde(rename = "match")]
+    pub(crate) pattern: Pattern,
+    pub(crate) group: String,
+    // 匹配后不再检查后面的规则
+    #[serde(default)]
+    pub(crate) stop: bool,
+}
+
+// 按顺序检查全部规则，匹配的分组都加入，第一个为主分组
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct CategoryConfig {
+    #[serde(default)]
+    pub(crate) rules: Vec<CategoryRule>,
+    // 频道ID -> 分组，优先于规则
+    #[serde(default)]
+    pub(crate) overrides: HashMap<u64, Vec<String>>,
+    // 没有规则匹配时的分组
+    #[serde(default = "default_group")]
+    pub(crate) default: String,
+}
+
+fn default_group() -> String {
+    "普通频道".to_string()
+}
+
+fn keyword(keyword: &str, group: &str) -> CategoryRule {
+    CategoryRule {
+        pattern: Pattern::Keyword(keyword.to_string()),
+        group: group.to_string(),
+        stop: true,
+    }
+}
+
+// 没有配置时按清晰度分组
+impl Default for CategoryConfig {
+    fn default() -> Self {
+        Self {
+            rules: vec![
+                keyword("超高清", "超清频道"),
+                keyword("4K", "超清频道"),
+                keyword("高清", "高清频道"),
+                keyword("超清", "高清频道"),
+                keyword("卫视", "高清频道"),
+            ],
+            overrides: HashMap::new(),
+            default: default_group(),
+        }
+    }
+}
+
+enum Compiled {
+    Keyword(String),
+    Regex(Regex),
+}
+
+struct Categorizer {
+    config: CategoryConfig,
+    rules: Vec<(Compiled, String, bool)>,
+}
+
+impl Categorizer {
+    fn new(config: CategoryConfig) -> Result<Self> {
+        let rules = config
+            .rules
+            .iter()
+            .map(|r| {
+                let compiled = match &r.pattern {
+                    Pattern::Keyword(k) => Compiled::Keyword(k.clone()),
+                    Pattern::Regex(re) => {
+                        Compiled::Regex(Regex::new(re).map_err(|e| anyhow!("Invalid regex '{}': {}", re, e))?)
+                    }
+                };
+                Ok((compiled, r.group.clone(), r.stop))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { config, rules })
+    }
+
+    fn groups(&self, id: u64, name: &str) -> Vec<String> {
+        if let Some(groups) = self.config.overrides.get(&id).filter(|g| !g.is_empty()) {
+            return groups.clone();
+        }
+        let mut groups: Vec<String> = Vec::new();
+        for (pattern, group, stop) in self.rules.iter() {
+            let matched = match pattern {
+                Compiled::Keyword(k) => name.contains(k.as_str()),
+                Compiled::Regex(re) => re.is_match(name),
+            };
+            if matched {
+                if !groups.contains(group) {
+                    groups.push(group.clone());
+                }
+                if *stop {
+                    break;
+                }
+            }
+        }
+        if groups.is_empty() {
+            groups.push(self.config.default.clone());
+        }
+        groups
+    }
+}
+
+// 频道所属的分组，至少有一个
+pub(crate) fn groups(id: u64, name: &str) -> Vec<String> {
+    CATEGORIES
+        .lock()
+        .map(|c| c.groups(id, name))
+        .unwrap_or_else(|_| vec![default_group()])
+}
+
+pub(crate) fn config() -> CategoryConfig {
+    CATEGORIES.lock().map(|c| c.config.clone()).unwrap_or_default()
+}
+
+pub(crate) fn set(config: CategoryConfig) -> Result<()> {
+    let categorizer = Categorizer::new(config.clone())?;
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(CATEGORY_FILE)?;
+    serde_json::to_writer_pretty(file, &config)?;
+    if let Ok(mut current) = CATEGORIES.lock() {
+        *current = categorizer;
+    }
+    Ok(())
+}
+
+pub(crate) fn load() -> Result<()> {
+    if Path::new(CATEGORY_FILE).exists() {
+        let config: CategoryConfig = serde_json::from_reader(File::open(CATEGORY_FILE)?)?;
+        log::info!("Loaded {} category rules", config.rules.len());
+        let categorizer = Categorizer::new(config)?;
+        if let Ok(mut current) = CATEGORIES.lock() {
+            *current = categorizer;
+        }
+    }
+    Ok(())
+}
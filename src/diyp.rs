@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use chrono::{NaiveDate, TimeZone, Utc};
+use serde::Serialize;
+
+use crate::catchup;
+use crate::iptv::{Channel, Program};
+use crate::xmltv_sources::normalize_name;
+
+const DAY_MS: i64 = 86_400_000;
+
+// DIYP/百川/超级直播 使用的 EPG JSON 格式
+#[derive(Serialize)]
+pub(crate) struct DiypEpg {
+    channel_name: String,
+    date: String,
+    url: String,
+    epg_data: Vec<DiypProgramme>,
+    // 当天请求时附带正在播放的节目
+    #[serde(skip_serializing_if = "Option::is_none")]
+    current: Option<DiypProgramme>,
+}
+
+#[derive(Serialize)]
+struct DiypProgramme {
+    start: String,
+    end: String,
+    title: String,
+    desc: String,
+}
+
+impl DiypProgramme {
+    fn new(programme: &Program) -> Self {
+        Self {
+            start: format_hm(programme.start),
+            end: format_hm(programme.stop),
+            title: programme.title.clone(),
+            desc: programme.desc.clone(),
+        }
+    }
+}
+
+fn format_hm(unix_ms: i64) -> String {
+    Utc.timestamp_millis_opt(unix_ms)
+        .single()
+        .map(|t| t.with_timezone(&catchup::local_offset()).format("%H:%M").to_string())
+        .unwrap_or_default()
+}
+
+pub(crate) fn today() -> NaiveDate {
+    Utc::now().with_timezone(&catchup::local_offset()).date_naive()
+}
+
+// 日期：2024-01-01 或 20240101
+pub(crate) fn parse_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(date, "%Y%m%d"))
+        .ok()
+}
+
+// 按名称查找频道：先精确匹配名称或ID，再按规范化名称匹配，优先有节目单的频道
+pub(crate) fn find_channel<'a>(
+    channels: &'a [Channel],
+    name: &str,
+    epg: &HashMap<u64, Vec<Program>>,
+) -> Option<&'a Channel> {
+    let name = name.trim();
+    if let Some(channel) = channels
+        .iter()
+        .find(|c| c.name == name || c.id.to_string() == name)
+    {
+        return Some(channel);
+    }
+    let normalized = normalize_name(name);
+    let mut candidates = channels.iter().filter(|c| normalize_name(&c.name) == normalized);
+    let first = candidates.next()?;
+    std::iter::once(first)
+        .chain(candidates)
+        .find(|c| epg.get(&c.id).is_some_and(|p| !p.is_empty()))
+        .or(Some(first))
+}
+
+pub(crate) fn epg_for_day(name: &str, date: NaiveDate, programmes: &[Program]) -> DiypEpg {
+    let offset = catchup::local_offset();
+    let day_start = date
+        .and_hms_opt(0, 0, 0)
+        .and_then(|t| offset.from_local_datetime(&t).single())
+        .map(|t| t.timestamp_millis())
+        .unwrap_or_default();
+    let day_end = day_start + DAY_MS;
+
+    let epg_data = programmes
+        .iter()
+        .filter(|p| p.start >= day_start && p.start < day_end)
+        .map(DiypProgramme::new)
+        .collect();
+    let current = if date == today() {
+        let now = Utc::now().timestamp_millis();
+        programmes
+            .iter()
+            .find(|p| p.start <= now && now < p.stop)
+            .map(DiypProgramme::new)
+    } else {
+        None
+    };
+
+    DiypEpg {
+        channel_name: name.to_string(),
+        date: date.format("%Y-%m-%d").to_string(),
+        url: "https://github.com/spacex-3/rust-iptv-proxy".to_string(),
+        epg_data,
+        current,
+    }
+}
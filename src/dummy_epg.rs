@@ -0,0 +1,55 @@
+use chrono::{Duration, TimeZone, Utc};
+
+use crate::args::Args;
+use crate::catchup;
+use crate::epg_store::FUTURE_DAYS;
+use crate::iptv::{Channel, Program};
+
+// 没有节目单的频道生成的占位节目
+#[derive(Clone)]
+pub(crate) struct DummyEpg {
+    block_minutes: i64,
+    // 标题模板，{name} 为频道名，{id} 为频道ID
+    title: String,
+}
+
+impl DummyEpg {
+    pub(crate) fn from_args(args: &Args) -> Option<Self> {
+        args.dummy_epg.then(|| Self {
+            block_minutes: args.dummy_epg_minutes.max(1) as i64,
+            title: args.dummy_epg_title.clone(),
+        })
+    }
+
+    // 从昨天零点到节目单存储的最后一天，按本地时间对齐的固定时长节目
+    pub(crate) fn programmes(&self, channel: &Channel) -> Vec<Program> {
+        let offset = catchup::local_offset();
+        let today = Utc::now().with_timezone(&offset).date_naive();
+        let at_midnight = |date: chrono::NaiveDate| {
+            date.and_hms_opt(0, 0, 0)
+                .and_then(|t| offset.from_local_datetime(&t).single())
+                .map(|t| t.timestamp_millis())
+        };
+        let (Some(begin), Some(end)) = (
+            at_midnight(today - Duration::days(1)),
+            at_midnight(today + Duration::days(FUTURE_DAYS + 1)),
+        ) else {
+            return vec![];
+        };
+
+        let title = self
+            .title
+            .replace("{name}", &channel.name)
+            .replace("{id}", &channel.id.to_string());
+        let block = self.block_minutes * 60 * 1000;
+        (begin..end)
+            .step_by(block as usize)
+            .map(|start| Program {
+                start,
+                stop: (start + block).min(end),
+                title: title.clone(),
+                ..Default::default()
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,388 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    fs::{self, File, OpenOptions},
+    path::Path,
+    sync::{LazyLock, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use futures_util::{stream, StreamExt};
+use log::{debug, error, info, warn};
+use rand::Rng;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tokio::{task::JoinHandle, time::Instant};
+
+use crate::args::Args;
+use crate::epg_sanitize::{self, Anomalies};
+use crate::epg_store::{self, EPG_STORE};
+use crate::iptv::{self, Channel, Program};
+
+const CRAWL_STATE_FILE: &str = "epg_crawl.json";
+// 保留最近的任务记录数
+const MAX_JOBS: usize = 20;
+const MAX_ATTEMPTS: u32 = 4;
+const BACKOFF_BASE_MS: u64 = 1000;
+const BACKOFF_MAX_MS: u64 = 30_000;
+// 每完成这么多频道保存一次节目单存储和进度
+const CHECKPOINT_EVERY: usize = 20;
+
+static JOBS: LazyLock<Mutex<JobList>> = LazyLock::new(|| Mutex::new(JobList::default()));
+// 同一时间只运行一个任务，其余排队
+static RUNNER: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+#[derive(Default)]
+struct JobList {
+    next_id: u64,
+    jobs: VecDeque<EpgJob>,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum JobStatus {
+    Queued,
+    Running,
+    Finished,
+    Failed,
+}
+
+#[derive(Serialize, Clone)]
+pub(crate) struct ChannelError {
+    channel_id: u64,
+    channel_name: String,
+    attempts: u32,
+    error: String,
+}
+
+#[derive(Serialize, Clone)]
+pub(crate) struct ChannelAnomalies {
+    channel_id: u64,
+    channel_name: String,
+    #[serde(flatten)]
+    anomalies: Anomalies,
+}
+
+#[derive(Serialize, Clone)]
+pub(crate) struct EpgJob {
+    id: u64,
+    trigger: String,
+    status: JobStatus,
+    created_at: i64,
+    started_at: Option<i64>,
+    finished_at: Option<i64>,
+    total: usize,     // 频道总数
+    skipped: usize,   // 节目单已是最新或上次已完成的频道
+    completed: usize,
+    failed: usize,
+    requests: usize,
+    errors: Vec<ChannelError>,
+    // 节目单中发现的重叠、重复等问题
+    anomalies: Vec<ChannelAnomalies>,
+    error: Option<String>,
+}
+
+// 未完成任务的进度，异常退出后重启时据此继续
+#[derive(Serialize, Deserialize, Default)]
+struct CrawlState {
+    trigger: String,
+    done: HashSet<u64>,
+}
+
+// 按固定间隔放行请求
+struct RateLimiter {
+    interval: Duration,
+    next: tokio::sync::Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(per_second: f64) -> Self {
+        let interval = if per_second > 0.0 {
+            Duration::from_secs_f64(1.0 / per_second)
+        } else {
+            Duration::ZERO
+        };
+        Self {
+            interval,
+            next: tokio::sync::Mutex::new(Instant::now()),
+        }
+    }
+
+    async fn acquire(&self) {
+        let mut next = self.next.lock().await;
+        let now = Instant::now();
+        if *next > now {
+            tokio::time::sleep_until(*next).await;
+        }
+        *next = (*next).max(now) + self.interval;
+    }
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+fn update(id: u64, f: impl FnOnce(&mut EpgJob)) {
+    if let Ok(mut list) = JOBS.lock() {
+        if let Some(job) = list.jobs.iter_mut().find(|j| j.id == id) {
+            f(job);
+        }
+    }
+}
+
+// 最近的任务，新任务在前
+pub(crate) fn jobs() -> Vec<EpgJob> {
+    JOBS.lock()
+        .map(|list| list.jobs.iter().rev().cloned().collect())
+        .unwrap_or_default()
+}
+
+pub(crate) fn job(id: u64) -> Option<EpgJob> {
+    JOBS.lock()
+        .ok()
+        .and_then(|list| list.jobs.iter().find(|j| j.id == id).cloned())
+}
+
+// 提交一个EPG获取任务，返回任务ID和任务句柄
+pub(crate) fn submit(args: Args, trigger: &str) -> (u64, JoinHandle<Result<()>>) {
+    spawn_job(args, CrawlState {
+        trigger: trigger.to_string(),
+        done: HashSet::new(),
+    })
+}
+
+// 上次任务没有完成时，继续获取剩下的频道
+pub(crate) fn resume(args: &Args) -> Option<(u64, JoinHandle<Result<()>>)> {
+    if !Path::new(CRAWL_STATE_FILE).exists() {
+        return None;
+    }
+    let state = match File::open(CRAWL_STATE_FILE)
+        .map_err(anyhow::Error::from)
+        .and_then(|file| Ok(serde_json::from_reader::<_, CrawlState>(file)?))
+    {
+        Ok(state) => state,
+        Err(e) => {
+            warn!("Failed to load EPG crawl state: {}", e);
+            return None;
+        }
+    };
+    info!("Resuming interrupted EPG job, {} channels already done", state.done.len());
+    Some(spawn_job(args.clone(), state))
+}
+
+fn spawn_job(args: Args, state: CrawlState) -> (u64, JoinHandle<Result<()>>) {
+    let id = match JOBS.lock() {
+        Ok(mut list) => {
+            list.next_id += 1;
+            let id = list.next_id;
+            list.jobs.push_back(EpgJob {
+                id,
+                trigger: state.trigger.clone(),
+                status: JobStatus::Queued,
+                created_at: now_ms(),
+                started_at: None,
+                finished_at: None,
+                total: 0,
+                skipped: 0,
+                completed: 0,
+                failed: 0,
+                requests: 0,
+                errors: vec![],
+                anomalies: vec![],
+                error: None,
+            });
+            while list.jobs.len() > MAX_JOBS {
+                list.jobs.pop_front();
+            }
+            id
+        }
+        Err(_) => 0,
+    };
+    let handle = tokio::spawn(async move {
+        let _running = RUNNER.lock().await;
+        update(id, |job| {
+            job.status = JobStatus::Running;
+            job.started_at = Some(now_ms());
+        });
+        info!("EPG job {} started", id);
+        let result = crawl(&args, id, state).await;
+        update(id, |job| {
+            job.finished_at = Some(now_ms());
+            match &result {
+                Ok(()) => job.status = JobStatus::Finished,
+                Err(e) => {
+                    job.status = JobStatus::Failed;
+                    job.error = Some(e.to_string());
+                }
+            }
+        });
+        match &result {
+            Ok(()) => info!("EPG job {} finished", id),
+            Err(e) => error!("EPG job {} failed: {}", id, e),
+        }
+        result
+    });
+    (id, handle)
+}
+
+async fn crawl(args: &Args, id: u64, mut state: CrawlState) -> Result<()> {
+    let (client, base_url) = iptv::login(args).await?;
+    let channels = iptv::list_channels(&client, &base_url, args).await?;
+
+    let now = now_ms();
+    let history_days = args.epg_history_days as i64;
+    let total = channels.len();
+
+    // 只获取节目单存储中缺少的日期，跳过上次已完成的频道
+    let pending = match EPG_STORE.lock() {
+        Ok(store) => channels
+            .into_iter()
+            .filter(|c| !state.done.contains(&c.id))
+            .map(|c| {
+                let spans = store.missing_spans(c.id, now, history_days);
+                (c, spans)
+            })
+            .filter(|(_, spans)| !spans.is_empty())
+            .collect::<Vec<_>>(),
+        Err(_) => vec![],
+    };
+    update(id, |job| {
+        job.total = total;
+        job.skipped = total - pending.len();
+    });
+    info!("EPG job {}: {} of {} channels need fetching", id, pending.len(), total);
+    save_state(&state)?;
+
+    let limiter = RateLimiter::new(args.epg_rate);
+    let mut results = stream::iter(pending)
+        .map(|(channel, spans)| crawl_channel(&client, &base_url, &limiter, id, channel, spans, now))
+        .buffer_unordered(args.epg_concurrency.max(1));
+
+    let mut since_checkpoint = 0;
+    while let Some((channel, anomalies, result)) = results.next().await {
+        if !anomalies.is_empty() {
+            debug!("'{}' 的节目单存在问题: {}", channel.name, serde_json::to_string(&anomalies).unwrap_or_default());
+            update(id, |job| {
+                job.anomalies.push(ChannelAnomalies {
+                    channel_id: channel.id,
+                    channel_name: channel.name.clone(),
+                    anomalies,
+                })
+            });
+        }
+        match result {
+            Ok(()) => {
+                state.done.insert(channel.id);
+                update(id, |job| job.completed += 1);
+            }
+            Err((attempts, e)) => {
+                warn!("✗ 获取 '{}' 的EPG失败（{}次尝试）: {}", channel.name, attempts, e);
+                update(id, |job| {
+                    job.failed += 1;
+                    job.errors.push(ChannelError {
+                        channel_id: channel.id,
+                        channel_name: channel.name.clone(),
+                        attempts,
+                        error: e.to_string(),
+                    });
+                });
+            }
+        }
+        since_checkpoint += 1;
+        if since_checkpoint >= CHECKPOINT_EVERY {
+            checkpoint(&state).await;
+            since_checkpoint = 0;
+        }
+    }
+    drop(results);
+
+    if let Ok(mut store) = EPG_STORE.lock() {
+        store.prune(now, history_days);
+    }
+    epg_store::save().await?;
+    fs::remove_file(CRAWL_STATE_FILE)?;
+    Ok(())
+}
+
+async fn crawl_channel(
+    client: &Client,
+    base_url: &str,
+    limiter: &RateLimiter,
+    id: u64,
+    channel: Channel,
+    spans: Vec<(i64, i64)>,
+    now: i64,
+) -> (Channel, Anomalies, Result<(), (u32, anyhow::Error)>) {
+    let mut anomalies = Anomalies::default();
+    for (begin, end) in spans {
+        match fetch_with_retry(client, base_url, limiter, id, channel.id, begin, end).await {
+            Ok(mut programmes) => {
+                debug!("✓ 获取到 '{}' 的 {} 个节目", channel.name, programmes.len());
+                // 空档在生成XMLTV时处理，存储中只保留真实节目
+                anomalies += epg_sanitize::sanitize(&mut programmes, None);
+                if let Ok(mut store) = EPG_STORE.lock() {
+                    store.merge(channel.id, programmes);
+                    store.mark_fetched(channel.id, begin, end, now);
+                }
+            }
+            Err(e) => return (channel, anomalies, Err(e)),
+        }
+    }
+    (channel, anomalies, Ok(()))
+}
+
+async fn fetch_with_retry(
+    client: &Client,
+    base_url: &str,
+    limiter: &RateLimiter,
+    id: u64,
+    channel_id: u64,
+    begin: i64,
+    end: i64,
+) -> Result<Vec<Program>, (u32, anyhow::Error)> {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        limiter.acquire().await;
+        update(id, |job| job.requests += 1);
+        match iptv::fetch_playbill(client, base_url, channel_id, begin, end).await {
+            Ok(programmes) => return Ok(programmes),
+            Err(e) if attempt >= MAX_ATTEMPTS => return Err((attempt, e)),
+            Err(e) => {
+                let delay = backoff(attempt);
+                debug!("频道 {} EPG获取第{}次失败，{}ms后重试: {}", channel_id, attempt, delay.as_millis(), e);
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+// 指数退避，在 [d/2, d] 之间随机抖动
+fn backoff(attempt: u32) -> Duration {
+    let delay = BACKOFF_BASE_MS
+        .saturating_mul(1 << (attempt - 1).min(16))
+        .min(BACKOFF_MAX_MS);
+    Duration::from_millis(rand::thread_rng().gen_range(delay / 2..=delay))
+}
+
+async fn checkpoint(state: &CrawlState) {
+    if let Err(e) = epg_store::save().await {
+        error!("Failed to save EPG store: {}", e);
+    }
+    if let Err(e) = save_state(state) {
+        error!("Failed to save EPG crawl state: {}", e);
+    }
+}
+
+fn save_state(state: &CrawlState) -> Result<()> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(CRAWL_STATE_FILE)?;
+    serde_json::to_writer(file, state)?;
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use chrono::{DateTime, NaiveDateTime, TimeZone};
+use encoding_rs::GBK;
+
+use crate::catchup;
+use crate::iptv::Program;
+
+// GB2312 一级汉字按拼音排序，各声母的起始区位码
+const PINYIN_INITIALS: &[(u16, char)] = &[
+    (0xB0A1, 'a'),
+    (0xB0C5, 'b'),
+    (0xB2C1, 'c'),
+    (0xB4EE, 'd'),
+    (0xB6EA, 'e'),
+    (0xB7A2, 'f'),
+    (0xB8C1, 'g'),
+    (0xB9FE, 'h'),
+    (0xBBF7, 'j'),
+    (0xBFA6, 'k'),
+    (0xC0AC, 'l'),
+    (0xC2E8, 'm'),
+    (0xC4C3, 'n'),
+    (0xC5B6, 'o'),
+    (0xC5BE, 'p'),
+    (0xC6DA, 'q'),
+    (0xC8BB, 'r'),
+    (0xC8F6, 's'),
+    (0xCBFA, 't'),
+    (0xCDDA, 'w'),
+    (0xCEF4, 'x'),
+    (0xD1B9, 'y'),
+    (0xD4D1, 'z'),
+];
+// 一级汉字结束位置，之后的二级汉字按部首排序，无法取声母
+const PINYIN_END: u16 = 0xD7FA;
+
+fn pinyin_initial(c: char) -> Option<char> {
+    if c.is_ascii_alphanumeric() {
+        return Some(c.to_ascii_lowercase());
+    }
+    let mut buf = [0u8; 4];
+    let (bytes, _, unmappable) = GBK.encode(c.encode_utf8(&mut buf));
+    if unmappable || bytes.len() != 2 {
+        return None;
+    }
+    let code = u16::from_be_bytes([bytes[0], bytes[1]]);
+    if !(PINYIN_INITIALS[0].0..PINYIN_END).contains(&code) {
+        return None;
+    }
+    PINYIN_INITIALS
+        .iter()
+        .rev()
+        .find(|(start, _)| code >= *start)
+        .map(|(_, initial)| *initial)
+}
+
+// 拼音首字母：新闻联播 -> xwlb，字母和数字保留
+pub(crate) fn pinyin_initials(text: &str) -> String {
+    text.chars().filter_map(pinyin_initial).collect()
+}
+
+// 时间参数：Unix 时间戳（秒或毫秒）、RFC 3339 或本地时间 20240101120000 / 2024-01-01 12:00
+// 时间戳和 20240101120000 与回看参数相同，由 catchup::parse_time 解析
+pub(crate) fn parse_time(value: &str) -> Option<i64> {
+    let value = value.trim();
+    if let Some(ms) = catchup::parse_time(value) {
+        return Some(ms);
+    }
+    if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+        return Some(dt.timestamp_millis());
+    }
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .and_then(|dt| catchup::local_offset().from_local_datetime(&dt).single())
+        .map(|dt| dt.timestamp_millis())
+}
+
+// 与 [from, to) 有重叠的节目
+pub(crate) fn in_range(programme: &Program, from: Option<i64>, to: Option<i64>) -> bool {
+    from.is_none_or(|from| programme.stop > from) && to.is_none_or(|to| programme.start < to)
+}
+
+// 正在播放和下一个节目
+pub(crate) fn now_next(programmes: &[Program], now: i64) -> (Option<&Program>, Option<&Program>) {
+    let current = programmes.iter().find(|p| p.start <= now && now < p.stop);
+    let next = programmes
+        .iter()
+        .filter(|p| p.start >= current.map_or(now, |c| c.stop))
+        .min_by_key(|p| p.start);
+    (current, next)
+}
+
+// 标题或简介包含关键字，或标题拼音首字母包含关键字
+pub(crate) fn matches(programme: &Program, query: &str) -> bool {
+    let query = query.to_lowercase();
+    if programme.title.to_lowercase().contains(&query) || programme.desc.to_lowercase().contains(&query) {
+        return true;
+    }
+    query.chars().all(|c| c.is_ascii_alphanumeric()) && pinyin_initials(&programme.title).contains(&query)
+}
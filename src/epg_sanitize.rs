@@ -0,0 +1,73 @@
+use serde::Serialize;
+
+use crate::iptv::Program;
+
+// 小于这个长度的空档不补
+const MIN_GAP_MS: i64 = 5 * 60 * 1000;
+
+// 整理节目单时发现的问题
+#[derive(Serialize, Clone, Copy, Default)]
+pub(crate) struct Anomalies {
+    pub(crate) invalid: usize,    // 结束时间不晚于开始时间
+    pub(crate) duplicates: usize, // 开始时间和标题相同的重复节目
+    pub(crate) overlaps: usize,   // 与下一个节目重叠，已截断
+    pub(crate) gaps: usize,       // 节目之间的空档
+}
+
+impl std::ops::AddAssign for Anomalies {
+    fn add_assign(&mut self, other: Self) {
+        self.invalid += other.invalid;
+        self.duplicates += other.duplicates;
+        self.overlaps += other.overlaps;
+        self.gaps += other.gaps;
+    }
+}
+
+impl Anomalies {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.invalid + self.duplicates + self.overlaps + self.gaps == 0
+    }
+}
+
+// 整理节目单：去掉无效和重复的节目，截断重叠的节目；
+// gap_title 不为空时用该标题的占位节目补上空档
+pub(crate) fn sanitize(programmes: &mut Vec<Program>, gap_title: Option<&str>) -> Anomalies {
+    let mut anomalies = Anomalies::default();
+
+    let before = programmes.len();
+    programmes.retain(|p| p.stop > p.start);
+    anomalies.invalid = before - programmes.len();
+
+    programmes.sort_by(|a, b| a.start.cmp(&b.start).then(b.stop.cmp(&a.stop)));
+    let before = programmes.len();
+    programmes.dedup_by(|next, prev| next.start == prev.start && next.title == prev.title);
+    anomalies.duplicates = before - programmes.len();
+
+    // 前一个节目截断到下一个节目开始，截断后没有时长的丢弃
+    let mut sanitized: Vec<Program> = Vec::with_capacity(programmes.len());
+    for programme in programmes.drain(..) {
+        if let Some(prev) = sanitized.last_mut() {
+            if prev.stop > programme.start {
+                anomalies.overlaps += 1;
+                prev.stop = programme.start;
+                if prev.stop <= prev.start {
+                    sanitized.pop();
+                }
+            } else if programme.start - prev.stop >= MIN_GAP_MS {
+                anomalies.gaps += 1;
+                if let Some(title) = gap_title {
+                    let filler = Program {
+                        start: prev.stop,
+                        stop: programme.start,
+                        title: title.to_string(),
+                        ..Default::default()
+                    };
+                    sanitized.push(filler);
+                }
+            }
+        }
+        sanitized.push(programme);
+    }
+    *programmes = sanitized;
+    anomalies
+}
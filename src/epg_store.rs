@@ -0,0 +1,205 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{File, OpenOptions},
+    path::Path,
+    sync::{LazyLock, Mutex},
+};
+
+use anyhow::Result;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+use crate::catchup;
+use crate::iptv::{Channel, Program};
+
+const EPG_STORE_FILE: &str = "epg_store.json";
+const DAY_MS: i64 = 86_400_000;
+// 今天及以后的节目单可能变化，超过这个时间重新获取
+const REFRESH_MS: i64 = 6 * 3600 * 1000;
+// 向后获取的天数
+pub(crate) const FUTURE_DAYS: i64 = 5;
+
+// 节目单存储：按 (频道, 开始时间) 保存，记录每个频道每天的获取时间，只获取缺少的日期
+pub(crate) static EPG_STORE: LazyLock<Mutex<EpgStore>> =
+    LazyLock::new(|| Mutex::new(EpgStore::default()));
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub(crate) struct EpgStore {
+    programmes: HashMap<u64, BTreeMap<i64, Program>>,
+    // 频道 -> 本地日期序号 -> 获取时间
+    fetched: HashMap<u64, BTreeMap<i64, i64>>,
+}
+
+// 本地时区下的日期序号
+fn day_of(unix_ms: i64) -> i64 {
+    (unix_ms + catchup::local_offset().local_minus_utc() as i64 * 1000).div_euclid(DAY_MS)
+}
+
+fn day_start(day: i64) -> i64 {
+    day * DAY_MS - catchup::local_offset().local_minus_utc() as i64 * 1000
+}
+
+impl EpgStore {
+    pub(crate) fn load() -> Result<Self> {
+        if Path::new(EPG_STORE_FILE).exists() {
+            let file = File::open(EPG_STORE_FILE)?;
+            Ok(serde_json::from_reader(file)?)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub(crate) fn save(&self) -> Result<()> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(EPG_STORE_FILE)?;
+        serde_json::to_writer(file, self)?;
+        Ok(())
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.programmes.values().all(|p| p.is_empty())
+    }
+
+    // 需要获取的时间段（已合并相邻日期），返回 [开始, 结束) 毫秒时间戳
+    pub(crate) fn missing_spans(&self, channel_id: u64, now: i64, history_days: i64) -> Vec<(i64, i64)> {
+        let today = day_of(now);
+        let fetched = self.fetched.get(&channel_id);
+        let mut spans: Vec<(i64, i64)> = Vec::new();
+        for day in (today - history_days)..=(today + FUTURE_DAYS) {
+            let fresh = match fetched.and_then(|f| f.get(&day)) {
+                // 过去的日期获取过一次即可
+                Some(_) if day < today => true,
+                Some(&at) => now - at < REFRESH_MS,
+                None => false,
+            };
+            if fresh {
+                continue;
+            }
+            let (start, end) = (day_start(day), day_start(day + 1));
+            match spans.last_mut() {
+                Some(last) if last.1 == start => last.1 = end,
+                _ => spans.push((start, end)),
+            }
+        }
+        spans
+    }
+
+    pub(crate) fn mark_fetched(&mut self, channel_id: u64, begin: i64, end: i64, now: i64) {
+        let fetched = self.fetched.entry(channel_id).or_default();
+        for day in day_of(begin)..day_of(end) {
+            fetched.insert(day, now);
+        }
+    }
+
+    // 合并节目：与新节目时间重叠的旧节目被替换，其余保留
+    pub(crate) fn merge(&mut self, channel_id: u64, programmes: Vec<Program>) {
+        let entry = self.programmes.entry(channel_id).or_default();
+        for programme in programmes {
+            if programme.stop <= programme.start {
+                continue;
+            }
+            let overlapping = entry
+                .range(..programme.stop)
+                .rev()
+                .take_while(|(_, p)| p.stop > programme.start)
+                .map(|(&start, _)| start)
+                .collect::<Vec<_>>();
+            for start in overlapping {
+                entry.remove(&start);
+            }
+            entry.insert(programme.start, programme);
+        }
+    }
+
+    // 已有节目的总时长，毫秒
+    pub(crate) fn coverage(&self, channel_id: u64) -> i64 {
+        self.programmes
+            .get(&channel_id)
+            .map(|p| p.values().map(|p| p.stop - p.start).sum())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn programmes(&self, channel_id: u64) -> Vec<Program> {
+        self.programmes
+            .get(&channel_id)
+            .map(|p| p.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    // 删除超出历史窗口的节目和获取记录
+    pub(crate) fn prune(&mut self, now: i64, history_days: i64) {
+        let oldest_day = day_of(now) - history_days;
+        let cutoff = day_start(oldest_day);
+        let mut removed = 0;
+        for programmes in self.programmes.values_mut() {
+            let before = programmes.len();
+            programmes.retain(|_, p| p.stop > cutoff);
+            removed += before - programmes.len();
+        }
+        self.programmes.retain(|_, p| !p.is_empty());
+        for fetched in self.fetched.values_mut() {
+            fetched.retain(|&day, _| day >= oldest_day);
+        }
+        self.fetched.retain(|_, f| !f.is_empty());
+        if removed > 0 {
+            info!("EPG store pruned {} expired programmes", removed);
+        }
+    }
+}
+
+// 锁内只复制存储，写入磁盘在阻塞线程中进行，不阻塞读取和运行时
+pub(crate) async fn save() -> Result<()> {
+    let snapshot = match EPG_STORE.lock() {
+        Ok(store) => store.clone(),
+        Err(_) => return Ok(()),
+    };
+    tokio::task::spawn_blocking(move || snapshot.save()).await?
+}
+
+// 单个频道的节目单，生成XMLTV时逐个频道读取
+pub(crate) fn programmes(channel_id: u64) -> Vec<Program> {
+    EPG_STORE.lock().map(|store| store.programmes(channel_id)).unwrap_or_default()
+}
+
+pub(crate) fn has_programmes(channel_id: u64) -> bool {
+    EPG_STORE
+        .lock()
+        .is_ok_and(|store| store.programmes.get(&channel_id).is_some_and(|p| !p.is_empty()))
+}
+
+// 把存储中的节目单附加到频道
+pub(crate) fn attach(channels: &mut [Channel]) {
+    if let Ok(store) = EPG_STORE.lock() {
+        for channel in channels.iter_mut() {
+            channel.epg = store.programmes(channel.id);
+        }
+    }
+}
+
+// 启动时加载节目单存储，没有时从旧的XMLTV缓存导入
+pub(crate) fn init(xmltv_epg: impl FnOnce() -> Result<HashMap<u64, Vec<Program>>>) {
+    let mut store = match EpgStore::load() {
+        Ok(store) => store,
+        Err(e) => {
+            log::warn!("Failed to load EPG store: {}", e);
+            EpgStore::default()
+        }
+    };
+    if store.is_empty() {
+        match xmltv_epg() {
+            Ok(epg) => {
+                debug!("Importing {} channels from XMLTV cache into EPG store", epg.len());
+                for (channel_id, programmes) in epg {
+                    store.merge(channel_id, programmes);
+                }
+            }
+            Err(e) => debug!("No XMLTV cache to import into EPG store: {}", e),
+        }
+    }
+    if let Ok(mut s) = EPG_STORE.lock() {
+        *s = store;
+    }
+}
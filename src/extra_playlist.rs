@@ -0,0 +1,200 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use regex_lite::Regex;
+use reqwest::Client;
+
+use crate::args::Args;
+use crate::category;
+use crate::iptv::Channel;
+
+const SOURCE_TTL: Duration = Duration::from_secs(600);
+
+// 额外频道的ID：8 开头的 16 位数，不与上游频道ID冲突，也不超过 JavaScript 的安全整数
+const ID_BASE: u64 = 8_000_000_000_000_000;
+const ID_RANGE: u64 = 1_000_000_000_000_000;
+
+// 按来源缓存解析结果，来源暂时不可用时使用过期的
+type Entries = (Instant, Vec<M3uEntry>);
+static SOURCES: LazyLock<Mutex<HashMap<String, Entries>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// M3U 中的一个频道
+#[derive(Clone)]
+struct M3uEntry {
+    attrs: BTreeMap<String, String>,
+    name: String,
+    url: String,
+}
+
+pub(crate) fn is_extra_id(id: u64) -> bool {
+    (ID_BASE..ID_BASE + ID_RANGE).contains(&id)
+}
+
+// 解析 #EXTINF 的属性和名称：-1 tvg-id="x" group-title="y",名称
+fn parse_extinf(info: &str) -> (BTreeMap<String, String>, String) {
+    let mut attrs = BTreeMap::new();
+    let mut chars = info.char_indices().peekable();
+    let mut key = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            // 引号外的第一个逗号之后是名称
+            ',' => return (attrs, info[i + 1..].trim().to_string()),
+            '=' if chars.peek().map(|&(_, c)| c) == Some('"') => {
+                chars.next();
+                let value = chars.by_ref().map(|(_, c)| c).take_while(|&c| c != '"').collect::<String>();
+                attrs.insert(std::mem::take(&mut key).to_lowercase(), value);
+            }
+            c if c.is_whitespace() => key.clear(),
+            c => key.push(c),
+        }
+    }
+    (attrs, String::new())
+}
+
+fn parse_m3u(text: &str) -> Vec<M3uEntry> {
+    let mut entries = Vec::new();
+    let mut current: Option<(BTreeMap<String, String>, String)> = None;
+    for line in text.lines().map(|l| l.trim().trim_start_matches('\u{feff}')).filter(|l| !l.is_empty()) {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            current = Some(parse_extinf(info));
+        } else if let Some(group) = line.strip_prefix("#EXTGRP:") {
+            if let Some((attrs, _)) = current.as_mut() {
+                attrs.entry("group-title".to_string()).or_insert_with(|| group.trim().to_string());
+            }
+        } else if !line.starts_with('#') {
+            if let Some((attrs, name)) = current.take() {
+                let name = if name.is_empty() {
+                    attrs.get("tvg-name").cloned().unwrap_or_else(|| line.to_string())
+                } else {
+                    name
+                };
+                entries.push(M3uEntry { attrs, name, url: line.to_string() });
+            }
+        }
+    }
+    entries
+}
+
+async fn fetch(source: &str) -> Result<String> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        let client = Client::builder().timeout(Duration::from_secs(15)).build()?;
+        Ok(client.get(source).send().await?.error_for_status()?.text().await?)
+    } else {
+        Ok(std::fs::read_to_string(source)?)
+    }
+}
+
+async fn entries(source: &str) -> Vec<M3uEntry> {
+    let cached = SOURCES.lock().ok().and_then(|s| s.get(source).cloned());
+    if let Some((loaded_at, entries)) = &cached {
+        if loaded_at.elapsed() < SOURCE_TTL {
+            return entries.clone();
+        }
+    }
+    match fetch(source).await {
+        Ok(text) => {
+            let entries = parse_m3u(&text);
+            info!("Loaded extra playlist {}: {} channels", source, entries.len());
+            if let Ok(mut sources) = SOURCES.lock() {
+                sources.insert(source.to_string(), (Instant::now(), entries.clone()));
+            }
+            entries
+        }
+        Err(e) => {
+            warn!("Failed to load extra playlist {}: {}", source, e);
+            cached.map(|(_, entries)| entries).unwrap_or_default()
+        }
+    }
+}
+
+// FNV-1a，同一来源的同一频道每次得到相同的ID
+fn stable_id(source: &str, key: &str) -> u64 {
+    let hash = source
+        .bytes()
+        .chain(std::iter::once(b'\n'))
+        .chain(key.bytes())
+        .fold(0xcbf29ce484222325u64, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3));
+    ID_BASE + hash % ID_RANGE
+}
+
+fn compile(pattern: Option<&str>, arg: &str) -> Option<Regex> {
+    let pattern = pattern?;
+    Regex::new(pattern)
+        .map_err(|e| warn!("Invalid {} '{}': {}", arg, pattern, e))
+        .ok()
+}
+
+fn to_channel(id: u64, entry: M3uEntry) -> Channel {
+    // group-title 可以用 ; 分隔多个分组，没有时按分组规则
+    let groups = entry
+        .attrs
+        .get("group-title")
+        .map(|g| g.split(';').map(str::trim).filter(|g| !g.is_empty()).map(String::from).collect::<Vec<_>>())
+        .filter(|g| !g.is_empty())
+        .unwrap_or_else(|| category::groups(id, &entry.name));
+    Channel {
+        id,
+        name: entry.name,
+        rtsp: entry.url,
+        igmp: None,
+        epg: vec![],
+        category: groups[0].clone(),
+        groups,
+        catchup: false,
+        catchup_days: 0,
+        chno: None,
+        extra: Some(entry.attrs),
+    }
+}
+
+// --extra-playlist 中的频道，按来源和出现顺序，经 --extra-playlist-include/exclude 筛选
+pub(crate) async fn channels(args: &Args) -> Vec<Channel> {
+    if args.extra_playlist.is_empty() {
+        return vec![];
+    }
+    let include = compile(args.extra_playlist_include.as_deref(), "--extra-playlist-include");
+    let exclude = compile(args.extra_playlist_exclude.as_deref(), "--extra-playlist-exclude");
+
+    let mut ids = HashSet::new();
+    let mut channels = Vec::new();
+    for source in args.extra_playlist.iter() {
+        for entry in entries(source).await {
+            if include.as_ref().is_some_and(|re| !re.is_match(&entry.name))
+                || exclude.as_ref().is_some_and(|re| re.is_match(&entry.name))
+            {
+                continue;
+            }
+            let key = entry.attrs.get("tvg-id").filter(|id| !id.is_empty()).unwrap_or(&entry.name);
+            let mut id = stable_id(source, key);
+            // 同名频道依次取下一个ID
+            while !ids.insert(id) {
+                id = ID_BASE + (id - ID_BASE + 1) % ID_RANGE;
+            }
+            channels.push(to_channel(id, entry));
+        }
+    }
+    channels
+}
+
+// 原样保留的回看属性
+pub(crate) fn catchup_attrs(attrs: &BTreeMap<String, String>) -> String {
+    ["catchup", "catchup-days", "catchup-source"]
+        .iter()
+        .filter_map(|key| attrs.get(*key).map(|value| format!(r#" {}="{}""#, key, value)))
+        .collect()
+}
+
+pub(crate) async fn fetch_logo(url: &str) -> Result<Vec<u8>> {
+    let client = Client::builder().timeout(Duration::from_secs(15)).build()?;
+    let response = client.get(url).send().await?.error_for_status()?;
+    let bytes = response.bytes().await?;
+    if bytes.is_empty() {
+        return Err(anyhow!("Empty logo from {}", url));
+    }
+    Ok(bytes.to_vec())
+}
@@ -0,0 +1,260 @@
+use std::{
+    collections::HashMap,
+    fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{LazyLock, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use log::{debug, info};
+
+use crate::iptv::get_client_with_if;
+
+const LOCAL: &str = "本地网络";
+const CARRIER_NAT: &str = "运营商内网";
+const UNKNOWN: &str = "未知地区";
+
+const CACHE_TTL: Duration = Duration::from_secs(24 * 3600);
+const CACHE_MAX: usize = 10000;
+
+// 启动时由 --geoip-db/--geoip-online 设置
+static CONFIG: OnceLock<Config> = OnceLock::new();
+// IP -> (查询时间, 位置)
+static CACHE: LazyLock<Mutex<HashMap<IpAddr, (Instant, String)>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+struct Config {
+    db: Option<Database>,
+    online: bool,
+}
+
+enum Database {
+    Xdb(Xdb),
+    #[cfg(feature = "mmdb")]
+    Mmdb(maxminddb::Reader<Vec<u8>>),
+}
+
+// ip2region 的 xdb 文件（IPv4）：256 字节文件头，256x256 的向量索引，之后是 14 字节的段索引
+struct Xdb(Vec<u8>);
+
+const XDB_HEADER: usize = 256;
+const XDB_VECTOR_ENTRY: usize = 8;
+const XDB_SEGMENT: usize = 14;
+
+impl Xdb {
+    fn u32_at(&self, at: usize) -> Option<u32> {
+        Some(u32::from_le_bytes(self.0.get(at..at + 4)?.try_into().ok()?))
+    }
+
+    // 区域为 国家|区域|省份|城市|ISP，未知的字段为 0
+    fn lookup(&self, ip: Ipv4Addr) -> Option<String> {
+        let [a, b, ..] = ip.octets();
+        let vector = XDB_HEADER + (a as usize * 256 + b as usize) * XDB_VECTOR_ENTRY;
+        let start = self.u32_at(vector)? as usize;
+        let end = self.u32_at(vector + 4)? as usize;
+        let ip = u32::from(ip);
+
+        let (mut low, mut high) = (0, end.checked_sub(start)? / XDB_SEGMENT);
+        while low <= high {
+            let mid = (low + high) / 2;
+            let at = start + mid * XDB_SEGMENT;
+            if ip < self.u32_at(at)? {
+                high = mid.checked_sub(1)?;
+            } else if ip > self.u32_at(at + 4)? {
+                low = mid + 1;
+            } else {
+                let len = u16::from_le_bytes(self.0.get(at + 8..at + 10)?.try_into().ok()?) as usize;
+                let ptr = self.u32_at(at + 10)? as usize;
+                let region = std::str::from_utf8(self.0.get(ptr..ptr + len)?).ok()?;
+                let mut fields = region.split('|').filter(|f| !f.is_empty() && *f != "0").collect::<Vec<_>>();
+                fields.dedup();
+                // 有省份等信息时去掉国家，与在线查询的格式一致
+                if fields.len() > 1 {
+                    fields.remove(0);
+                }
+                return (!fields.is_empty()).then(|| fields.join("-"));
+            }
+        }
+        None
+    }
+}
+
+// 优先使用中文名称
+#[cfg(feature = "mmdb")]
+fn name(names: Option<&std::collections::BTreeMap<&str, &str>>) -> Option<String> {
+    let names = names?;
+    names.get("zh-CN").or_else(|| names.get("en")).map(|s| s.to_string())
+}
+
+#[cfg(feature = "mmdb")]
+fn mmdb_lookup(reader: &maxminddb::Reader<Vec<u8>>, ip: IpAddr) -> Option<String> {
+    let city: maxminddb::geoip2::City = reader.lookup(ip).ok()?;
+    let fields = [
+        city.subdivisions.as_ref().and_then(|s| name(s.first()?.names.as_ref())),
+        city.city.as_ref().and_then(|c| name(c.names.as_ref())),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+    if fields.is_empty() {
+        name(city.country.as_ref()?.names.as_ref())
+    } else {
+        Some(fields.join("-"))
+    }
+}
+
+impl Database {
+    // .xdb 为 ip2region，其余按 MaxMind 格式
+    fn open(path: &str) -> Result<Self> {
+        let data = fs::read(path)?;
+        if path.ends_with(".xdb") {
+            if data.len() < XDB_HEADER + 256 * 256 * XDB_VECTOR_ENTRY {
+                return Err(anyhow!("Invalid ip2region database {}", path));
+            }
+            return Ok(Self::Xdb(Xdb(data)));
+        }
+        #[cfg(feature = "mmdb")]
+        {
+            Ok(Self::Mmdb(maxminddb::Reader::from_source(data)?))
+        }
+        #[cfg(not(feature = "mmdb"))]
+        {
+            Err(anyhow!("MaxMind database {} requires the mmdb feature", path))
+        }
+    }
+
+    fn lookup(&self, ip: IpAddr) -> Option<String> {
+        match self {
+            Self::Xdb(xdb) => match ip {
+                IpAddr::V4(v4) => xdb.lookup(v4),
+                IpAddr::V6(_) => None,
+            },
+            #[cfg(feature = "mmdb")]
+            Self::Mmdb(reader) => mmdb_lookup(reader, ip),
+        }
+    }
+}
+
+pub(crate) fn init(db: Option<&str>, online: bool) -> Result<()> {
+    let db = db.map(Database::open).transpose()?;
+    if db.is_some() {
+        info!("Loaded GeoIP database");
+    }
+    CONFIG
+        .set(Config { db, online })
+        .map_err(|_| anyhow!("GeoIP already initialized"))
+}
+
+// 本机、私有网络、链路本地、运营商级 NAT 和 IPv6 唯一本地地址不需要查询
+fn classify(ip: IpAddr) -> Option<&'static str> {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            if v4.is_loopback() || v4.is_private() || v4.is_link_local() || v4.is_unspecified() {
+                Some(LOCAL)
+            } else if a == 100 && (64..128).contains(&b) {
+                Some(CARRIER_NAT)
+            } else {
+                None
+            }
+        }
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return classify(IpAddr::V4(v4));
+            }
+            let first = v6.segments()[0];
+            if v6.is_loopback() || v6.is_unspecified() || first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80 {
+                Some(LOCAL)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+// 连接地址可能带端口，IPv6 可能带方括号
+fn parse_ip(ip: &str) -> Option<IpAddr> {
+    let ip = ip.trim();
+    ip.parse::<IpAddr>()
+        .or_else(|_| ip.parse::<SocketAddr>().map(|s| s.ip()))
+        .or_else(|_| ip.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>())
+        .ok()
+}
+
+// 使用 ip-api.com，格式为 regionName-city-isp
+async fn lookup_online(ip: IpAddr) -> Option<String> {
+    let client = get_client_with_if(None)
+        .map_err(|e| debug!("Failed to create HTTP client for IP location: {}", e))
+        .ok()?;
+    let url = format!("http://ip-api.com/json/{}?lang=zh-CN", ip);
+    let response = client
+        .get(&url)
+        .send()
+        .await
+        .map_err(|e| debug!("Failed to request IP location for {}: {}", ip, e))
+        .ok()?;
+    if !response.status().is_success() {
+        debug!("IP location API returned error status for {}: {}", ip, response.status());
+        return None;
+    }
+    let data = response
+        .json::<serde_json::Value>()
+        .await
+        .map_err(|e| debug!("Failed to parse IP location response for {}: {}", ip, e))
+        .ok()?;
+    if data.get("status").and_then(|s| s.as_str()) != Some("success") {
+        return None;
+    }
+    let field = |key: &str| data.get(key).and_then(|v| v.as_str()).unwrap_or("").to_string();
+    let (region_name, city, isp) = (field("regionName"), field("city"), field("isp"));
+    if region_name.is_empty() && city.is_empty() && isp.is_empty() {
+        return None;
+    }
+    Some(format!("{}-{}-{}", region_name, city, isp))
+}
+
+fn cached(ip: IpAddr) -> Option<String> {
+    let cache = CACHE.lock().ok()?;
+    let (at, location) = cache.get(&ip)?;
+    (at.elapsed() < CACHE_TTL).then(|| location.clone())
+}
+
+fn remember(ip: IpAddr, location: &str) {
+    if let Ok(mut cache) = CACHE.lock() {
+        if cache.len() >= CACHE_MAX {
+            cache.retain(|_, (at, _)| at.elapsed() < CACHE_TTL);
+            if cache.len() >= CACHE_MAX {
+                cache.clear();
+            }
+        }
+        cache.insert(ip, (Instant::now(), location.to_string()));
+    }
+}
+
+// 先查本地数据库，没有结果且启用了 --geoip-online 时在线查询
+pub(crate) async fn location(ip: &str) -> String {
+    let Some(ip) = parse_ip(ip) else {
+        return UNKNOWN.to_string();
+    };
+    if let Some(class) = classify(ip) {
+        return class.to_string();
+    }
+    if let Some(location) = cached(ip) {
+        return location;
+    }
+    let Some(config) = CONFIG.get() else {
+        return UNKNOWN.to_string();
+    };
+    let mut found = config.db.as_ref().and_then(|db| db.lookup(ip));
+    if found.is_none() && config.online {
+        found = lookup_online(ip).await;
+    }
+    match found {
+        Some(location) => {
+            remember(ip, &location);
+            location
+        }
+        // 查询失败不缓存，下次播放时重试
+        None => UNKNOWN.to_string(),
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::web::Bytes;
+use anyhow::Result;
+use futures_core::stream::Stream;
+use futures_util::stream::StreamExt;
+use log::{info, warn};
+use tokio::{sync::Notify, task::JoinHandle};
+
+use crate::ts::{PacketKind, TsAnalyzer, TS_PACKET_SIZE, TS_SYNC_BYTE};
+
+// 每个频道一个切片会话，所有 HLS 客户端共用
+static SESSIONS: LazyLock<Mutex<HashMap<u64, Arc<Session>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// 到达该时长后在下一个关键帧处切片
+const TARGET_DURATION: Duration = Duration::from_secs(4);
+// 一直没有关键帧（如纯音频频道）时按该时长强制切片
+const MAX_DURATION: Duration = Duration::from_secs(10);
+// 单个切片的大小上限，与 GOP 缓存一致
+const MAX_SEGMENT_SIZE: usize = 16 * 1024 * 1024;
+// 播放列表中保留的切片数
+const WINDOW: usize = 6;
+// 超过该时间没有请求播放列表或切片则停止切片
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+// 首次请求播放列表时等待第一个切片的时间
+const FIRST_SEGMENT_TIMEOUT: Duration = Duration::from_secs(20);
+
+struct Segment {
+    sequence: u64,
+    duration: Duration,
+    data: Bytes,
+}
+
+struct Session {
+    segments: Mutex<VecDeque<Segment>>,
+    // 新切片生成或上游结束时通知等待的请求
+    ready: Notify,
+    ended: Mutex<bool>,
+    last_access: Mutex<Instant>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Session {
+    fn touch(&self) {
+        if let Ok(mut last_access) = self.last_access.lock() {
+            *last_access = Instant::now();
+        }
+    }
+
+    fn idle(&self) -> bool {
+        self.last_access.lock().is_ok_and(|t| t.elapsed() >= IDLE_TIMEOUT)
+    }
+
+    fn push(&self, segment: Segment) {
+        if let Ok(mut segments) = self.segments.lock() {
+            segments.push_back(segment);
+            while segments.len() > WINDOW {
+                segments.pop_front();
+            }
+        }
+        self.ready.notify_waiters();
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.get_mut().ok().and_then(|t| t.take()) {
+            task.abort();
+        }
+    }
+}
+
+fn session(channel_id: u64) -> Option<Arc<Session>> {
+    SESSIONS.lock().ok()?.get(&channel_id).cloned()
+}
+
+fn remove(channel_id: u64, session: &Arc<Session>) {
+    if let Ok(mut sessions) = SESSIONS.lock() {
+        if sessions.get(&channel_id).is_some_and(|s| Arc::ptr_eq(s, session)) {
+            sessions.remove(&channel_id);
+        }
+    }
+}
+
+// 有切片会话时刷新访问时间并返回 true
+pub(crate) fn touch(channel_id: u64) -> bool {
+    match session(channel_id) {
+        Some(session) => {
+            session.touch();
+            true
+        }
+        None => false,
+    }
+}
+
+// 没有切片会话时用 open 打开上游开始切片，返回是否新建了会话
+pub(crate) fn start<F, S>(channel_id: u64, open: F) -> bool
+where
+    F: FnOnce() -> S,
+    S: Stream<Item = Result<Bytes>> + Send + 'static,
+{
+    let mut sessions = SESSIONS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(session) = sessions.get(&channel_id) {
+        session.touch();
+        return false;
+    }
+    info!("HLS session started for channel {}", channel_id);
+    let session = Arc::new(Session {
+        segments: Mutex::new(VecDeque::new()),
+        ready: Notify::new(),
+        ended: Mutex::new(false),
+        last_access: Mutex::new(Instant::now()),
+        task: Mutex::new(None),
+    });
+    // 任务只持有弱引用，会话从表中移除后随之停止
+    let task = tokio::spawn(segment_stream(channel_id, Arc::downgrade(&session), open()));
+    if let Ok(mut t) = session.task.lock() {
+        *t = Some(task);
+    }
+    sessions.insert(channel_id, session);
+    true
+}
+
+// 当前窗口的媒体播放列表，还没有切片时等待第一个切片
+pub(crate) async fn playlist(channel_id: u64) -> Option<String> {
+    let session = session(channel_id)?;
+    session.touch();
+    let deadline = tokio::time::Instant::now() + FIRST_SEGMENT_TIMEOUT;
+    loop {
+        // 先注册再检查，检查之后生成的切片也能唤醒
+        let notified = session.ready.notified();
+        let ended = session.ended.lock().is_ok_and(|e| *e);
+        {
+            let segments = session.segments.lock().ok()?;
+            if !segments.is_empty() {
+                return Some(to_m3u8(&segments));
+            }
+        }
+        if ended || tokio::time::timeout_at(deadline, notified).await.is_err() {
+            return None;
+        }
+    }
+}
+
+pub(crate) fn segment(channel_id: u64, sequence: u64) -> Option<Bytes> {
+    let session = session(channel_id)?;
+    session.touch();
+    let segments = session.segments.lock().ok()?;
+    segments
+        .iter()
+        .find(|s| s.sequence == sequence)
+        .map(|s| s.data.clone())
+}
+
+fn to_m3u8(segments: &VecDeque<Segment>) -> String {
+    let target = segments
+        .iter()
+        .map(|s| s.duration.as_secs_f64().ceil() as u64)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let mut m3u8 = format!(
+        "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:{}\n#EXT-X-MEDIA-SEQUENCE:{}\n",
+        target,
+        segments.front().map_or(0, |s| s.sequence)
+    );
+    for s in segments {
+        m3u8 += &format!("#EXTINF:{:.3},\n{}.ts\n", s.duration.as_secs_f64(), s.sequence);
+    }
+    m3u8
+}
+
+// 按关键帧切分上游的 MPEG-TS，每个切片以最近的 PAT/PMT 开头，可以单独解码
+async fn segment_stream<S>(channel_id: u64, session: std::sync::Weak<Session>, stream: S)
+where
+    S: Stream<Item = Result<Bytes>> + Send + 'static,
+{
+    let mut stream = std::pin::pin!(stream);
+    let mut analyzer = TsAnalyzer::new();
+    let mut remainder = Vec::new();
+    let mut pat: Option<Vec<u8>> = None;
+    let mut pmt: BTreeMap<u16, Vec<u8>> = BTreeMap::new();
+    let mut current: Vec<u8> = Vec::new();
+    let mut started_at: Option<Instant> = None;
+    let waiting_since = Instant::now();
+    // 会话重建后序号继续增长，避免播放器把新切片当成已播放过的
+    let mut sequence = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    while let Some(item) = stream.next().await {
+        let Some(session) = session.upgrade() else {
+            return;
+        };
+        if session.idle() {
+            info!("HLS session for channel {} idle, stopping", channel_id);
+            remove(channel_id, &session);
+            return;
+        }
+        let bytes = match item {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                warn!("HLS upstream of channel {} failed: {}", channel_id, e);
+                break;
+            }
+        };
+
+        // 按 TS 包边界对齐，切片不能从包中间开始
+        remainder.extend_from_slice(&bytes);
+        let Some(offset) = remainder.iter().position(|&b| b == TS_SYNC_BYTE) else {
+            remainder.clear();
+            continue;
+        };
+        let end = offset + (remainder.len() - offset) / TS_PACKET_SIZE * TS_PACKET_SIZE;
+        let packets = remainder[offset..end].to_vec();
+        remainder.drain(..end);
+        analyzer.push(&packets);
+
+        for packet in packets.chunks_exact(TS_PACKET_SIZE) {
+            let Ok(packet) = <&[u8; TS_PACKET_SIZE]>::try_from(packet) else {
+                continue;
+            };
+            let kind = analyzer.classify(packet);
+            match kind {
+                PacketKind::Pat => pat = Some(packet.to_vec()),
+                PacketKind::Pmt(pid) => {
+                    pmt.insert(pid, packet.to_vec());
+                }
+                _ => {}
+            }
+            let cut = match started_at {
+                Some(t) if kind == PacketKind::Keyframe => t.elapsed() >= TARGET_DURATION,
+                Some(t) => t.elapsed() >= MAX_DURATION || current.len() >= MAX_SEGMENT_SIZE,
+                // 从第一个关键帧开始切片，一直没有关键帧时不再等待
+                None => kind == PacketKind::Keyframe || waiting_since.elapsed() >= MAX_DURATION,
+            };
+            if cut {
+                if let Some(t) = started_at {
+                    session.push(Segment {
+                        sequence,
+                        duration: t.elapsed(),
+                        data: Bytes::from(std::mem::take(&mut current)),
+                    });
+                    sequence += 1;
+                }
+                started_at = Some(Instant::now());
+                if !matches!(kind, PacketKind::Pat | PacketKind::Pmt(_)) {
+                    current.extend(pat.iter().chain(pmt.values()).flatten());
+                }
+            }
+            if started_at.is_some() {
+                current.extend_from_slice(packet);
+            }
+        }
+    }
+
+    let Some(session) = session.upgrade() else {
+        return;
+    };
+    if let Ok(mut ended) = session.ended.lock() {
+        *ended = true;
+    }
+    session.ready.notify_waiters();
+    remove(channel_id, &session);
+    info!("HLS session for channel {} ended", channel_id);
+}
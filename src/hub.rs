@@ -0,0 +1,292 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, LazyLock, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::web::Bytes;
+use anyhow::Result;
+use async_stream::stream;
+use futures_core::stream::Stream;
+use futures_util::stream::StreamExt;
+use log::{error, info, warn};
+use serde::Serialize;
+use tokio::{sync::broadcast, task::JoinHandle};
+
+use crate::proxy::Source;
+use crate::ts::{GopCache, StreamInfo, TsAnalyzer};
+
+// 同一个上游只拉一次流，分发给所有客户端
+static HUB: LazyLock<Mutex<HashMap<String, Arc<Upstream>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+const BROADCAST_CAPACITY: usize = 1024;
+
+#[derive(Default)]
+struct UpstreamState {
+    analyzer: TsAnalyzer,
+    gop: GopCache,
+}
+
+struct Upstream {
+    source: String,
+    started_at: i64,
+    // 上游结束时置空，让所有订阅者收到Closed
+    tx: Mutex<Option<broadcast::Sender<Bytes>>>,
+    state: Mutex<UpstreamState>,
+    clients: Mutex<HashMap<u64, String>>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ActiveStreamInfo {
+    source: String,
+    started_at: i64,
+    clients: Vec<String>,
+    info: StreamInfo,
+}
+
+struct ClientGuard {
+    upstream: Arc<Upstream>,
+    id: u64,
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        let Ok(mut hub) = HUB.lock() else {
+            return;
+        };
+        let remaining = match self.upstream.clients.lock() {
+            Ok(mut clients) => {
+                clients.remove(&self.id);
+                clients.len()
+            }
+            Err(_) => 0,
+        };
+        if remaining > 0 {
+            return;
+        }
+        // 最后一个客户端断开，停止拉流
+        if hub
+            .get(&self.upstream.source)
+            .is_some_and(|u| Arc::ptr_eq(u, &self.upstream))
+        {
+            hub.remove(&self.upstream.source);
+        }
+        if let Some(task) = self.upstream.task.lock().ok().and_then(|mut t| t.take()) {
+            task.abort();
+        }
+        info!("Stream hub stopped {}", self.upstream.source);
+    }
+}
+
+pub(crate) fn active_streams() -> Vec<ActiveStreamInfo> {
+    let upstreams = match HUB.lock() {
+        Ok(hub) => hub.values().cloned().collect::<Vec<_>>(),
+        Err(_) => return vec![],
+    };
+    let mut infos = upstreams
+        .iter()
+        .map(|u| ActiveStreamInfo {
+            source: u.source.clone(),
+            started_at: u.started_at,
+            clients: u
+                .clients
+                .lock()
+                .map(|c| c.values().cloned().collect())
+                .unwrap_or_default(),
+            info: u
+                .state
+                .lock()
+                .map(|s| s.analyzer.info())
+                .unwrap_or_else(|_| TsAnalyzer::new().info()),
+        })
+        .collect::<Vec<_>>();
+    infos.sort_by_key(|i| i.started_at);
+    infos
+}
+
+// 订阅一个上游：已有则复用并先发送缓存的GOP，否则用open打开新的上游
+pub(crate) fn subscribe<F, S>(
+    source: String,
+    client_ip: String,
+    open: F,
+) -> impl Stream<Item = Result<Bytes>>
+where
+    F: FnOnce() -> S,
+    S: Stream<Item = Result<Bytes>> + Send + 'static,
+{
+    let id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
+    let upstream = {
+        let mut hub = HUB.lock().unwrap_or_else(|e| e.into_inner());
+        let upstream = match hub.get(&source) {
+            Some(upstream) => {
+                info!("Stream hub reusing {}", source);
+                upstream.clone()
+            }
+            None => {
+                let upstream = start(source.clone(), open());
+                hub.insert(source, upstream.clone());
+                upstream
+            }
+        };
+        if let Ok(mut clients) = upstream.clients.lock() {
+            clients.insert(id, client_ip);
+        }
+        upstream
+    };
+    // 在返回流之前创建，流未被轮询就被丢弃时也会注销客户端
+    let guard = ClientGuard { upstream, id };
+    let upstream = &guard.upstream;
+
+    // 持有状态锁时订阅，保证缓存和实时数据之间不丢不重
+    let (burst, rx) = {
+        let state = upstream.state.lock().unwrap_or_else(|e| e.into_inner());
+        let rx = upstream
+            .tx
+            .lock()
+            .ok()
+            .and_then(|tx| tx.as_ref().map(|tx| tx.subscribe()));
+        (state.gop.burst(), rx)
+    };
+
+    stream! {
+        let _guard = guard;
+        let Some(mut rx) = rx else {
+            return;
+        };
+        if let Some(burst) = burst {
+            yield Ok(Bytes::from(burst));
+        }
+        loop {
+            match rx.recv().await {
+                Ok(bytes) => yield Ok(bytes),
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("Stream hub client {} lagged, dropped {} chunks", id, n);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+}
+
+fn start<S>(source: String, stream: S) -> Arc<Upstream>
+where
+    S: Stream<Item = Result<Bytes>> + Send + 'static,
+{
+    info!("Stream hub started {}", source);
+    let (tx, _) = broadcast::channel(BROADCAST_CAPACITY);
+    let upstream = Arc::new(Upstream {
+        source,
+        started_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64,
+        tx: Mutex::new(Some(tx)),
+        state: Mutex::new(UpstreamState::default()),
+        clients: Mutex::new(HashMap::new()),
+        task: Mutex::new(None),
+    });
+
+    let pump = upstream.clone();
+    let task = tokio::spawn(async move {
+        let mut stream = std::pin::pin!(stream);
+        while let Some(item) = stream.next().await {
+            let bytes = match item {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    warn!("Stream hub upstream {} failed: {}", pump.source, e);
+                    break;
+                }
+            };
+            let Ok(mut state) = pump.state.lock() else {
+                break;
+            };
+            let state = &mut *state;
+            state.analyzer.push(&bytes);
+            state.gop.push(&state.analyzer, &bytes);
+            if let Ok(tx) = pump.tx.lock() {
+                if let Some(tx) = tx.as_ref() {
+                    let _ = tx.send(bytes);
+                }
+            }
+        }
+        if let Ok(mut tx) = pump.tx.lock() {
+            tx.take();
+        }
+        if let Ok(mut hub) = HUB.lock() {
+            if hub.get(&pump.source).is_some_and(|u| Arc::ptr_eq(u, &pump)) {
+                hub.remove(&pump.source);
+            }
+        }
+        info!("Stream hub upstream {} ended", pump.source);
+    });
+    if let Ok(mut t) = upstream.task.lock() {
+        *t = Some(task);
+    }
+    upstream
+}
+
+// 按顺序尝试频道的各个来源，超时无数据或中途断流时切换到下一个来源，客户端连接保持不断
+pub(crate) fn failover(
+    name: String,
+    sources: Vec<Source>,
+    client_ip: String,
+    if_name: Option<String>,
+    timeout: Duration,
+) -> impl Stream<Item = Result<Bytes>> {
+    stream! {
+        if sources.is_empty() {
+            return;
+        }
+        let mut index = 0;
+        let mut failures = 0;
+        loop {
+            let source = sources[index % sources.len()].clone();
+            let key = source.key();
+            let if_name = if_name.clone();
+            let mut upstream = std::pin::pin!(subscribe(key.clone(), client_ip.clone(), move || {
+                source.open(if_name)
+            }));
+
+            let mut received = false;
+            loop {
+                match tokio::time::timeout(timeout, upstream.next()).await {
+                    Ok(Some(Ok(bytes))) => {
+                        received = true;
+                        yield Ok(bytes);
+                    }
+                    Ok(Some(Err(e))) => {
+                        warn!("Source {} of {} failed: {}", key, name, e);
+                        break;
+                    }
+                    Ok(None) => {
+                        warn!("Source {} of {} ended", key, name);
+                        break;
+                    }
+                    Err(_) => {
+                        warn!("Source {} of {} stalled for {:?}", key, name, timeout);
+                        break;
+                    }
+                }
+            }
+
+            failures = if received { 0 } else { failures + 1 };
+            // 所有来源都连续尝试两轮无数据则放弃
+            if failures >= sources.len() * 2 {
+                error!("All sources of {} failed, closing stream", name);
+                break;
+            }
+            index += 1;
+            info!(
+                "Failover for {}: {} -> {}",
+                name,
+                key,
+                sources[index % sources.len()].key()
+            );
+        }
+    }
+}
@@ -1,4 +1,8 @@
 use crate::args::Args;
+use crate::catchup;
+use crate::category;
+use crate::epg_store;
+use crate::extra_playlist;
 use anyhow::{anyhow, Result};
 use des::{
     cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyInit},
@@ -6,40 +10,33 @@ use des::{
 };
 #[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
 use local_ip_address::list_afinet_netifas;
-use log::{debug, info, warn, error};
+use log::{debug, info};
 use rand::Rng;
 use regex_lite::Regex;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    collections::{BTreeMap, HashMap},
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
 };
-use tokio::task::JoinSet;
 
-fn parse_channel_mapping(mapping_str: &str) -> HashMap<String, String> {
-    let mut mapping = HashMap::new();
-    for pair in mapping_str.split(',') {
-        if let Some((from, to)) = pair.split_once('=') {
-            mapping.insert(from.trim().to_string(), to.trim().to_string());
-        }
-    }
-    mapping
-}
-
-#[allow(dead_code)]
-fn get_mapped_channel_name(channel_name: &str, mapping: &HashMap<String, String>) -> String {
-    mapping.get(channel_name).cloned().unwrap_or_else(|| channel_name.to_string())
-}
-
-fn categorize_channel(channel_name: &str) -> String {
-    if channel_name.contains("超高清") || channel_name.contains("4K") {
-        "超清频道".to_string()
-    } else if channel_name.contains("高清") || channel_name.contains("超清") || channel_name.contains("卫视") {
-        "高清频道".to_string()
-    } else {
-        "普通频道".to_string()
-    }
+// 根据频道配置判断是否支持回看及回看天数
+fn catchup_capability(config: &HashMap<String, String>, has_igmp: bool, default_days: u32) -> (bool, u32) {
+    let time_shift = config.get("TimeShift").map(|v| v == "1");
+    let time_shift_url = config.get("TimeShiftURL").map(|v| !v.is_empty());
+    let catchup = match (time_shift, time_shift_url) {
+        (None, None) => has_igmp,
+        (a, b) => a.unwrap_or(false) || b.unwrap_or(false),
+    };
+    // TimeShiftLength 单位为秒，不足一天的是直播时移缓冲，不代表回看窗口
+    let days = config
+        .get("TimeShiftLength")
+        .and_then(|v| v.parse::<u32>().ok())
+        .filter(|&len| len >= 86400)
+        .map(|len| len.div_ceil(86400))
+        .unwrap_or(default_days);
+    (catchup, days)
 }
 
 pub(crate) fn get_client_with_if(#[allow(unused_variables)] if_name: Option<&str>) -> Result<Client> {
@@ -91,12 +88,25 @@ pub(crate) async fn get_base_url(client: &Client, args: &Args) -> Result<String>
     Ok(base_url)
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Default)]
 pub(crate) struct Program {
     pub(crate) start: i64,
     pub(crate) stop: i64,
     pub(crate) title: String,
     pub(crate) desc: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) categories: Vec<String>,
+    // 集数，如 "12"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) episode: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) series_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) icon: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) rating: Option<String>,
+    #[serde(default)]
+    pub(crate) catchup: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -106,7 +116,21 @@ pub(crate) struct Channel {
     pub(crate) rtsp: String,
     pub(crate) igmp: Option<String>,
     pub(crate) epg: Vec<Program>,
+    // 主分组，即 groups 的第一个
     pub(crate) category: String,
+    // 频道所属的全部分组
+    #[serde(default)]
+    pub(crate) groups: Vec<String>,
+    #[serde(default)]
+    pub(crate) catchup: bool,
+    #[serde(default)]
+    pub(crate) catchup_days: u32,
+    // 频道表中设置的频道号
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) chno: Option<u32>,
+    // 来自 --extra-playlist 的频道保留 #EXTINF 属性，rtsp 为其播放地址
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) extra: Option<BTreeMap<String, String>>,
 }
 
 #[derive(Deserialize)]
@@ -133,6 +157,63 @@ struct Bill {
     start_time: i64,
     #[serde(rename = "endTime")]
     end_time: i64,
+    // 其余字段各地上游的名称和类型不统一
+    #[serde(flatten)]
+    extra: HashMap<String, serde_json::Value>,
+}
+
+impl Bill {
+    // 取第一个存在且非空的字段
+    fn field(&self, keys: &[&str]) -> Option<String> {
+        keys.iter()
+            .filter_map(|key| self.extra.get(*key))
+            .find_map(value_string)
+    }
+
+    fn into_program(self, base_url: &str) -> Program {
+        let categories = self
+            .field(&["genres", "genre", "genreName", "contentType"])
+            .map(|genres| {
+                genres
+                    .split([',', '，', '/', '|', ';'])
+                    .map(|g| g.trim().to_string())
+                    .filter(|g| !g.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let icon = self
+            .field(&["picture", "pictures", "pictureURL", "posterURL", "poster"])
+            .map(|url| if url.starts_with('/') { format!("{}{}", base_url, url) } else { url });
+        let catchup = self
+            .field(&["isCatchup", "isCatchUp", "isTvod", "isPlayable"])
+            .is_some_and(|v| v == "1" || v.eq_ignore_ascii_case("true"));
+        Program {
+            start: self.start_time,
+            stop: self.end_time,
+            desc: self.field(&["introduce", "description", "desc"]).unwrap_or_default(),
+            categories,
+            episode: self.field(&["episodeNumber", "episodeNo", "sitcomNO", "episode"]),
+            series_id: self.field(&["seriesID", "seriesId", "seriesid"]),
+            icon,
+            rating: self.field(&["ratingName", "rating", "ratingID", "ratingid"]),
+            catchup,
+            title: self.name,
+        }
+    }
+}
+
+fn value_string(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(s) if !s.trim().is_empty() => Some(s.trim().to_string()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        serde_json::Value::Bool(b) => Some(b.to_string()),
+        serde_json::Value::Array(values) => values.iter().find_map(value_string),
+        serde_json::Value::Object(map) => ["href", "url", "src"]
+            .iter()
+            .filter_map(|key| map.get(*key))
+            .find_map(value_string),
+        _ => None,
+    }
 }
 
 pub(crate) async fn get_channels(
@@ -141,6 +222,39 @@ pub(crate) async fn get_channels(
     scheme: &str,
     host: &str,
 ) -> Result<Vec<Channel>> {
+    let mut channels = get_upstream_channels(args, need_epg).await?;
+    for channel in channels.iter_mut() {
+        if args.rtsp_proxy {
+            channel.rtsp = channel
+                .rtsp
+                .replace("rtsp://", &format!("{}://{}/rtsp/", scheme, host));
+        }
+        if args.udp_proxy {
+            channel.igmp = channel
+                .igmp
+                .as_ref()
+                .map(|igmp| igmp.replace("igmp://", &format!("{}://{}/udp/", scheme, host)));
+        }
+    }
+    Ok(channels)
+}
+
+// 获取频道列表，rtsp/igmp 保持上游原始地址；节目单取自节目单存储。
+// 额外播放列表的频道排在上游频道之后
+pub(crate) async fn get_upstream_channels(args: &Args, need_epg: bool) -> Result<Vec<Channel>> {
+    let (client, base_url) = login(args).await?;
+    let mut channels = list_channels(&client, &base_url, args).await?;
+    channels.extend(extra_playlist::channels(args).await);
+
+    if need_epg {
+        epg_store::attach(&mut channels);
+    }
+
+    Ok(channels)
+}
+
+// 登录上游，返回已认证的 client 和 EPG 服务器地址
+pub(crate) async fn login(args: &Args) -> Result<(Client, String)> {
     info!("Obtaining channels");
 
     let user = args.user.as_str();
@@ -201,6 +315,10 @@ pub(crate) async fn get_channels(
         reqwest::Url::parse_with_params(format!("{base_url}/EPG/oauth/v2/token").as_str(), params)?;
     let _response = client.get(url).send().await?.error_for_status()?;
 
+    Ok((client, base_url))
+}
+
+pub(crate) async fn list_channels(client: &Client, base_url: &str, args: &Args) -> Result<Vec<Channel>> {
     let url = reqwest::Url::parse(format!("{base_url}/EPG/jsp/getchannellistHWCTC.jsp").as_str())?;
 
     let response = client.get(url).send().await?.error_for_status()?;
@@ -239,160 +357,96 @@ pub(crate) async fn get_channels(
                 })
                 .map(|(rtsp, igmp)| {
                     (
-                        if args.rtsp_proxy {
-                            rtsp.replace("rtsp://", &format!("{}://{}/rtsp/", scheme, host))
-                        } else {
-                            rtsp.to_string()
-                        }
-                        .replace("zoneoffset=0", "zoneoffset=480"),
-                        igmp.map(|igmp| {
-                            if args.udp_proxy {
-                                igmp.replace("igmp://", &format!("{}://{}/udp/", scheme, host))
-                            } else {
-                                igmp.to_string()
-                            }
-                        }),
+                        rtsp.replace(
+                            "zoneoffset=0",
+                            &format!("zoneoffset={}", catchup::zone_offset_minutes()),
+                        ),
+                        igmp.map(|igmp| igmp.to_string()),
                     )
                 })
-                .map(|u| (i, n, u))
+                .map(|u| {
+                    let catchup = catchup_capability(m, u.1.is_some(), args.catchup_days);
+                    (i, n, u, catchup)
+                })
         })
-        .map(|(i, n, (rtsp, igmp))| Channel {
-            id: i,
-            name: n.to_owned(),
-            category: categorize_channel(&n),
-            rtsp,
-            igmp,
-            epg: vec![],
+        .map(|(i, n, (rtsp, igmp), (catchup, catchup_days))| {
+            // 分组只在这里计算一次，之后各处都使用 category/groups
+            let groups = category::groups(i, &n);
+            Channel {
+                id: i,
+                name: n.to_owned(),
+                category: groups[0].clone(),
+                groups,
+                rtsp,
+                igmp,
+                epg: vec![],
+                catchup,
+                catchup_days,
+                chno: None,
+                extra: None,
+            }
         })
         .collect::<Vec<_>>();
 
     info!("Got {} channel(s)", channels.len());
 
-    if !need_epg {
-        return Ok(channels);
-    }
+    Ok(channels)
+}
 
-    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
-
-    let mut tasks = JoinSet::new();
-
-    for channel in channels.into_iter() {
-        let params = [
-            ("channelId", format!("{}", channel.id)),
-            ("begin", format!("{}", now - 86400000 * 2)),
-            ("end", format!("{}", now + 86400000 * 5)),
-        ];
-        let url = reqwest::Url::parse_with_params(
-            format!("{base_url}/EPG/jsp/iptvsnmv3/en/play/ajax/_ajax_getPlaybillList.jsp").as_str(),
-            params,
-        )?;
-        let client = client.clone();
-        
-        // 使用重试机制来获取EPG
-        tasks.spawn(async move { 
-            let mut last_error = None;
-            let mut result = None;
-            
-            // 最多重试3次
-            for attempt in 1..=3 {
-                match client.get(url.clone()).send().await {
-                    Ok(response) => {
-                        if attempt > 1 {
-                            debug!("✓ '{}' EPG获取在第{}次尝试后成功", channel.name, attempt);
-                        }
-                        result = Some(Ok(response));
-                        break;
-                    }
-                    Err(e) => {
-                        if attempt < 3 {
-                            debug!("✗ '{}' EPG获取第{}次失败，将重试: {}", channel.name, attempt, e);
-                        }
-                        last_error = Some(e);
-                        if attempt < 3 {
-                            // 等待递增的时间后重试（1秒，2秒，3秒...）
-                            tokio::time::sleep(std::time::Duration::from_millis(1000 * attempt)).await;
-                        }
-                    }
-                }
-            }
-            
-            // 如果所有重试都失败，返回最后一个错误
-            let final_result = result.unwrap_or_else(|| Err(last_error.unwrap()));
-            (final_result, channel)
-        });
+// 获取一个频道在 [begin, end) 内的节目单，重试由 epg_crawler 负责
+pub(crate) async fn fetch_playbill(
+    client: &Client,
+    base_url: &str,
+    channel_id: u64,
+    begin: i64,
+    end: i64,
+) -> Result<Vec<Program>> {
+    let params = [
+        ("channelId", format!("{}", channel_id)),
+        ("begin", format!("{}", begin)),
+        ("end", format!("{}", end)),
+    ];
+    let url = reqwest::Url::parse_with_params(
+        format!("{base_url}/EPG/jsp/iptvsnmv3/en/play/ajax/_ajax_getPlaybillList.jsp").as_str(),
+        params,
+    )?;
+
+    let response_text = client.get(url).send().await?.error_for_status()?.text().await?;
+    match serde_json::from_str::<PlaybillList>(&response_text) {
+        Ok(play_bill_list) => Ok(play_bill_list
+            .list
+            .into_iter()
+            .map(|bill| bill.into_program(base_url))
+            .collect()),
+        Err(e) => {
+            // 记录响应内容的前100个字符，方便调试
+            debug!("响应内容: {}", response_text.chars().take(100).collect::<String>());
+            Err(anyhow!("解析EPG数据失败: {}", e))
+        }
     }
-    let mut channels = vec![];
-    let mut failed_channels = 0;
-    let mut successful_channels = 0;
-    
-    while let Some(result) = tasks.join_next().await {
-        match result {
-            Ok((Ok(res), mut channel)) => {
-                successful_channels += 1;
-                // 成功获取HTTP响应
-                // 先获取响应文本，用于调试
-                let response_text = res.text().await.unwrap_or_else(|_| "Failed to get response text".to_string());
-                
-                match serde_json::from_str::<PlaybillList>(&response_text) {
-                    Ok(play_bill_list) => {
-                        // 成功解析JSON
-                        for bill in play_bill_list.list.into_iter() {
-                            channel.epg.push(Program {
-                                start: bill.start_time,
-                                stop: bill.end_time,
-                                title: bill.name.clone(),
-                                desc: bill.name,
-                            })
-                        }
-                        if !channel.epg.is_empty() {
-                            debug!("✓ 获取到 '{}' 的 {} 个节目", channel.name, channel.epg.len());
-                        }
-                    }
-                    Err(e) => {
-                        // JSON解析失败
-                        warn!("✗ 解析 '{}' 的EPG数据失败: {}", channel.name, e);
-                        // 记录响应内容的前100个字符，方便调试
-                        if response_text.len() > 100 {
-                            debug!("响应内容前100字符: {}", &response_text[..100]);
-                        } else {
-                            debug!("响应内容: {}", response_text);
-                        }
-                    }
-                }
-                channels.push(channel);
-            }
-            Ok((Err(e), channel)) => {
-                // HTTP请求失败（所有重试都失败）
-                warn!("✗ 获取 '{}' 的EPG在3次重试后仍然失败: {}", channel.name, e);
-                channels.push(channel);
-                failed_channels += 1;
-            }
-            Err(e) => {
-                // 任务执行失败（JoinError）
-                error!("✗ EPG获取任务执行失败: {}", e);
-                failed_channels += 1;
-            }
+}
+
+// EDS 返回的 EPG 服务器地址，获取台标时复用，不必每次都请求 EDS
+static ICON_BASE_URL: LazyLock<Mutex<Option<(Instant, String)>>> = LazyLock::new(|| Mutex::new(None));
+const ICON_BASE_URL_TTL: Duration = Duration::from_secs(3600);
+
+async fn icon_base_url(client: &Client, args: &Args) -> Result<String> {
+    if let Some((fetched, base_url)) = ICON_BASE_URL.lock().ok().and_then(|b| b.clone()) {
+        if fetched.elapsed() < ICON_BASE_URL_TTL {
+            return Ok(base_url);
         }
     }
-    
-    let total_channels = successful_channels + failed_channels;
-    let channels_with_epg = channels.iter().filter(|ch| !ch.epg.is_empty()).count();
-    
-    info!("EPG获取完成: 成功请求 {}/{} 个频道, 其中 {} 个频道有节目单", 
-        successful_channels, total_channels, channels_with_epg);
-    
-    if failed_channels > 0 {
-        warn!("共有 {} 个频道在重试后仍获取EPG失败", failed_channels);
+    let base_url = get_base_url(client, args).await?;
+    if let Ok(mut cached) = ICON_BASE_URL.lock() {
+        *cached = Some((Instant::now(), base_url.clone()));
     }
-
-    Ok(channels)
+    Ok(base_url)
 }
 
 pub(crate) async fn get_icon(args: &Args, id: &str) -> Result<Vec<u8>> {
     let client = get_client_with_if(args.interface.as_deref())?;
-    let base_url = get_base_url(&client, args).await?;
+    let base_url = icon_base_url(&client, args).await?;
 
-    // 先尝试用原始 ID 获取图标
     let url = reqwest::Url::parse(&format!(
         "{base_url}/EPG/jsp/iptvsnmv3/en/list/images/channelIcon/{}.png",
         id
@@ -404,17 +458,7 @@ pub(crate) async fn get_icon(args: &Args, id: &str) -> Result<Vec<u8>> {
         Ok(resp) => {
             match resp.error_for_status() {
                 Ok(resp) => Ok(resp.bytes().await?.to_vec()),
-                Err(_) => {
-                    // 如果获取失败，尝试使用映射
-                    if let Some(mapping_str) = &args.channel_mapping {
-                        let _mapping = parse_channel_mapping(mapping_str);
-                        // 这里需要实现通过频道名查找对应ID的逻辑
-                        // 暂时返回错误，后续可以完善
-                        Err(anyhow!("Icon not found for id: {}", id))
-                    } else {
-                        Err(anyhow!("Icon not found for id: {}", id))
-                    }
-                }
+                Err(_) => Err(anyhow!("Icon not found for id: {}", id)),
             }
         }
         Err(e) => Err(anyhow!("Network error: {}", e))
@@ -0,0 +1,126 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{File, OpenOptions},
+    path::Path,
+    sync::{LazyLock, Mutex},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::iptv::Channel;
+use crate::mapping::{Resolved, RuleSet};
+
+const LINEUP_FILE: &str = "lineup.json";
+
+// 自定义频道表，按列表顺序排列
+static LINEUP: LazyLock<Mutex<Vec<LineupEntry>>> = LazyLock::new(|| Mutex::new(Vec::new()));
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct LineupEntry {
+    pub(crate) id: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) number: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) group: Option<String>,
+    #[serde(default)]
+    pub(crate) hidden: bool,
+}
+
+pub(crate) fn entries() -> Vec<LineupEntry> {
+    LINEUP.lock().map(|l| l.clone()).unwrap_or_default()
+}
+
+pub(crate) fn set(entries: Vec<LineupEntry>) -> Result<()> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(LINEUP_FILE)?;
+    serde_json::to_writer_pretty(file, &entries)?;
+    if let Ok(mut lineup) = LINEUP.lock() {
+        *lineup = entries;
+    }
+    Ok(())
+}
+
+pub(crate) fn load() -> Result<()> {
+    if Path::new(LINEUP_FILE).exists() {
+        let entries: Vec<LineupEntry> = serde_json::from_reader(File::open(LINEUP_FILE)?)?;
+        log::info!("Loaded lineup with {} entries", entries.len());
+        if let Ok(mut lineup) = LINEUP.lock() {
+            *lineup = entries;
+        }
+    }
+    Ok(())
+}
+
+// 按频道表排序，keep_hidden 为 false 时去掉隐藏的频道：表中的频道在前，新出现的频道按上游顺序排在后面；
+// 频道表的名称、分组和频道号优先于映射规则
+fn apply(resolved: Vec<Resolved>, keep_hidden: bool) -> Vec<Resolved> {
+    let lineup = entries();
+    if lineup.is_empty() {
+        return resolved;
+    }
+    let positions = lineup
+        .iter()
+        .enumerate()
+        .map(|(i, e)| (e.id, (i, e)))
+        .collect::<HashMap<_, _>>();
+
+    let mut arranged = resolved
+        .into_iter()
+        .enumerate()
+        .filter_map(|(upstream, mut r)| {
+            let Some(&(position, entry)) = positions.get(&r.id) else {
+                return Some(((usize::MAX, upstream), r));
+            };
+            if entry.hidden && !keep_hidden {
+                return None;
+            }
+            if let Some(name) = &entry.name {
+                r.name = name.clone();
+                r.applied.insert("name", "lineup".to_string());
+            }
+            if let Some(group) = &entry.group {
+                r.group = Some(group.clone());
+                r.applied.insert("group", "lineup".to_string());
+            }
+            if let Some(number) = entry.number {
+                r.chno = Some(number);
+                r.applied.insert("chno", "lineup".to_string());
+            }
+            Some(((position, upstream), r))
+        })
+        .collect::<Vec<_>>();
+    arranged.sort_by_key(|(order, _)| *order);
+    arranged.into_iter().map(|(_, r)| r).collect()
+}
+
+// 隐藏的频道
+pub(crate) fn hidden_ids() -> HashSet<u64> {
+    LINEUP
+        .lock()
+        .map(|l| l.iter().filter(|e| e.hidden).map(|e| e.id).collect())
+        .unwrap_or_default()
+}
+
+// 应用映射规则和频道表，返回可见的频道及其结果
+pub(crate) fn arrange<'a>(rules: &RuleSet, channels: &'a [Channel]) -> Vec<(&'a Channel, Resolved)> {
+    arrange_with(rules, channels, false)
+}
+
+// 同 arrange，但保留隐藏的频道，用于共享的 XMLTV 缓存，输出时再过滤
+pub(crate) fn arrange_all<'a>(rules: &RuleSet, channels: &'a [Channel]) -> Vec<(&'a Channel, Resolved)> {
+    arrange_with(rules, channels, true)
+}
+
+fn arrange_with<'a>(rules: &RuleSet, channels: &'a [Channel], keep_hidden: bool) -> Vec<(&'a Channel, Resolved)> {
+    let by_id = channels.iter().map(|c| (c.id, c)).collect::<HashMap<_, _>>();
+    apply(rules.resolve(channels), keep_hidden)
+        .into_iter()
+        .filter_map(|r| by_id.get(&r.id).map(|&c| (c, r)))
+        .collect()
+}
@@ -0,0 +1,230 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    path::PathBuf,
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+// 台标保存在数据目录下，每个台标一个图片文件和一个元数据文件
+const LOGO_DIR: &str = "logos";
+// 超过这个时间的台标在后台重新获取，期间继续使用旧的
+const STALE_AFTER_MS: i64 = 7 * 24 * 3600 * 1000;
+
+static MEMORY: LazyLock<Mutex<Memory>> = LazyLock::new(|| Mutex::new(Memory::default()));
+// 正在后台刷新的台标
+static REFRESHING: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+// 最近获取失败的来源，一段时间内不再请求
+static MISSES: LazyLock<Mutex<HashMap<String, i64>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+const MISS_TTL_MS: i64 = 3600 * 1000;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct LogoMeta {
+    pub(crate) fetched_at: i64,
+    // 内容的 MD5，用作 ETag
+    pub(crate) hash: String,
+    pub(crate) content_type: String,
+}
+
+pub(crate) struct Logo {
+    pub(crate) data: Vec<u8>,
+    pub(crate) meta: LogoMeta,
+}
+
+impl Logo {
+    // 不经缓存的台标，如上传的和本地目录中的
+    pub(crate) fn new(data: Vec<u8>) -> Self {
+        let meta = LogoMeta {
+            fetched_at: chrono::Utc::now().timestamp_millis(),
+            hash: format!("{:x}", md5::compute(&data)),
+            content_type: image_type(&data).unwrap_or("image/png").to_string(),
+        };
+        Self { data, meta }
+    }
+
+    fn is_stale(&self) -> bool {
+        chrono::Utc::now().timestamp_millis() - self.meta.fetched_at > STALE_AFTER_MS
+    }
+}
+
+pub(crate) enum Lookup {
+    Fresh(Arc<Logo>),
+    Stale(Arc<Logo>),
+    Missing,
+}
+
+// 内存中的台标，超过上限时淘汰最久未使用的
+#[derive(Default)]
+struct Memory {
+    logos: HashMap<String, (Arc<Logo>, u64)>,
+    bytes: usize,
+    limit: usize,
+    tick: u64,
+}
+
+impl Memory {
+    fn get(&mut self, key: &str) -> Option<Arc<Logo>> {
+        self.tick += 1;
+        let tick = self.tick;
+        self.logos.get_mut(key).map(|(logo, used)| {
+            *used = tick;
+            logo.clone()
+        })
+    }
+
+    fn insert(&mut self, key: String, logo: Arc<Logo>) {
+        self.tick += 1;
+        self.bytes += logo.data.len();
+        if let Some((old, _)) = self.logos.insert(key, (logo, self.tick)) {
+            self.bytes -= old.data.len();
+        }
+        while self.limit > 0 && self.bytes > self.limit && self.logos.len() > 1 {
+            let Some(oldest) = self.logos.iter().min_by_key(|(_, (_, used))| *used).map(|(k, _)| k.clone()) else {
+                break;
+            };
+            if let Some((logo, _)) = self.logos.remove(&oldest) {
+                self.bytes -= logo.data.len();
+                debug!("Evicted logo {} from memory", oldest);
+            }
+        }
+    }
+}
+
+pub(crate) fn set_memory_limit(bytes: usize) {
+    if let Ok(mut memory) = MEMORY.lock() {
+        memory.limit = bytes;
+    }
+}
+
+// 只接受频道ID这样的键，避免路径穿越
+pub(crate) fn valid_key(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn paths(key: &str) -> (PathBuf, PathBuf) {
+    let dir = PathBuf::from(LOGO_DIR);
+    (dir.join(format!("{}.img", key)), dir.join(format!("{}.json", key)))
+}
+
+fn load(key: &str) -> Option<Logo> {
+    let (data_path, meta_path) = paths(key);
+    let meta: LogoMeta = serde_json::from_reader(File::open(meta_path).ok()?).ok()?;
+    let data = fs::read(data_path).ok()?;
+    Some(Logo { data, meta })
+}
+
+pub(crate) fn lookup(key: &str) -> Lookup {
+    let logo = match MEMORY.lock().ok().and_then(|mut m| m.get(key)) {
+        Some(logo) => logo,
+        None => {
+            let Some(logo) = load(key) else {
+                return Lookup::Missing;
+            };
+            let logo = Arc::new(logo);
+            if let Ok(mut memory) = MEMORY.lock() {
+                memory.insert(key.to_string(), logo.clone());
+            }
+            logo
+        }
+    };
+    if logo.is_stale() {
+        Lookup::Stale(logo)
+    } else {
+        Lookup::Fresh(logo)
+    }
+}
+
+// 按文件头识别图片格式
+pub(crate) fn image_type(data: &[u8]) -> Option<&'static str> {
+    match data {
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [0xff, 0xd8, 0xff, ..] => Some("image/jpeg"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        _ if data.starts_with(b"<svg") || data.starts_with(b"<?xml") => Some("image/svg+xml"),
+        _ => None,
+    }
+}
+
+// 保存新获取的台标，先写临时文件再替换
+pub(crate) fn store(key: &str, data: Vec<u8>) -> Result<Arc<Logo>> {
+    if data.is_empty() {
+        return Err(anyhow!("Empty logo for {}", key));
+    }
+    let Logo { data, meta } = Logo::new(data);
+    let (data_path, meta_path) = paths(key);
+    fs::create_dir_all(LOGO_DIR)?;
+    let tmp = data_path.with_extension("img.tmp");
+    fs::write(&tmp, &data)?;
+    fs::rename(&tmp, &data_path)?;
+    fs::write(&meta_path, serde_json::to_vec_pretty(&meta)?)?;
+
+    let logo = Arc::new(Logo { data, meta });
+    if let Ok(mut memory) = MEMORY.lock() {
+        memory.insert(key.to_string(), logo.clone());
+    }
+    Ok(logo)
+}
+
+// 缩放或转换后的台标只保存在内存中
+pub(crate) fn variant(key: &str) -> Option<Arc<Logo>> {
+    MEMORY.lock().ok()?.get(key)
+}
+
+pub(crate) fn store_variant(key: &str, logo: Logo) -> Arc<Logo> {
+    let logo = Arc::new(logo);
+    if let Ok(mut memory) = MEMORY.lock() {
+        memory.insert(key.to_string(), logo.clone());
+    }
+    logo
+}
+
+// 同一台标只有一个后台刷新
+pub(crate) fn begin_refresh(key: &str) -> bool {
+    REFRESHING.lock().is_ok_and(|mut r| r.insert(key.to_string()))
+}
+
+pub(crate) fn end_refresh(key: &str) {
+    if let Ok(mut refreshing) = REFRESHING.lock() {
+        refreshing.remove(key);
+    }
+}
+
+pub(crate) fn mark_missing(key: &str) {
+    if let Ok(mut misses) = MISSES.lock() {
+        misses.insert(key.to_string(), chrono::Utc::now().timestamp_millis());
+    }
+}
+
+pub(crate) fn recently_missing(key: &str) -> bool {
+    let now = chrono::Utc::now().timestamp_millis();
+    MISSES.lock().is_ok_and(|mut misses| {
+        misses.retain(|_, at| now - *at < MISS_TTL_MS);
+        misses.contains_key(key)
+    })
+}
+
+// 清空内存和磁盘上的台标，返回清除的数量
+pub(crate) fn clear() -> Result<usize> {
+    if let Ok(mut memory) = MEMORY.lock() {
+        memory.logos.clear();
+        memory.bytes = 0;
+    }
+    if let Ok(mut misses) = MISSES.lock() {
+        misses.clear();
+    }
+    let mut count = 0;
+    if let Ok(entries) = fs::read_dir(LOGO_DIR) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|e| e == "img") {
+                count += 1;
+            }
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(count)
+}
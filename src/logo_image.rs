@@ -0,0 +1,106 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+
+// ?w= 允许的宽度范围
+const MIN_WIDTH: u32 = 16;
+const MAX_WIDTH: u32 = 1024;
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum LogoFormat {
+    // 保持原格式，只在需要缩放时转为 PNG
+    Original,
+    Png,
+    Webp,
+}
+
+impl FromStr for LogoFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "original" => Ok(Self::Original),
+            "png" => Ok(Self::Png),
+            "webp" => Ok(Self::Webp),
+            _ => Err(anyhow!("Unknown logo format '{}'", s)),
+        }
+    }
+}
+
+// --logo-size 的 宽x高，如 256x256
+pub(crate) fn parse_size(size: &str) -> Result<(u32, u32)> {
+    let (w, h) = size
+        .split_once(['x', 'X'])
+        .ok_or_else(|| anyhow!("Invalid logo size '{}', expected WIDTHxHEIGHT", size))?;
+    let (w, h): (u32, u32) = (w.trim().parse()?, h.trim().parse()?);
+    if !(MIN_WIDTH..=MAX_WIDTH).contains(&w) || !(MIN_WIDTH..=MAX_WIDTH).contains(&h) {
+        return Err(anyhow!("Logo size must be between {} and {}", MIN_WIDTH, MAX_WIDTH));
+    }
+    Ok((w, h))
+}
+
+// ?w= 请求的宽度，高度按配置的比例，没有配置时为正方形
+pub(crate) fn variant_size(width: u32, configured: Option<(u32, u32)>) -> (u32, u32) {
+    let width = width.clamp(MIN_WIDTH, MAX_WIDTH);
+    let (w, h) = configured.unwrap_or((1, 1));
+    (width, (width * h / w).max(1))
+}
+
+pub(crate) const fn available() -> bool {
+    cfg!(feature = "logo-processing")
+}
+
+// 缩放到 宽x高 以内并居中，四周用透明像素填充；
+// 不需要处理时返回 None，SVG 等无法解码的格式原样返回
+#[cfg(feature = "logo-processing")]
+pub(crate) fn process(
+    data: &[u8],
+    size: Option<(u32, u32)>,
+    format: LogoFormat,
+) -> Result<Option<(Vec<u8>, &'static str)>> {
+    use image::{imageops, DynamicImage, ImageFormat, RgbaImage};
+
+    if size.is_none() && format == LogoFormat::Original {
+        return Ok(None);
+    }
+    let Ok(guessed) = image::guess_format(data) else {
+        return Ok(None);
+    };
+    let same_format = matches!(
+        (guessed, format),
+        (ImageFormat::Png, LogoFormat::Png) | (ImageFormat::WebP, LogoFormat::Webp)
+    );
+    if size.is_none() && same_format {
+        return Ok(None);
+    }
+
+    let img = image::load_from_memory_with_format(data, guessed)?;
+    let img = match size {
+        Some((width, height)) => {
+            let scaled = img.resize(width, height, imageops::FilterType::Lanczos3).to_rgba8();
+            let mut canvas = RgbaImage::new(width, height);
+            let x = (width - scaled.width()) / 2;
+            let y = (height - scaled.height()) / 2;
+            imageops::overlay(&mut canvas, &scaled, x as i64, y as i64);
+            canvas
+        }
+        None => img.to_rgba8(),
+    };
+    let (output, content_type) = match format {
+        LogoFormat::Webp => (ImageFormat::WebP, "image/webp"),
+        _ => (ImageFormat::Png, "image/png"),
+    };
+    let mut out = std::io::Cursor::new(Vec::new());
+    DynamicImage::ImageRgba8(img).write_to(&mut out, output)?;
+    Ok(Some((out.into_inner(), content_type)))
+}
+
+// 未启用 logo-processing 时原样返回
+#[cfg(not(feature = "logo-processing"))]
+pub(crate) fn process(
+    _data: &[u8],
+    _size: Option<(u32, u32)>,
+    _format: LogoFormat,
+) -> Result<Option<(Vec<u8>, &'static str)>> {
+    Ok(None)
+}
@@ -0,0 +1,178 @@
+use std::io::Write;
+
+use anyhow::Result;
+use flate2::{write::ZlibEncoder, Compression, Crc};
+
+use crate::epg_query::pinyin_initials;
+
+const SIZE: u32 = 256;
+const RADIUS: u32 = 32;
+// 文字区域的边距
+const MARGIN: u32 = 32;
+// 最多显示的字符数
+const MAX_CHARS: usize = 5;
+
+const COLORS: &[[u8; 3]] = &[
+    [0x5b, 0x6e, 0xe1],
+    [0x7b, 0x4f, 0xa0],
+    [0x2f, 0x8f, 0x83],
+    [0xc0, 0x50, 0x4d],
+    [0xd0, 0x8a, 0x2e],
+    [0x3b, 0x7d, 0xd8],
+    [0x6a, 0x8f, 0x3a],
+    [0x8c, 0x5a, 0x3c],
+];
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+
+// 5x7 点阵字体，每行低 5 位从左到右
+const DIGITS: [[u8; 7]; 10] = [
+    [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110],
+    [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+    [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111],
+    [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110],
+    [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010],
+    [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110],
+    [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110],
+    [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000],
+    [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110],
+    [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100],
+];
+
+const LETTERS: [[u8; 7]; 26] = [
+    [0b01110, 0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001],
+    [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110],
+    [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110],
+    [0b11100, 0b10010, 0b10001, 0b10001, 0b10001, 0b10010, 0b11100],
+    [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111],
+    [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000],
+    [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111],
+    [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+    [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+    [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100],
+    [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001],
+    [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111],
+    [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001],
+    [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001],
+    [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+    [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000],
+    [0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101],
+    [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001],
+    [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110],
+    [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100],
+    [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+    [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100],
+    [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010],
+    [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001],
+    [0b10001, 0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100],
+    [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111],
+];
+
+fn glyph(c: char) -> Option<&'static [u8; 7]> {
+    match c {
+        '0'..='9' => DIGITS.get(c as usize - '0' as usize),
+        'A'..='Z' => LETTERS.get(c as usize - 'A' as usize),
+        _ => None,
+    }
+}
+
+// 占位图上的文字：以字母开头的名称取开头的字母和数字（CCTV-1 -> CCTV1），
+// 中文名称取拼音首字母（湖南卫视 -> HNWS）
+fn initials(name: &str) -> String {
+    let latin = name
+        .trim()
+        .chars()
+        .take_while(|c| c.is_ascii())
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>();
+    let text = if latin.is_empty() { pinyin_initials(name) } else { latin };
+    let text = text
+        .to_ascii_uppercase()
+        .chars()
+        .filter(|c| glyph(*c).is_some())
+        .take(MAX_CHARS)
+        .collect::<String>();
+    if text.is_empty() {
+        "TV".to_string()
+    } else {
+        text
+    }
+}
+
+// 圆角方块内的像素
+fn inside(x: u32, y: u32) -> bool {
+    let dx = RADIUS.saturating_sub(x.min(SIZE - 1 - x));
+    let dy = RADIUS.saturating_sub(y.min(SIZE - 1 - y));
+    dx == 0 || dy == 0 || dx * dx + dy * dy <= RADIUS * RADIUS
+}
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    let mut crc = Crc::new();
+    crc.update(kind);
+    crc.update(data);
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    out.extend_from_slice(&crc.sum().to_be_bytes());
+}
+
+// RGBA 像素编码为 PNG，每行不使用过滤
+fn encode_png(width: u32, height: u32, rgba: &[u8]) -> Result<Vec<u8>> {
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 8 位深度，RGBA，默认压缩、过滤和不隔行
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    for row in rgba.chunks(width as usize * 4) {
+        encoder.write_all(&[0])?;
+        encoder.write_all(row)?;
+    }
+    let data = encoder.finish()?;
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    png_chunk(&mut png, b"IHDR", &header);
+    png_chunk(&mut png, b"IDAT", &data);
+    png_chunk(&mut png, b"IEND", &[]);
+    Ok(png)
+}
+
+// 带频道名首字母的 PNG 占位台标，颜色由名称决定
+pub(crate) fn render(name: &str) -> Result<Vec<u8>> {
+    let hash = md5::compute(name.as_bytes());
+    let [r, g, b] = COLORS[hash[0] as usize % COLORS.len()];
+    let text = initials(name).chars().filter_map(glyph).collect::<Vec<_>>();
+
+    // 字符间隔一列，按可用区域取最大的整数倍放大
+    let columns = text.len() as u32 * (GLYPH_WIDTH + 1) - 1;
+    let scale = ((SIZE - 2 * MARGIN) / columns).min((SIZE - 2 * MARGIN) / 2 / GLYPH_HEIGHT);
+    let left = (SIZE - columns * scale) / 2;
+    let top = (SIZE - GLYPH_HEIGHT * scale) / 2;
+    let lit = |x: u32, y: u32| {
+        if x < left || y < top {
+            return false;
+        }
+        let (col, row) = ((x - left) / scale, (y - top) / scale);
+        let (index, bit) = ((col / (GLYPH_WIDTH + 1)) as usize, col % (GLYPH_WIDTH + 1));
+        row < GLYPH_HEIGHT
+            && bit < GLYPH_WIDTH
+            && text.get(index).is_some_and(|g| g[row as usize] >> (GLYPH_WIDTH - 1 - bit) & 1 == 1)
+    };
+
+    let mut rgba = Vec::with_capacity((SIZE * SIZE * 4) as usize);
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            let pixel = if !inside(x, y) {
+                [0, 0, 0, 0]
+            } else if lit(x, y) {
+                [0xff, 0xff, 0xff, 0xff]
+            } else {
+                [r, g, b, 0xff]
+            };
+            rgba.extend_from_slice(&pixel);
+        }
+    }
+    encode_png(SIZE, SIZE, &rgba)
+}
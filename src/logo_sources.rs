@@ -0,0 +1,83 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::logo_cache;
+use crate::xmltv_sources::normalize_name;
+
+// 通过 API 上传的台标，优先于其他来源
+const CUSTOM_DIR: &str = "custom_logos";
+
+fn custom_path(key: &str) -> PathBuf {
+    Path::new(CUSTOM_DIR).join(format!("{}.img", key))
+}
+
+pub(crate) fn custom(key: &str) -> Option<Vec<u8>> {
+    fs::read(custom_path(key)).ok()
+}
+
+pub(crate) fn set_custom(key: &str, data: &[u8]) -> Result<()> {
+    if logo_cache::image_type(data).is_none() {
+        return Err(anyhow!("Unsupported image format"));
+    }
+    fs::create_dir_all(CUSTOM_DIR)?;
+    fs::write(custom_path(key), data)?;
+    Ok(())
+}
+
+// 返回是否删除了已上传的台标
+pub(crate) fn remove_custom(key: &str) -> Result<bool> {
+    match fs::remove_file(custom_path(key)) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// 查找时依次尝试的名称：显示名称、上游名称及其规范化形式
+pub(crate) fn candidate_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut candidates: Vec<String> = Vec::new();
+    for name in names {
+        for candidate in [name.trim().to_string(), normalize_name(name)] {
+            if !candidate.is_empty() && !candidates.contains(&candidate) {
+                candidates.push(candidate);
+            }
+        }
+    }
+    candidates
+}
+
+// 本地目录中的 {名称}.png，返回文件路径和内容
+pub(crate) fn local(dir: &str, names: &[String]) -> Option<(PathBuf, Vec<u8>)> {
+    names
+        .iter()
+        .filter(|name| !name.contains(['/', '\\']) && !name.starts_with('.'))
+        .find_map(|name| {
+            let path = Path::new(dir).join(format!("{}.png", name));
+            fs::read(&path).ok().map(|data| (path, data))
+        })
+}
+
+fn encode_path_segment(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// 远程模板，{name} 为名称，{id} 为频道ID，名称经 URL 编码
+pub(crate) fn template_urls(template: &str, id: &str, names: &[String]) -> Vec<String> {
+    let mut urls: Vec<String> = Vec::new();
+    for name in names {
+        let url = template.replace("{name}", &encode_path_segment(name)).replace("{id}", id);
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+    urls
+}
@@ -1,57 +1,75 @@
 use actix_web::{
-    get, post,
-    web::{Data, Path, Query, Json},
+    delete, get, post,
+    http::header,
+    web::{Bytes, Data, Path, Query, Json},
     App, HttpRequest, HttpResponse, HttpServer, Responder,
     dev::{ServiceRequest, ServiceResponse, forward_ready, Service, Transform},
     body::{BoxBody, MessageBody},
     Error,
 };
 use actix_files as fs;
-use anyhow::{anyhow, Result};
-use chrono::{FixedOffset, TimeZone, Utc};
+use async_stream::stream;
+use anyhow::Result;
 use log::{debug, info, warn, error};
 use serde::{Deserialize, Serialize};
 use base64::{engine::general_purpose, Engine as _};
-use reqwest::Client;
 use std::{
     collections::{BTreeMap, HashMap},
     fs::{File, OpenOptions},
-    io::{BufWriter, Cursor, Read, Write},
+    io::Write,
     net::SocketAddrV4,
     path::Path as StdPath,
     process::exit,
     str::FromStr,
-    sync::{Mutex, LazyLock},
+    sync::{Arc, Mutex, LazyLock},
     time::{SystemTime, UNIX_EPOCH},
     future::{Ready, ready},
 };
-use xml::{
-    reader::XmlEvent as XmlReadEvent,
-    writer::{EmitterConfig, XmlEvent as XmlWriteEvent},
-    EventReader,
-};
+use xml::writer::{EmitterConfig, EventWriter, XmlEvent as XmlWriteEvent};
 use futures_util::future::LocalBoxFuture;
 
 mod args;
 use args::Args;
 
+mod catalog;
+mod category;
+mod catchup;
+mod diyp;
+mod dummy_epg;
+mod epg_crawler;
+mod epg_query;
+mod epg_sanitize;
+mod epg_store;
+mod extra_playlist;
+mod geoip;
+use catchup::CatchupStyle;
+use dummy_epg::DummyEpg;
+use logo_image::LogoFormat;
+use mapping::{RuleSet, CHANNEL_MAPPINGS};
+use profile::{Profile, StreamMode};
+
 mod iptv;
+mod lineup;
+mod logo_cache;
+mod logo_image;
+mod logo_placeholder;
+mod logo_sources;
+mod mapping;
+mod mapping_io;
+mod profile;
 mod xmltv_parser;
-use iptv::{get_channels, get_icon, get_base_url, get_client_with_if, Channel, Program};
-use xmltv_parser::parse_epg_from_xmltv;
+mod xmltv_cache;
+mod xmltv_sources;
+use iptv::{get_channels, get_icon, Channel, Program};
 
+mod hls;
+mod hub;
 mod proxy;
+mod ts;
 
 static OLD_PLAYLIST: Mutex<Option<String>> = Mutex::new(None);
-static OLD_XMLTV: Mutex<Option<String>> = Mutex::new(None);
-static CHANNEL_MAPPINGS: LazyLock<Mutex<HashMap<u64, u64>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
-static MAPPED_XMLTV_CACHE: Mutex<Option<String>> = Mutex::new(None);
-// Logo缓存，避免重复请求电信服务器
-static LOGO_CACHE: LazyLock<Mutex<HashMap<String, Vec<u8>>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
 // 播放统计缓存
 static PLAYBACK_RECORDS: LazyLock<Mutex<Vec<PlaybackRecord>>> = LazyLock::new(|| Mutex::new(Vec::new()));
-const MAPPINGS_FILE: &str = "channel_mappings.json";
-const XMLTV_CACHE_FILE: &str = "xmltv_cache.xml";
 const STATS_FILE: &str = "playback_stats.json";
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -66,31 +84,6 @@ struct PlaybackRecord {
     ip_location: Option<String>, // IP地理位置
 }
 
-#[derive(Deserialize)]
-struct PlaybillList {
-    #[serde(rename = "playbillLites")]
-    list: Vec<Bill>,
-}
-
-#[derive(Deserialize)]
-struct Bill {
-    name: String,
-    #[serde(rename = "startTime")]
-    start_time: i64,
-    #[serde(rename = "endTime")]
-    end_time: i64,
-}
-
-fn parse_channel_mapping(mapping_str: &str) -> HashMap<String, String> {
-    let mut mapping = HashMap::new();
-    for pair in mapping_str.split(',') {
-        if let Some((from, to)) = pair.split_once('=') {
-            mapping.insert(from.trim().to_string(), to.trim().to_string());
-        }
-    }
-    mapping
-}
-
 // 从HttpRequest获取真实客户端IP
 fn get_client_ip(req: &HttpRequest) -> String {
     // 优先获取X-Real-IP (Lucky传递的真实IP)
@@ -112,74 +105,6 @@ fn get_client_ip(req: &HttpRequest) -> String {
         .unwrap_or("unknown").to_string()
 }
 
-// 获取IP地理位置信息
-async fn get_ip_location(ip: &str) -> Option<String> {
-    // 跳过本地IP
-    if ip == "127.0.0.1" || ip == "localhost" || ip.starts_with("192.168.") || 
-       ip.starts_with("10.") || ip.starts_with("172.") {
-        return Some("本地网络".to_string());
-    }
-    
-    // 如果IP为unknown或其他特殊值，直接返回
-    if ip == "unknown" || ip.is_empty() {
-        return Some("未知地区".to_string());
-    }
-    
-    // 使用 ip-api.com 获取地理位置信息
-    match get_client_with_if(None) {
-        Ok(client) => {
-            let url = format!("http://ip-api.com/json/{}?lang=zh-CN", ip);
-            match client.get(&url).send().await {
-                Ok(response) => {
-                    if response.status().is_success() {
-                        match response.json::<serde_json::Value>().await {
-                            Ok(data) => {
-                                // 检查API调用是否成功
-                                if let Some(status) = data.get("status") {
-                                    if status.as_str() == Some("success") {
-                                        // 提取 regionName, city, isp
-                                        let region_name = data.get("regionName")
-                                            .and_then(|v| v.as_str())
-                                            .unwrap_or("");
-                                        let city = data.get("city")
-                                            .and_then(|v| v.as_str())
-                                            .unwrap_or("");
-                                        let isp = data.get("isp")
-                                            .and_then(|v| v.as_str())
-                                            .unwrap_or("");
-                                        
-                                        // 组合格式: "regionName-city-isp"
-                                        let location = format!("{}-{}-{}", region_name, city, isp);
-                                        
-                                        // 如果信息不完整，尝试使用可用的信息
-                                        if !region_name.is_empty() || !city.is_empty() || !isp.is_empty() {
-                                            return Some(location);
-                                        }
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                debug!("Failed to parse IP location response for {}: {}", ip, e);
-                            }
-                        }
-                    } else {
-                        debug!("IP location API returned error status for {}: {}", ip, response.status());
-                    }
-                }
-                Err(e) => {
-                    debug!("Failed to request IP location for {}: {}", ip, e);
-                }
-            }
-        }
-        Err(e) => {
-            debug!("Failed to create HTTP client for IP location: {}", e);
-        }
-    }
-    
-    // 如果获取失败，返回未知地区
-    Some("未知地区".to_string())
-}
-
 // 从RTSP URL路径提取频道ID
 fn extract_channel_id_from_rtsp_url(rtsp_path: &str) -> String {
     // 从URL中提取频道ID，基于你的日志格式：
@@ -208,10 +133,21 @@ fn extract_channel_id_from_rtsp_url(rtsp_path: &str) -> String {
     rtsp_path.to_string()
 }
 
+// 按上游RTSP路径在频道目录中精确查找频道
+async fn find_channel_by_rtsp_path(rtsp_path: &str, args: &Args) -> Option<Channel> {
+    let channels = catalog::channels(args).await.ok()?;
+    channels.into_iter().find(|ch| {
+        ch.rtsp
+            .strip_prefix("rtsp://")
+            .map(|url| url.split('?').next().unwrap_or(url))
+            == Some(rtsp_path)
+    })
+}
+
 // 根据频道ID查找频道名称（增强版 - 支持RTSP ID反向查找）
 async fn get_channel_name_by_id(channel_id: &str, args: &Args) -> String {
     // 尝试从现有频道列表中找到对应名称
-    match get_channels(args, false, "http", &args.bind).await {
+    match catalog::channels(args).await {
         Ok(channels) => {
             // 策略1：精确匹配ID
             if let Ok(id_num) = channel_id.parse::<u64>() {
@@ -223,7 +159,7 @@ async fn get_channel_name_by_id(channel_id: &str, args: &Args) -> String {
             // 策略2：从频道的RTSP URL中反向查找（这是关键！）
             // 分析每个频道的RTSP URL，提取其中的ID与当前channel_id匹配
             for channel in &channels {
-                if let Some(rtsp_url) = &channel.rtsp.split("/rtsp/").nth(1) {
+                if let Some(rtsp_url) = channel.rtsp.strip_prefix("rtsp://") {
                     let rtsp_parts: Vec<&str> = rtsp_url.split('/').collect();
                     // 检查RTSP URL倒数第二部分是否匹配
                     if rtsp_parts.len() >= 2 {
@@ -269,31 +205,6 @@ async fn get_channel_name_by_id(channel_id: &str, args: &Args) -> String {
     format!("未知频道({})", channel_id)
 }
 
-fn find_mapped_channel_id(channel_name: &str, channels: &[Channel], mapping: &HashMap<String, String>) -> u64 {
-    // 先尝试映射
-    if let Some(mapped_name) = mapping.get(channel_name) {
-        // 查找映射后的频道名对应的ID
-        for ch in channels {
-            if ch.name == *mapped_name {
-                return ch.id;
-            }
-        }
-    }
-    // 如果没有映射或者映射的频道不存在，返回0作为默认值
-    0
-}
-
-// 加载频道映射从文件
-fn load_mappings_from_file() -> Result<HashMap<u64, u64>> {
-    if StdPath::new(MAPPINGS_FILE).exists() {
-        let file = File::open(MAPPINGS_FILE)?;
-        let mappings: HashMap<u64, u64> = serde_json::from_reader(file)?;
-        Ok(mappings)
-    } else {
-        Ok(HashMap::new())
-    }
-}
-
 // 保存播放记录到文件
 fn save_playback_record(record: &PlaybackRecord) -> Result<()> {
     // 加载现有记录
@@ -330,277 +241,259 @@ fn load_playback_records() -> Result<Vec<PlaybackRecord>> {
         Ok(Vec::new())
     }
 }
-fn save_mappings_to_file(mappings: &HashMap<u64, u64>) -> Result<()> {
-    let file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(MAPPINGS_FILE)?;
-    serde_json::to_writer_pretty(file, mappings)?;
+
+
+fn to_xmltv_time(unix_time: i64) -> Result<String> {
+    Ok(format!("{} {}", catchup::format_local(unix_time)?, catchup::offset_suffix()))
+}
+
+fn write_programme<W: std::io::Write>(writer: &mut EventWriter<W>, channel_id: u64, epg: &Program) -> Result<()> {
+    writer.write(
+        XmlWriteEvent::start_element("programme")
+            .attr("start", &to_xmltv_time(epg.start)?)
+            .attr("stop", &to_xmltv_time(epg.stop)?)
+            .attr("channel", &format!("{}", channel_id)),
+    )?;
+    writer.write(XmlWriteEvent::start_element("title").attr("lang", "chi"))?;
+    writer.write(XmlWriteEvent::characters(&epg.title))?;
+    writer.write(XmlWriteEvent::end_element())?;
+    if !epg.desc.is_empty() {
+        writer.write(XmlWriteEvent::start_element("desc"))?;
+        writer.write(XmlWriteEvent::characters(&epg.desc))?;
+        writer.write(XmlWriteEvent::end_element())?;
+    }
+    for category in epg.categories.iter() {
+        writer.write(XmlWriteEvent::start_element("category").attr("lang", "chi"))?;
+        writer.write(XmlWriteEvent::characters(category))?;
+        writer.write(XmlWriteEvent::end_element())?;
+    }
+    if let Some(icon) = &epg.icon {
+        writer.write(XmlWriteEvent::start_element("icon").attr("src", icon))?;
+        writer.write(XmlWriteEvent::end_element())?;
+    }
+    if let Some(episode) = &epg.episode {
+        // xmltv_ns 从0开始计数
+        if let Ok(n) = episode.parse::<u32>() {
+            if n > 0 {
+                writer.write(XmlWriteEvent::start_element("episode-num").attr("system", "xmltv_ns"))?;
+                writer.write(XmlWriteEvent::characters(&format!(".{}.", n - 1)))?;
+                writer.write(XmlWriteEvent::end_element())?;
+            }
+        }
+        writer.write(XmlWriteEvent::start_element("episode-num").attr("system", "onscreen"))?;
+        writer.write(XmlWriteEvent::characters(episode))?;
+        writer.write(XmlWriteEvent::end_element())?;
+    }
+    if let Some(rating) = &epg.rating {
+        writer.write(XmlWriteEvent::start_element("rating"))?;
+        writer.write(XmlWriteEvent::start_element("value"))?;
+        writer.write(XmlWriteEvent::characters(rating))?;
+        writer.write(XmlWriteEvent::end_element())?;
+        writer.write(XmlWriteEvent::end_element())?;
+    }
+    writer.write(XmlWriteEvent::end_element())?;
     Ok(())
 }
 
-// 加载缓存的XMLTV
-fn load_xmltv_cache() -> Result<Option<String>> {
-    if StdPath::new(XMLTV_CACHE_FILE).exists() {
-        let mut file = File::open(XMLTV_CACHE_FILE)?;
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)?;
-        Ok(Some(contents))
-    } else {
-        Ok(None)
+// 生成XMLTV时按频道读取节目单：节目单存储中的节目，用外部XMLTV源补充后清理；
+// 每次只有一个频道的节目在内存中
+struct Guide {
+    external: xmltv_sources::ExternalEpg,
+    gap_fill: Option<String>,
+}
+
+impl Guide {
+    async fn new(args: &Args, channels: &[Channel]) -> Self {
+        Self {
+            external: xmltv_sources::external(args, channels).await,
+            gap_fill: args.epg_gap_fill.clone(),
+        }
+    }
+
+    fn programmes(&self, channel: &Channel) -> Vec<Program> {
+        let mut epg = epg_store::programmes(channel.id);
+        self.external.fill(channel.id, &mut epg);
+        let anomalies = epg_sanitize::sanitize(&mut epg, self.gap_fill.as_deref());
+        if !anomalies.is_empty() {
+            debug!(
+                "Sanitized EPG of {}: {} invalid, {} duplicates, {} overlaps, {} gaps",
+                channel.name, anomalies.invalid, anomalies.duplicates, anomalies.overlaps, anomalies.gaps
+            );
+        }
+        epg
     }
 }
 
-// 保存XMLTV缓存
-fn save_xmltv_cache(xmltv_content: &str) -> Result<()> {
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(XMLTV_CACHE_FILE)?;
-    file.write_all(xmltv_content.as_bytes())?;
+fn write_channel<W: Write>(writer: &mut EventWriter<W>, id: u64, name: &str, base: &str) -> Result<()> {
+    writer.write(XmlWriteEvent::start_element("channel").attr("id", &id.to_string()))?;
+    writer.write(XmlWriteEvent::start_element("display-name"))?;
+    writer.write(XmlWriteEvent::characters(name))?;
+    writer.write(XmlWriteEvent::end_element())?;
+    writer.write(XmlWriteEvent::start_element("icon").attr("src", &format!("{}/logo/{}.png", base, id)))?;
+    writer.write(XmlWriteEvent::end_element())?;
+    writer.write(XmlWriteEvent::end_element())?;
     Ok(())
 }
 
-
-fn to_xmltv_time(unix_time: i64) -> Result<String> {
-    match Utc.timestamp_millis_opt(unix_time) {
-        chrono::LocalResult::Single(t) => Ok(t
-            .with_timezone(&FixedOffset::east_opt(8 * 60 * 60).ok_or(anyhow!(""))?)
-            .format("%Y%m%d%H%M%S")
-            .to_string()),
-        _ => Err(anyhow!("fail to parse time")),
+// 同时写入可见频道的版本和完整版本，隐藏的频道只写入完整版本
+fn write_both<W: Write>(
+    visible: &mut EventWriter<W>,
+    full: &mut Option<EventWriter<W>>,
+    hidden: bool,
+    mut write: impl FnMut(&mut EventWriter<W>) -> Result<()>,
+) -> Result<()> {
+    if !hidden {
+        write(visible)?;
     }
+    if let Some(full) = full {
+        write(full)?;
+    }
+    Ok(())
 }
 
-fn to_xmltv<R: Read>(channels: Vec<Channel>, extra: Option<EventReader<R>>, mapping: &HashMap<String, String>) -> Result<String> {
-    let mut buf = BufWriter::new(Vec::new());
-    let mut writer = EmitterConfig::new()
-        .perform_indent(false)
-        .create_writer(&mut buf);
-    writer.write(
-        XmlWriteEvent::start_element("tv")
-            .attr("generator-info-name", "iptv-proxy")
-            .attr("source-info-name", "iptv-proxy"),
-    )?;
-    for channel in channels.iter() {
+// 生成XMLTV，out 不含隐藏的频道；full 为 Some 时同时写入包含隐藏频道的完整版本
+fn to_xmltv<W: Write>(
+    out: W,
+    full: Option<W>,
+    channels: &[Channel],
+    guide: &Guide,
+    rules: &RuleSet,
+    base: &str,
+    dummy: Option<&DummyEpg>,
+) -> Result<()> {
+    let create = |out: W| EmitterConfig::new().perform_indent(false).create_writer(out);
+    let mut visible = create(out);
+    let mut full = full.map(create);
+    write_both(&mut visible, &mut full, false, |writer| {
         writer.write(
-            XmlWriteEvent::start_element("channel").attr("id", &format!("{}", channel.id)),
+            XmlWriteEvent::start_element("tv")
+                .attr("generator-info-name", "iptv-proxy")
+                .attr("source-info-name", "iptv-proxy"),
         )?;
-        writer.write(XmlWriteEvent::start_element("display-name"))?;
-        writer.write(XmlWriteEvent::characters(&channel.name))?;
-        writer.write(XmlWriteEvent::end_element())?;
-        writer.write(XmlWriteEvent::end_element())?;
+        Ok(())
+    })?;
+    let hidden = lineup::hidden_ids();
+    let arranged = if full.is_some() {
+        lineup::arrange_all(rules, channels)
+    } else {
+        lineup::arrange(rules, channels)
+    };
+    for (_, r) in arranged.iter() {
+        write_both(&mut visible, &mut full, hidden.contains(&r.id), |writer| {
+            write_channel(writer, r.id, &r.name, base)
+        })?;
     }
-    if let Some(extra) = extra {
-        for e in extra {
-            match e {
-                Ok(XmlReadEvent::StartElement {
-                    name, attributes, ..
-                }) => {
-                    let name = name.to_string();
-                    let name = name.as_str();
-                    if name != "channel"
-                        && name != "display-name"
-                        && name != "desc"
-                        && name != "title"
-                        && name != "sub-title"
-                        && name != "programme"
-                    {
-                        continue;
-                    }
-                    let name = if name == "title" {
-                        let mut iter = attributes.iter();
-                        loop {
-                            let attr = iter.next();
-                            if attr.is_none() {
-                                break "title";
-                            }
-                            let attr = attr.unwrap();
-                            if attr.name.to_string() == "lang" && attr.value != "chi" {
-                                break "title_extra";
-                            }
-                        }
-                    } else {
-                        name
-                    };
-                    let mut tag = XmlWriteEvent::start_element(name);
-                    for attr in attributes.iter() {
-                        tag = tag.attr(attr.name.borrow(), &attr.value);
-                    }
-                    writer.write(tag)?;
-                }
-                Ok(XmlReadEvent::Characters(content)) => {
-                    writer.write(XmlWriteEvent::characters(&content))?;
-                }
-                Ok(XmlReadEvent::EndElement { name }) => {
-                    let name = name.to_string();
-                    let name = name.as_str();
-                    if name != "channel"
-                        && name != "display-name"
-                        && name != "desc"
-                        && name != "title"
-                        && name != "sub-title"
-                        && name != "programme"
-                    {
-                        continue;
-                    }
-                    writer.write(XmlWriteEvent::end_element())?;
-                }
-                _ => {}
+    // 为每个频道生成节目信息，映射的来源频道有节目单时使用来源频道的
+    let mut mapped_count = 0;
+    let mut channels_with_epg = 0;
+    for &(channel, ref r) in arranged.iter() {
+        let source = (r.epg_id != channel.id)
+            .then(|| channels.iter().find(|ch| ch.id == r.epg_id))
+            .flatten()
+            .map(|ch| (ch, guide.programmes(ch)))
+            .filter(|(_, epg)| !epg.is_empty());
+        let epg = match source {
+            Some((source, epg)) => {
+                log::debug!("Channel '{}' ({}) using EPG from '{}' ({}), rule {}",
+                    channel.name, channel.id, source.name, source.id, r.applied.get("epg").map_or("", |s| s.as_str()));
+                mapped_count += 1;
+                epg
             }
-        }
+            None => guide.programmes(channel),
+        };
+        let epg = if !epg.is_empty() {
+            channels_with_epg += 1;
+            epg
+        } else if let Some(dummy) = dummy {
+            // 没有节目单时生成占位节目，避免播放器隐藏频道
+            dummy.programmes(channel)
+        } else {
+            log::warn!("Channel '{}' ({}) has no EPG and no mapping found", channel.name, channel.id);
+            continue;
+        };
+        write_both(&mut visible, &mut full, hidden.contains(&channel.id), |writer| {
+            epg.iter().try_for_each(|programme| write_programme(writer, channel.id, programme))
+        })?;
     }
-    // 为每个频道生成节目信息
-    for channel in channels.iter() {
-        let mut mapped_epg_used = false;
-        let mut mapped_from = String::new();
-        
-        // 首先检查是否有映射
-        if let Ok(mappings) = CHANNEL_MAPPINGS.try_lock() {
-            // 查找是否有其他频道映射到当前频道
-            let mut source_channels = Vec::new();
-            for (&from_id, &to_id) in mappings.iter() {
-                if to_id == channel.id {
-                    source_channels.push(from_id);
-                }
-            }
-            
-            if !source_channels.is_empty() {
-                // 当前频道是映射的目标，使用自己的EPG数据
-                log::debug!("Channel '{}' ({}) is target for sources: {:?}", channel.name, channel.id, source_channels);
-                // 使用自己的EPG数据（因为它是目标频道）
-                if !channel.epg.is_empty() {
-                    mapped_from = format!("target for channels {:?}", source_channels);
-                    log::debug!("Channel '{}' ({}) using its own EPG ({} programs) as mapping target", 
-                        channel.name, channel.id, channel.epg.len());
-                    
-                    for epg in channel.epg.iter() {
-                        writer.write(
-                            XmlWriteEvent::start_element("programme")
-                                .attr("start", &format!("{} +0800", to_xmltv_time(epg.start)?))
-                                .attr("stop", &format!("{} +0800", to_xmltv_time(epg.stop)?))
-                                .attr("channel", &format!("{}", channel.id)),
-                        )?;
-                        writer.write(XmlWriteEvent::start_element("title").attr("lang", "chi"))?;
-                        writer.write(XmlWriteEvent::characters(&epg.title))?;
-                        writer.write(XmlWriteEvent::end_element())?;
-                        if !epg.desc.is_empty() {
-                            writer.write(XmlWriteEvent::start_element("desc"))?;
-                            writer.write(XmlWriteEvent::characters(&epg.desc))?;
-                            writer.write(XmlWriteEvent::end_element())?;
-                        }
-                        writer.write(XmlWriteEvent::end_element())?;
-                    }
-                    mapped_epg_used = true;
-                }
-            } else if let Some(&mapped_id) = mappings.get(&channel.id) {
-                // 当前频道是映射的源，使用目标频道的EPG数据
-                log::debug!("Channel '{}' ({}) is source, mapping to target {}", channel.name, channel.id, mapped_id);
-                if let Some(mapped_channel) = channels.iter().find(|ch| ch.id == mapped_id) {
-                    if !mapped_channel.epg.is_empty() {
-                        // 使用映射频道的EPG数据
-                        mapped_from = format!("ID {} ({})", mapped_id, mapped_channel.name);
-                        log::debug!("Channel '{}' ({}) using EPG from mapped channel '{}' ({} programs)", 
-                            channel.name, channel.id, mapped_channel.name, mapped_channel.epg.len());
-                        
-                        for epg in mapped_channel.epg.iter() {
-                            writer.write(
-                                XmlWriteEvent::start_element("programme")
-                                    .attr("start", &format!("{} +0800", to_xmltv_time(epg.start)?))
-                                    .attr("stop", &format!("{} +0800", to_xmltv_time(epg.stop)?))
-                                    .attr("channel", &format!("{}", channel.id)),
-                            )?;
-                            writer.write(XmlWriteEvent::start_element("title").attr("lang", "chi"))?;
-                            writer.write(XmlWriteEvent::characters(&epg.title))?;
-                            writer.write(XmlWriteEvent::end_element())?;
-                            if !epg.desc.is_empty() {
-                                writer.write(XmlWriteEvent::start_element("desc"))?;
-                                writer.write(XmlWriteEvent::characters(&epg.desc))?;
-                                writer.write(XmlWriteEvent::end_element())?;
-                            }
-                            writer.write(XmlWriteEvent::end_element())?;
-                        }
-                        mapped_epg_used = true;
-                    }
-                }
-            }
+    write_both(&mut visible, &mut full, false, |writer| Ok(writer.write(XmlWriteEvent::end_element())?))?;
+
+    log::info!("XMLTV generation completed: {} total channels, {} with EPG, {} without EPG, {} mapped",
+        arranged.len(), channels_with_epg, arranged.len() - channels_with_epg, mapped_count);
+
+    visible.into_inner().flush()?;
+    if let Some(full) = full {
+        full.into_inner().flush()?;
+    }
+    Ok(())
+}
+
+// 缓存的节目单中台标地址的前缀：--public-url，未设置时用绑定地址，0.0.0.0 换成本机地址
+fn public_base(args: &Args) -> String {
+    if let Some(url) = &args.public_url {
+        return url.trim_end_matches('/').to_string();
+    }
+    let host = match args.bind.parse::<std::net::SocketAddr>() {
+        Ok(addr) if addr.ip().is_unspecified() => match local_ip_address::local_ip() {
+            Ok(ip) => std::net::SocketAddr::new(ip, addr.port()).to_string(),
+            Err(_) => args.bind.clone(),
+        },
+        _ => args.bind.clone(),
+    };
+    format!("http://{}", host)
+}
+
+// 生成XMLTV并写入缓存文件（含 gzip 版本），缓存包含隐藏的频道，输出时再过滤；
+// 节目单按频道从节目单存储读取，channels 不需要附带节目单
+async fn save_xmltv(args: &Args, channels: Vec<Channel>) -> Result<()> {
+    let guide = Guide::new(args, &channels).await;
+    let rules = RuleSet::load(args);
+    let base = public_base(args);
+    let dummy = DummyEpg::from_args(args);
+    tokio::task::spawn_blocking(move || {
+        xmltv_cache::save(|out, full| {
+            // 两个输出的生命周期不同，重新借用为同一类型
+            let (out, full): (&mut dyn Write, &mut dyn Write) = (&mut *out, &mut *full);
+            to_xmltv(out, Some(full), &channels, &guide, &rules, &base, dummy.as_ref())
+        })
+    })
+    .await?
+}
+
+// 后台重新生成XMLTV的状态：(正在生成, 生成期间又有修改)
+static XMLTV_REGENERATION: LazyLock<Mutex<(bool, bool)>> = LazyLock::new(|| Mutex::new((false, false)));
+
+// 映射、规则或频道表修改后在后台重新生成XMLTV缓存，节目单取自节目单存储，不重新抓取；
+// 生成期间的修改在本次完成后再生成一次
+fn regenerate_xmltv(args: &Data<Args>) {
+    {
+        let mut state = XMLTV_REGENERATION.lock().unwrap_or_else(|e| e.into_inner());
+        state.1 = true;
+        if state.0 {
+            return;
         }
-        
-        // 如果没有前端映射，尝试传统的名称映射
-        if !mapped_epg_used {
-            if let Some(mapped_name) = mapping.get(&channel.name) {
-                if let Some(mapped_channel) = channels.iter().find(|ch| ch.name == *mapped_name) {
-                    if !mapped_channel.epg.is_empty() {
-                        // 使用映射频道的EPG数据
-                        mapped_from = format!("name '{}' ({})", mapped_name, mapped_channel.name);
-                        log::debug!("Channel '{}' ({}) using EPG from mapped channel '{}' ({} programs)", 
-                            channel.name, channel.id, mapped_channel.name, mapped_channel.epg.len());
-                        
-                        for epg in mapped_channel.epg.iter() {
-                            writer.write(
-                                XmlWriteEvent::start_element("programme")
-                                    .attr("start", &format!("{} +0800", to_xmltv_time(epg.start)?))
-                                    .attr("stop", &format!("{} +0800", to_xmltv_time(epg.stop)?))
-                                    .attr("channel", &format!("{}", channel.id)),
-                            )?;
-                            writer.write(XmlWriteEvent::start_element("title").attr("lang", "chi"))?;
-                            writer.write(XmlWriteEvent::characters(&epg.title))?;
-                            writer.write(XmlWriteEvent::end_element())?;
-                            if !epg.desc.is_empty() {
-                                writer.write(XmlWriteEvent::start_element("desc"))?;
-                                writer.write(XmlWriteEvent::characters(&epg.desc))?;
-                                writer.write(XmlWriteEvent::end_element())?;
-                            }
-                            writer.write(XmlWriteEvent::end_element())?;
-                        }
-                        mapped_epg_used = true;
-                    }
+        state.0 = true;
+    }
+    let args = args.clone();
+    tokio::spawn(async move {
+        loop {
+            {
+                let mut state = XMLTV_REGENERATION.lock().unwrap_or_else(|e| e.into_inner());
+                if !state.1 {
+                    state.0 = false;
+                    break;
                 }
+                state.1 = false;
             }
-        }
-        
-        // 如果没有使用映射的EPG，使用自己的EPG数据
-        if !mapped_epg_used {
-            if !channel.epg.is_empty() {
-                // 使用自己的EPG数据
-                for epg in channel.epg.iter() {
-                    writer.write(
-                        XmlWriteEvent::start_element("programme")
-                            .attr("start", &format!("{} +0800", to_xmltv_time(epg.start)?))
-                            .attr("stop", &format!("{} +0800", to_xmltv_time(epg.stop)?))
-                            .attr("channel", &format!("{}", channel.id)),
-                    )?;
-                    writer.write(XmlWriteEvent::start_element("title").attr("lang", "chi"))?;
-                    writer.write(XmlWriteEvent::characters(&epg.title))?;
-                    writer.write(XmlWriteEvent::end_element())?;
-                    if !epg.desc.is_empty() {
-                        writer.write(XmlWriteEvent::start_element("desc"))?;
-                        writer.write(XmlWriteEvent::characters(&epg.desc))?;
-                        writer.write(XmlWriteEvent::end_element())?;
-                    }
-                    writer.write(XmlWriteEvent::end_element())?;
-                }
-            } else {
-                log::warn!("Channel '{}' ({}) has no EPG and no mapping found", channel.name, channel.id);
+            let result = match catalog::channels(&args).await {
+                Ok(channels) => save_xmltv(&args, channels).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => info!("XMLTV regenerated after configuration change"),
+                Err(e) => warn!("Failed to regenerate XMLTV: {}", e),
             }
-        } else {
-            // 记录映射使用情况
-            log::info!("Channel '{}' ({}) mapped from {}", channel.name, channel.id, mapped_from);
         }
-    }
-    writer.write(XmlWriteEvent::end_element())?;
-    
-    // 统计信息
-    let total_channels = channels.len();
-    let channels_with_epg = channels.iter().filter(|ch| !ch.epg.is_empty()).count();
-    let channels_without_epg = total_channels - channels_with_epg;
-    
-    log::info!("XMLTV generation completed: {} total channels, {} with EPG, {} without EPG", 
-        total_channels, channels_with_epg, channels_without_epg);
-    
-    Ok(String::from_utf8(buf.into_inner()?)?)
+    });
 }
 
 // 获取频道列表，并从XMLTV缓存中附加EPG数据
@@ -623,154 +516,8 @@ async fn get_channels_with_epg(args: &Args, scheme: &str, host: &str) -> Result<
 }
 
 // 从XMLTV缓存中获取EPG数据
-async fn get_epg_from_xmltv_cache() -> Result<HashMap<u64, Vec<Program>>> {
-    // 首先尝试从内存缓存获取
-    if let Ok(cache) = MAPPED_XMLTV_CACHE.try_lock() {
-        if let Some(ref xmltv_content) = *cache {
-            debug!("Using in-memory XMLTV cache, size: {} bytes", xmltv_content.len());
-            return parse_epg_from_xmltv(xmltv_content);
-        }
-    }
-    
-    // 如果内存没有，尝试从文件加载
-    match load_xmltv_cache() {
-        Ok(Some(xmltv_content)) => {
-            debug!("Loaded XMLTV from file cache, size: {} bytes", xmltv_content.len());
-            // 更新内存缓存
-            if let Ok(mut cache) = MAPPED_XMLTV_CACHE.try_lock() {
-                *cache = Some(xmltv_content.clone());
-            }
-            parse_epg_from_xmltv(&xmltv_content)
-        }
-        Ok(None) => {
-            debug!("No XMLTV cache found");
-            Ok(HashMap::new())
-        }
-        Err(e) => {
-            log::error!("Failed to load XMLTV cache: {}", e);
-            Err(anyhow!("Failed to load XMLTV cache: {}", e))
-        }
-    }
-}
-
-async fn parse_extra_xml(url: &str) -> Result<EventReader<Cursor<String>>> {
-    let client = Client::builder().build()?;
-    let url = reqwest::Url::parse(url)?;
-    let response = client.get(url).send().await?.error_for_status()?;
-    let xml = response.text().await?;
-    let reader = Cursor::new(xml);
-    Ok(EventReader::new(reader))
-}
-
-// 获取所有频道的EPG数据（带进度显示）
-async fn fetch_all_channels_epg_simple(args: &Args) -> Result<Vec<Channel>> {
-    log::info!("Starting EPG fetch for all channels");
-    
-      
-    let scheme = "http";
-    let host = &args.bind;
-    
-    // 先获取频道列表（这会进行认证并获取EPG数据）
-    let channels = get_channels(args, true, scheme, host).await?;
-    
-    let channel_count = channels.len();
-    log::info!("Got {} channels with EPG data", channel_count);
-    
-    let _now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
-    let mut with_epg_count = 0;
-    
-    // 统计有EPG数据的频道
-    for channel in channels.iter() {
-        if !channel.epg.is_empty() {
-            with_epg_count += 1;
-        }
-    }
-    
-    log::info!("EPG fetch completed: {}/{} channels have EPG data", with_epg_count, channel_count);
-      
-    Ok(channels)
-}
-
-// 获取所有频道的EPG数据
-async fn fetch_all_channels_epg(args: &Args) -> Result<Vec<Channel>> {
-    log::info!("Starting full EPG fetch for all channels");
-    
-    let scheme = "http";
-    let host = &args.bind;
-    
-    // 先获取频道列表（这会进行认证）
-    let channels = get_channels(args, false, scheme, host).await?;
-    
-    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
-    
-    let mut channels_with_epg = Vec::new();
-    
-    log::info!("Fetching EPG for {} channels", channels.len());
-    
-    // 限制并发数为1，避免被服务器拒绝
-    let semaphore = std::sync::Arc::new(tokio::sync::Semaphore::new(1));
-    let mut handles = Vec::new();
-    
-    // 为每个频道创建一个已认证的client
-    for channel in channels {
-        let params = [
-            ("channelId", format!("{}", channel.id)),
-            ("begin", format!("{}", now - 86400000 * 2)), // 2天前
-            ("end", format!("{}", now + 86400000 * 5)),   // 5天后
-        ];
-        
-        // 为每个频道创建新的client和认证
-        let client = get_client_with_if(args.interface.as_deref())?;
-        let base_url = get_base_url(&client, args).await?;
-        let url = reqwest::Url::parse_with_params(
-            format!("{}/EPG/jsp/iptvsnmv3/en/play/ajax/_ajax_getPlaybillList.jsp", base_url).as_str(),
-            params,
-        )?;
-        let permit = semaphore.clone().acquire_owned().await?;
-        
-        let handle = tokio::spawn(async move {
-            let _permit = permit; // 持有permit直到任务完成
-            let response = client.get(url).send().await;
-            (response, channel)
-        });
-        handles.push(handle);
-    }
-    
-    for handle in handles {
-        if let Ok((Ok(res), mut channel)) = handle.await {
-            log::debug!("Processing response for channel: {}", channel.name);
-            
-            let response_text = res.text().await.unwrap_or_else(|_| "Failed to get response text".to_string());
-            
-            match serde_json::from_str::<PlaybillList>(&response_text) {
-                Ok(play_bill_list) => {
-                    log::debug!("Got {} programs for channel '{}'", play_bill_list.list.len(), channel.name);
-                    for bill in play_bill_list.list.into_iter() {
-                        channel.epg.push(Program {
-                            start: bill.start_time,
-                            stop: bill.end_time,
-                            title: bill.name.clone(),
-                            desc: bill.name,
-                        })
-                    }
-                    log::debug!("Fetched {} programs for channel '{}'", channel.epg.len(), channel.name);
-                }
-                Err(e) => {
-                    log::warn!("Failed to parse EPG data for channel '{}': {}", channel.name, e);
-                    log::debug!("Response for channel {}: {}", channel.name, response_text);
-                }
-            }
-            channels_with_epg.push(channel);
-        }
-    }
-    
-    
-    let with_epg = channels_with_epg.iter().filter(|ch| !ch.epg.is_empty()).count();
-    let without_epg = channels_with_epg.len() - with_epg;
-    
-    log::info!("EPG fetch completed: {} channels with EPG, {} without EPG", with_epg, without_epg);
-    
-    Ok(channels_with_epg)
+async fn get_epg_from_xmltv_cache() -> Result<Arc<HashMap<u64, Vec<Program>>>> {
+    tokio::task::spawn_blocking(xmltv_cache::epg).await?
 }
 
 // 定时获取所有EPG数据
@@ -782,24 +529,12 @@ async fn fetch_all_epg_periodically(args: Data<Args>) {
         let host = &args.bind;
         
         // 使用累积式EPG获取
-        match get_channels_with_cumulative_epg(&args, scheme, host).await {
+        match get_channels_with_cumulative_epg(&args, scheme, host, "scheduled").await {
             Ok(channels) => {
                 // 重新生成XMLTV
-                let mapping = args.channel_mapping.as_ref()
-                    .map(|s| parse_channel_mapping(s))
-                    .unwrap_or_default();
-                
-                match to_xmltv(channels.clone(), None::<EventReader<Cursor<String>>>, &mapping) {
-                    Ok(xmltv) => {
-                        if let Ok(mut cache) = MAPPED_XMLTV_CACHE.try_lock() {
-                            *cache = Some(xmltv.clone());
-                            
-                            if let Err(e) = save_xmltv_cache(&xmltv) {
-                                log::error!("Failed to save XMLTV cache: {}", e);
-                            } else {
-                                log::info!("XMLTV regenerated after scheduled EPG cumulative fetch");
-                            }
-                        }
+                match save_xmltv(&args, channels).await {
+                    Ok(()) => {
+                        log::info!("XMLTV regenerated after scheduled EPG cumulative fetch");
                     }
                     Err(e) => {
                         log::error!("Failed to regenerate XMLTV: {}", e);
@@ -818,9 +553,6 @@ async fn fetch_all_epg_periodically(args: Data<Args>) {
 
 // 定时生成映射后的XMLTV
 async fn generate_mapped_xmltv_periodically(args: Data<Args>) {
-    let scheme = "http"; // 默认scheme
-    let host = &args.bind;
-    
     loop {
         tokio::time::sleep(tokio::time::Duration::from_secs(21600)).await; // 每6小时更新一次
         
@@ -834,30 +566,12 @@ async fn generate_mapped_xmltv_periodically(args: Data<Args>) {
             }
         }
         
-        let extra_xml = match &args.extra_xmltv {
-            Some(u) => parse_extra_xml(u).await.ok(),
-            None => None,
-        };
-        
-        let mapping = args.channel_mapping.as_ref()
-            .map(|s| parse_channel_mapping(s))
-            .unwrap_or_default();
-            
-        match get_channels(&args, true, scheme, host).await {
+        match catalog::channels(&args).await {
             Ok(channels) => {
                 // 使用内存中的CHANNEL_MAPPINGS来生成XMLTV
-                match to_xmltv_with_mappings(channels, extra_xml, &mapping).await {
-                    Ok(xmltv) => {
-                        if let Ok(mut cache) = MAPPED_XMLTV_CACHE.try_lock() {
-                            *cache = Some(xmltv.clone());
-                            
-                            // 保存到文件
-                            if let Err(e) = save_xmltv_cache(&xmltv) {
-                                log::error!("Failed to save XMLTV cache: {}", e);
-                            } else {
-                                log::info!("XMLTV cache updated and saved");
-                            }
-                        }
+                match save_xmltv(&args, channels).await {
+                    Ok(()) => {
+                        log::info!("XMLTV cache updated and saved");
                     }
                     Err(e) => {
                         log::error!("Failed to generate XMLTV: {}", e);
@@ -881,14 +595,11 @@ async fn update_logo_cache(args: &Args, channels: &[Channel]) -> Result<()> {
     let mut failed_count = 0;
     
     for channel in channels {
-        match get_icon(args, &channel.id.to_string()).await {
-            Ok(icon_data) => {
-                // 更新缓存
-                if let Ok(mut cache) = LOGO_CACHE.try_lock() {
-                    cache.insert(channel.id.to_string(), icon_data);
-                    updated_count += 1;
-                    debug!("✓ 更新频道 '{}' 的Logo缓存", channel.name);
-                }
+        let key = channel.id.to_string();
+        match fetch_logo(args, &key).await.and_then(|icon| logo_cache::store(&key, icon)) {
+            Ok(_) => {
+                updated_count += 1;
+                debug!("✓ 更新频道 '{}' 的Logo缓存", channel.name);
             }
             Err(e) => {
                 debug!("✗ 获取频道 '{}' 的Logo失败: {}", channel.name, e);
@@ -901,64 +612,19 @@ async fn update_logo_cache(args: &Args, channels: &[Channel]) -> Result<()> {
     Ok(())
 }
 
-async fn get_channels_with_cumulative_epg(args: &Args, scheme: &str, host: &str) -> Result<Vec<Channel>> {
-    // 1. 获取基础频道列表（不含EPG）
-    let mut base_channels = get_channels(args, false, scheme, host).await?;
-    
-    // 2. 从XMLTV缓存中读取已有的EPG数据
-    let existing_epg = match get_epg_from_xmltv_cache().await {
-        Ok(epg_data) => epg_data,
-        Err(_) => {
-            debug!("No existing EPG cache found, starting fresh");
-            HashMap::new()
-        }
-    };
-    
-    // 3. 获取新的EPG数据
-    info!("开始获取新的EPG数据以进行累积更新...");
-    let new_channels_with_epg = get_channels(args, true, scheme, host).await?;
-    
-    // 4. 创建新EPG数据的映射
-    let mut new_epg_map = HashMap::new();
-    for channel in &new_channels_with_epg {
-        if !channel.epg.is_empty() {
-            new_epg_map.insert(channel.id, channel.epg.clone());
-        }
-    }
-    
-    // 5. 合并EPG数据：新数据优先，但如果新数据中某频道无EPG则保留旧数据
-    let mut channels_with_preserved_count = 0;
-    let mut channels_with_new_count = 0;
-    
-    for channel in &mut base_channels {
-        if let Some(new_epg) = new_epg_map.get(&channel.id) {
-            // 使用新的EPG数据
-            channel.epg = new_epg.clone();
-            channels_with_new_count += 1;
-            debug!("✓ 频道 '{}' 使用新获取的EPG数据 ({} 个节目)", channel.name, new_epg.len());
-        } else if let Some(existing_epg) = existing_epg.get(&channel.id) {
-            // 保留旧的EPG数据
-            channel.epg = existing_epg.clone();
-            channels_with_preserved_count += 1;
-            debug!("← 频道 '{}' 保留缓存的EPG数据 ({} 个节目)", channel.name, existing_epg.len());
-        }
-        // 如果新旧数据都没有，则epg保持为空
+async fn get_channels_with_cumulative_epg(args: &Args, scheme: &str, host: &str, trigger: &str) -> Result<Vec<Channel>> {
+    // 由EPG任务增量获取缺少或需要刷新的日期，完成后从节目单存储读取
+    let (job_id, handle) = epg_crawler::submit(args.clone(), trigger);
+    info!("等待EPG任务 {} 完成...", job_id);
+    if let Err(e) = handle.await? {
+        warn!("EPG任务 {} 失败，使用已有节目单: {}", job_id, e);
     }
-    
-    let total_channels_with_epg = base_channels.iter().filter(|ch| !ch.epg.is_empty()).count();
-    info!("EPG累积合并完成: 新获取{}个, 保留缓存{}个, 总计{}个频道有节目单", 
-        channels_with_new_count, channels_with_preserved_count, total_channels_with_epg);
-    
-    Ok(base_channels)
-}
+    let channels = get_channels(args, false, scheme, host).await?;
 
-async fn to_xmltv_with_mappings<R: Read>(
-    channels: Vec<Channel>,
-    extra: Option<EventReader<R>>,
-    mapping: &HashMap<String, String>,
-) -> Result<String> {
-    // 直接使用原始的to_xmltv函数，它已经包含了映射逻辑
-    to_xmltv(channels, extra, mapping)
+    let total_channels_with_epg = channels.iter().filter(|ch| epg_store::has_programmes(ch.id)).count();
+    info!("EPG累积更新完成: 共{}个频道有节目单", total_channels_with_epg);
+
+    Ok(channels)
 }
 
 #[get("/api/playback-stats")]
@@ -1032,110 +698,245 @@ struct MappingRequest {
 }
 
 #[post("/api/channel-mappings")]
-async fn api_set_channel_mappings(req: Json<MappingRequest>) -> impl Responder {
+async fn api_set_channel_mappings(args: Data<Args>, req: Json<MappingRequest>) -> impl Responder {
     debug!("Setting channel mappings");
     
-    if let Ok(mut mappings) = CHANNEL_MAPPINGS.try_lock() {
+    let result = mapping::commit("replace", |mappings| {
         mappings.clear();
-        for mapping in &req.mappings {
-            mappings.insert(mapping.from_id, mapping.to_id);
+        for m in &req.mappings {
+            mappings.insert(m.from_id, m.to_id);
         }
-        
-        // 保存到文件
-        if let Err(e) = save_mappings_to_file(&mappings) {
-            log::error!("Failed to save mappings to file: {}", e);
+    });
+    match result {
+        Ok(_) => {
+            regenerate_xmltv(&args);
+            HttpResponse::Ok().json("Mappings updated successfully")
         }
-        
-        // 清除XMLTV缓存，强制重新生成
-        if let Ok(mut cache) = MAPPED_XMLTV_CACHE.try_lock() {
-            *cache = None;
+        Err(e) => {
+            log::error!("Failed to save mappings: {}", e);
+            HttpResponse::InternalServerError().json("Failed to update mappings")
         }
-        
-        
-        HttpResponse::Ok().json("Mappings updated successfully")
+    }
+}
+
+// 增量添加映射，已有的同一频道映射被替换
+#[post("/api/channel-mappings/add")]
+async fn api_add_channel_mappings(args: Data<Args>, req: Json<MappingRequest>) -> impl Responder {
+    match mapping::add_mappings("add", req.mappings.iter().map(|m| (m.from_id, m.to_id))) {
+        Ok(added) => {
+            regenerate_xmltv(&args);
+            HttpResponse::Ok().json(format!("{} mappings added", added))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(format!("Failed to update mappings: {}", e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct RemoveMappingRequest {
+    from_ids: Vec<u64>,
+}
+
+#[post("/api/channel-mappings/remove")]
+async fn api_remove_channel_mappings(args: Data<Args>, req: Json<RemoveMappingRequest>) -> impl Responder {
+    match mapping::remove_mappings(&req.from_ids) {
+        Ok(removed) => {
+            regenerate_xmltv(&args);
+            HttpResponse::Ok().json(format!("{} mappings removed", removed))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(format!("Failed to update mappings: {}", e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct MappingFormatQuery {
+    format: Option<String>,
+    // 导入方式：merge（默认）或 replace
+    mode: Option<String>,
+}
+
+impl MappingFormatQuery {
+    fn is_csv(&self) -> bool {
+        self.format.as_deref().is_some_and(|f| f.eq_ignore_ascii_case("csv"))
+    }
+}
+
+// 导出映射，格式为 json（默认）或 csv，包含频道ID和名称
+#[get("/api/channel-mappings/export")]
+async fn api_export_channel_mappings(args: Data<Args>, query: Query<MappingFormatQuery>) -> impl Responder {
+    let channels = catalog::channels(&args).await.unwrap_or_default();
+    let entries = mapping_io::export(&channels, &mapping::mappings());
+    if query.is_csv() {
+        HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header((header::CONTENT_DISPOSITION, "attachment; filename=\"channel_mappings.csv\""))
+            .body(mapping_io::to_csv(&entries))
     } else {
-        HttpResponse::InternalServerError().json("Failed to update mappings")
+        HttpResponse::Ok()
+            .insert_header((header::CONTENT_DISPOSITION, "attachment; filename=\"channel_mappings.json\""))
+            .json(entries)
+    }
+}
+
+// 导入映射，名称能对应到频道时按名称，否则按ID
+#[post("/api/channel-mappings/import")]
+async fn api_import_channel_mappings(
+    args: Data<Args>,
+    query: Query<MappingFormatQuery>,
+    body: Bytes,
+) -> impl Responder {
+    let body = String::from_utf8_lossy(&body);
+    let entries = if query.is_csv() {
+        mapping_io::parse_csv(&body)
+    } else {
+        serde_json::from_str::<Vec<mapping_io::MappingEntry>>(&body).map_err(anyhow::Error::from)
+    };
+    let entries = match entries {
+        Ok(entries) => entries,
+        Err(e) => return HttpResponse::BadRequest().json(format!("Invalid mappings: {}", e)),
+    };
+
+    let channels = catalog::channels(&args).await.unwrap_or_default();
+    let (mappings, skipped) = mapping_io::resolve(&entries, &channels);
+    let replace = query.mode.as_deref() == Some("replace");
+    let imported = mappings.len();
+    let result = mapping::commit("import", |current| {
+        if replace {
+            current.clear();
+        }
+        current.extend(mappings);
+    });
+    match result {
+        Ok(revision) => {
+            regenerate_xmltv(&args);
+            HttpResponse::Ok().json(serde_json::json!({
+                "imported": imported,
+                "skipped": skipped,
+                "revision": revision,
+            }))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(format!("Failed to update mappings: {}", e)),
     }
 }
 
+#[get("/api/channel-mappings/history")]
+async fn api_channel_mappings_history() -> impl Responder {
+    HttpResponse::Ok().json(mapping::history())
+}
+
+// 指定版本相对上一个版本的变化
+#[get("/api/channel-mappings/history/{id}")]
+async fn api_channel_mappings_revision(path: Path<u64>) -> impl Responder {
+    match mapping::revision_diff(path.into_inner()) {
+        Some(diff) => HttpResponse::Ok().json(diff),
+        None => HttpResponse::NotFound().json("Revision not found"),
+    }
+}
+
+#[post("/api/channel-mappings/history/{id}/rollback")]
+async fn api_rollback_channel_mappings(args: Data<Args>, path: Path<u64>) -> impl Responder {
+    match mapping::rollback(path.into_inner()) {
+        Ok(revision) => {
+            regenerate_xmltv(&args);
+            HttpResponse::Ok().json(serde_json::json!({ "revision": revision }))
+        }
+        Err(e) => HttpResponse::BadRequest().json(format!("Failed to roll back mappings: {}", e)),
+    }
+}
+
+// 自定义映射规则，匹配方式和字段见 mapping::MappingRule
+#[get("/api/mapping-rules")]
+async fn api_get_mapping_rules() -> impl Responder {
+    HttpResponse::Ok().json(mapping::custom_rules())
+}
+
+#[post("/api/mapping-rules")]
+async fn api_set_mapping_rules(args: Data<Args>, rules: Json<Vec<mapping::MappingRule>>) -> impl Responder {
+    match mapping::set_custom_rules(&args, rules.into_inner()) {
+        Ok(()) => {
+            regenerate_xmltv(&args);
+            HttpResponse::Ok().json("Mapping rules updated successfully")
+        }
+        Err(e) => HttpResponse::BadRequest().json(format!("Failed to update mapping rules: {}", e)),
+    }
+}
+
+// 预览映射结果：每个频道最终的名称、节目单和台标来源，以及各字段生效的规则
+async fn mapping_dry_run(args: &Args, rules: Result<RuleSet>) -> HttpResponse {
+    let rules = match rules {
+        Ok(rules) => rules,
+        Err(e) => return HttpResponse::BadRequest().json(format!("Invalid mapping rules: {}", e)),
+    };
+    match catalog::channels(args).await {
+        Ok(channels) => HttpResponse::Ok().json(rules.resolve(&channels)),
+        Err(e) => HttpResponse::InternalServerError().json(format!("Error getting channels: {}", e)),
+    }
+}
+
+#[get("/api/mapping-rules/dry-run")]
+async fn api_mapping_dry_run(args: Data<Args>) -> impl Responder {
+    mapping_dry_run(&args, Ok(RuleSet::load(&args))).await
+}
+
+// 用请求中的规则代替已保存的自定义规则进行预览，不保存
+#[post("/api/mapping-rules/dry-run")]
+async fn api_mapping_dry_run_with(args: Data<Args>, rules: Json<Vec<mapping::MappingRule>>) -> impl Responder {
+    mapping_dry_run(&args, RuleSet::with_custom(&args, rules.into_inner())).await
+}
+
 #[get("/api/cache-status")]
 async fn api_cache_status() -> impl Responder {
-    let cache_status = if let Ok(cache) = MAPPED_XMLTV_CACHE.try_lock() {
-        match *cache {
-            Some(_) => {
-                if let Ok(Some(_file_cache)) = load_xmltv_cache() {
-                    "cached_in_memory_and_file"
-                } else {
-                    "cached_in_memory_only"
-                }
-            }
-            None => {
-                if let Ok(Some(_)) = load_xmltv_cache() {
-                    "cached_in_file_only"
-                } else {
-                    "not_cached"
-                }
-            }
-        }
+    let cache_status = if xmltv_cache::exists() {
+        "cached"
     } else {
-        "cache_lock_error"
+        "not_cached"
     };
     
     HttpResponse::Ok().json(cache_status)
 }
 
-// 全局EPG获取进度状态
-
-
-
 #[post("/api/fetch-epg")]
 async fn api_fetch_epg(args: Data<Args>) -> impl Responder {
     debug!("Manual EPG fetch triggered with cumulative update and logo cache refresh");
-    
-    let scheme = "http";
-    let host = &args.bind;
-    
-    // 使用累积式EPG获取
-    match get_channels_with_cumulative_epg(&args, scheme, host).await {
-        Ok(channels) => {
-            // 1. 更新Logo缓存
-            if let Err(e) = update_logo_cache(&args, &channels).await {
-                log::warn!("Logo缓存更新失败: {}", e);
-            }
-            
-            // 2. 生成并保存XMLTV
-            let mapping = args.channel_mapping.as_ref()
-                .map(|s| parse_channel_mapping(s))
-                .unwrap_or_default();
-            
-            match to_xmltv(channels.clone(), None::<EventReader<Cursor<String>>>, &mapping) {
-                Ok(xmltv) => {
-                    // 更新缓存
-                    if let Ok(mut cache) = MAPPED_XMLTV_CACHE.try_lock() {
-                        *cache = Some(xmltv.clone());
-                    }
-                    
-                    // 保存到文件
-                    if let Err(e) = save_xmltv_cache(&xmltv) {
-                        log::error!("Failed to save XMLTV cache: {}", e);
-                    }
-                    
-                    // 统计信息
-                    let with_epg = channels.iter().filter(|ch| !ch.epg.is_empty()).count();
-                    let without_epg = channels.len() - with_epg;
-                    
-                    HttpResponse::Ok().json(format!("EPG累积获取成功: {} 个频道有节目单, {} 个频道无节目单，Logo缓存已更新", with_epg, without_epg))
-                }
-                Err(e) => {
-                    log::error!("Failed to generate XMLTV: {}", e);
-                    HttpResponse::InternalServerError().json(format!("Failed to generate XMLTV: {}", e))
-                }
+
+    let (job_id, handle) = epg_crawler::submit(args.get_ref().clone(), "manual");
+
+    // 任务完成后更新Logo缓存和XMLTV
+    let args = args.get_ref().clone();
+    tokio::spawn(async move {
+        if !matches!(handle.await, Ok(Ok(()))) {
+            return;
+        }
+        let channels = match catalog::channels(&args).await {
+            Ok(channels) => channels,
+            Err(e) => {
+                log::error!("Failed to get channels after EPG job {}: {}", job_id, e);
+                return;
             }
+        };
+
+        if let Err(e) = update_logo_cache(&args, &channels).await {
+            log::warn!("Logo缓存更新失败: {}", e);
         }
-        Err(e) => {
-            log::error!("Failed to fetch EPG cumulatively: {}", e);
-            HttpResponse::InternalServerError().json(format!("Failed to fetch EPG: {}", e))
+
+        match save_xmltv(&args, channels).await {
+            Ok(()) => log::info!("XMLTV regenerated after EPG job {}", job_id),
+            Err(e) => log::error!("Failed to generate XMLTV: {}", e),
         }
+    });
+
+    HttpResponse::Accepted().json(serde_json::json!({ "job_id": job_id }))
+}
+
+#[get("/api/epg-jobs")]
+async fn api_epg_jobs() -> impl Responder {
+    HttpResponse::Ok().json(epg_crawler::jobs())
+}
+
+#[get("/api/epg-jobs/{id}")]
+async fn api_epg_job(path: Path<u64>) -> impl Responder {
+    match epg_crawler::job(path.into_inner()) {
+        Some(job) => HttpResponse::Ok().json(job),
+        None => HttpResponse::NotFound().json("Job not found"),
     }
 }
 
@@ -1143,13 +944,15 @@ async fn api_fetch_epg(args: Data<Args>) -> impl Responder {
 async fn api_clear_logo_cache() -> impl Responder {
     debug!("Manual logo cache clear triggered");
     
-    if let Ok(mut cache) = LOGO_CACHE.try_lock() {
-        let cleared_count = cache.len();
-        cache.clear();
-        info!("Logo缓存已清空: {} 个Logo", cleared_count);
-        HttpResponse::Ok().json(format!("Logo缓存已清空: {} 个Logo", cleared_count))
-    } else {
-        HttpResponse::InternalServerError().json("无法访问Logo缓存")
+    if let Ok(mut sources) = LOGO_SOURCES.lock() {
+        sources.clear();
+    }
+    match logo_cache::clear() {
+        Ok(cleared_count) => {
+            info!("Logo缓存已清空: {} 个Logo", cleared_count);
+            HttpResponse::Ok().json(format!("Logo缓存已清空: {} 个Logo", cleared_count))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(format!("无法清空Logo缓存: {}", e)),
     }
 }
 
@@ -1159,35 +962,14 @@ async fn api_regenerate_xmltv(args: Data<Args>) -> impl Responder {
     
     let scheme = "http";
     let host = &args.bind;
-    let extra_xml = match &args.extra_xmltv {
-        Some(u) => parse_extra_xml(u).await.ok(),
-        None => None,
-    };
     
-    // 使用命令行映射（如果有）
-    let cli_mapping = args.channel_mapping.as_ref()
-        .map(|s| parse_channel_mapping(s))
-        .unwrap_or_default();
-    
-    match get_channels_with_cumulative_epg(&args, scheme, host).await {
+    match get_channels_with_cumulative_epg(&args, scheme, host, "regenerate").await {
         Ok(channels) => {
-            let channels_clone = channels.clone();
-            // 直接使用 to_xmltv 函数，它会自动处理 CHANNEL_MAPPINGS
-            match to_xmltv(channels_clone, extra_xml, &cli_mapping) {
-                Ok(xmltv) => {
-                    // 更新缓存
-                    if let Ok(mut cache) = MAPPED_XMLTV_CACHE.try_lock() {
-                        *cache = Some(xmltv.clone());
-                    }
-                    
-                    // 保存到文件
-                    if let Err(e) = save_xmltv_cache(&xmltv) {
-                        log::error!("Failed to save XMLTV cache: {}", e);
-                        return HttpResponse::InternalServerError().json("Failed to save cache");
-                    }
-                    
+            let channel_count = channels.len();
+            // to_xmltv 会自动处理 CHANNEL_MAPPINGS 和命令行映射
+            match save_xmltv(&args, channels).await {
+                Ok(()) => {
                     // 统计信息
-                    let channel_count = channels.len();
                     let mapped_count = if let Ok(m) = CHANNEL_MAPPINGS.try_lock() {
                         m.len()
                     } else {
@@ -1211,6 +993,32 @@ async fn api_regenerate_xmltv(args: Data<Args>) -> impl Responder {
     }
 }
 
+// 按规范化名称把同一频道的不同清晰度分组，建议映射到节目单最全的版本
+#[get("/api/channel-mappings/suggest")]
+async fn api_suggest_channel_mappings(args: Data<Args>) -> impl Responder {
+    let channels = match catalog::channels(&args).await {
+        Ok(channels) => channels,
+        Err(e) => return HttpResponse::InternalServerError().json(format!("Error getting channels: {}", e)),
+    };
+    let suggestions = match epg_store::EPG_STORE.lock() {
+        Ok(store) => mapping::suggest(&channels, |id| store.coverage(id)),
+        Err(_) => return HttpResponse::InternalServerError().json("Failed to read EPG store"),
+    };
+    HttpResponse::Ok().json(suggestions)
+}
+
+// 批量接受建议，与已有映射合并
+#[post("/api/channel-mappings/suggest")]
+async fn api_accept_channel_mappings(args: Data<Args>, req: Json<MappingRequest>) -> impl Responder {
+    match mapping::add_mappings("suggest", req.mappings.iter().map(|m| (m.from_id, m.to_id))) {
+        Ok(added) => {
+            regenerate_xmltv(&args);
+            HttpResponse::Ok().json(format!("{} mappings added", added))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(format!("Failed to update mappings: {}", e)),
+    }
+}
+
 #[get("/api/channel-mappings")]
 async fn api_get_channel_mappings() -> impl Responder {
     debug!("Getting channel mappings");
@@ -1225,28 +1033,36 @@ async fn api_get_channel_mappings() -> impl Responder {
     }
 }
 
+#[derive(Deserialize)]
+struct EpgRangeQuery {
+    from: Option<String>,
+    to: Option<String>,
+}
+
 #[get("/api/channel/{id}/epg")]
-async fn api_channel_epg(_args: Data<Args>, _req: HttpRequest, path: Path<u64>) -> impl Responder {
+async fn api_channel_epg(
+    _args: Data<Args>,
+    _req: HttpRequest,
+    path: Path<u64>,
+    query: Query<EpgRangeQuery>,
+) -> impl Responder {
     debug!("Get channel EPG from XMLTV cache");
     let channel_id = path.into_inner();
-    
-    // 检查是否有映射
-    let effective_channel_id = if let Ok(mappings) = CHANNEL_MAPPINGS.try_lock() {
-        mappings.get(&channel_id).copied().unwrap_or(channel_id)
-    } else {
-        channel_id
-    };
-    
-    debug!("Looking for EPG data for channel ID {} (effective: {})", channel_id, effective_channel_id);
-    
-    // 从XMLTV缓存中获取EPG数据
+    let from = query.from.as_deref().and_then(epg_query::parse_time);
+    let to = query.to.as_deref().and_then(epg_query::parse_time);
+
+    // 从XMLTV缓存中获取EPG数据，缓存中已按映射规则替换为来源频道的节目单
     match get_epg_from_xmltv_cache().await {
         Ok(epg_data) => {
-            if let Some(programs) = epg_data.get(&effective_channel_id) {
-                debug!("Found {} programs for channel {}", programs.len(), effective_channel_id);
+            if let Some(programs) = epg_data.get(&channel_id) {
+                debug!("Found {} programs for channel {}", programs.len(), channel_id);
+                let programs = programs
+                    .iter()
+                    .filter(|p| epg_query::in_range(p, from, to))
+                    .collect::<Vec<_>>();
                 HttpResponse::Ok().json(programs)
             } else {
-                debug!("No EPG data found for channel {}", effective_channel_id);
+                debug!("No EPG data found for channel {}", channel_id);
                 HttpResponse::Ok().json(Vec::<Program>::new())
             }
         }
@@ -1257,6 +1073,92 @@ async fn api_channel_epg(_args: Data<Args>, _req: HttpRequest, path: Path<u64>)
     }
 }
 
+// 所有频道正在播放和下一个节目
+#[get("/api/now")]
+async fn api_now(args: Data<Args>) -> impl Responder {
+    let channels = match catalog::channels(&args).await {
+        Ok(channels) => channels,
+        Err(e) => return HttpResponse::InternalServerError().json(format!("Error getting channels: {}", e)),
+    };
+    let epg_data = match get_epg_from_xmltv_cache().await {
+        Ok(epg_data) => epg_data,
+        Err(e) => return HttpResponse::InternalServerError().json(format!("Error getting EPG: {}", e)),
+    };
+
+    let now = chrono::Utc::now().timestamp_millis();
+    let response = channels
+        .iter()
+        .map(|c| {
+            let programs = epg_data
+                .get(&c.id)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            let (current, next) = epg_query::now_next(programs, now);
+            serde_json::json!({
+                "id": c.id,
+                "name": c.name,
+                "now": current,
+                "next": next,
+            })
+        })
+        .collect::<Vec<_>>();
+    HttpResponse::Ok().json(response)
+}
+
+#[derive(Deserialize)]
+struct EpgSearchQuery {
+    q: String,
+    from: Option<String>,
+    to: Option<String>,
+    limit: Option<usize>,
+}
+
+// 按标题、简介或拼音首字母搜索所有频道的节目
+#[get("/api/epg/search")]
+async fn api_epg_search(args: Data<Args>, query: Query<EpgSearchQuery>) -> impl Responder {
+    let keyword = query.q.trim();
+    if keyword.is_empty() {
+        return HttpResponse::BadRequest().json("Missing search keyword");
+    }
+    let from = query.from.as_deref().and_then(epg_query::parse_time);
+    let to = query.to.as_deref().and_then(epg_query::parse_time);
+    let limit = query.limit.unwrap_or(200).min(1000);
+
+    let channels = match catalog::channels(&args).await {
+        Ok(channels) => channels,
+        Err(e) => return HttpResponse::InternalServerError().json(format!("Error getting channels: {}", e)),
+    };
+    let epg_data = match get_epg_from_xmltv_cache().await {
+        Ok(epg_data) => epg_data,
+        Err(e) => return HttpResponse::InternalServerError().json(format!("Error getting EPG: {}", e)),
+    };
+
+    let mut results = channels
+        .iter()
+        .flat_map(|c| {
+            epg_data
+                .get(&c.id)
+                .into_iter()
+                .flatten()
+                .filter(|p| epg_query::in_range(p, from, to) && epg_query::matches(p, keyword))
+                .map(move |p| (c, p))
+        })
+        .collect::<Vec<_>>();
+    results.sort_by_key(|(c, p)| (p.start, c.id));
+    let response = results
+        .into_iter()
+        .take(limit)
+        .map(|(c, p)| {
+            serde_json::json!({
+                "channel_id": c.id,
+                "channel_name": c.name,
+                "programme": p,
+            })
+        })
+        .collect::<Vec<_>>();
+    HttpResponse::Ok().json(response)
+}
+
 #[get("/api/channels")]
 async fn api_channels(args: Data<Args>, req: HttpRequest) -> impl Responder {
     debug!("Get channels");
@@ -1264,11 +1166,51 @@ async fn api_channels(args: Data<Args>, req: HttpRequest) -> impl Responder {
     let host = req.connection_info().host().to_owned();
     
     match get_channels(&args, true, &scheme, &host).await {
-        Ok(channels) => HttpResponse::Ok().json(channels),
+        Ok(channels) => {
+            // 按频道表排序，使用显示名称、分组和频道号
+            let rules = RuleSet::load(&args);
+            let arranged = lineup::arrange(&rules, &channels)
+                .into_iter()
+                .map(|(c, r)| Channel {
+                    name: r.name,
+                    category: r.group.clone().unwrap_or_else(|| c.category.clone()),
+                    groups: r.group.map_or_else(|| c.groups.clone(), |g| vec![g]),
+                    chno: r.chno,
+                    ..c.clone()
+                })
+                .collect::<Vec<_>>();
+            HttpResponse::Ok().json(arranged)
+        }
         Err(e) => HttpResponse::InternalServerError().json(format!("Error getting channels: {}", e)),
     }
 }
 
+// 频道表：保存的条目（顺序即频道顺序）和上游的全部频道，供编辑页面使用
+#[get("/api/lineup")]
+async fn api_get_lineup(args: Data<Args>, req: HttpRequest) -> impl Responder {
+    let scheme = req.connection_info().scheme().to_owned();
+    let host = req.connection_info().host().to_owned();
+    let channels = get_channels(&args, false, &scheme, &host).await.unwrap_or_default();
+    HttpResponse::Ok().json(serde_json::json!({
+        "entries": lineup::entries(),
+        "channels": channels
+            .iter()
+            .map(|c| serde_json::json!({ "id": c.id, "name": c.name, "category": c.category }))
+            .collect::<Vec<_>>(),
+    }))
+}
+
+#[post("/api/lineup")]
+async fn api_set_lineup(args: Data<Args>, entries: Json<Vec<lineup::LineupEntry>>) -> impl Responder {
+    match lineup::set(entries.into_inner()) {
+        Ok(()) => {
+            regenerate_xmltv(&args);
+            HttpResponse::Ok().json("Lineup updated successfully")
+        }
+        Err(e) => HttpResponse::InternalServerError().json(format!("Failed to save lineup: {}", e)),
+    }
+}
+
 #[get("/api/channels-with-epg")]
 async fn api_channels_with_epg(args: Data<Args>, req: HttpRequest) -> impl Responder {
     debug!("Get channels with EPG from cache");
@@ -1291,108 +1233,469 @@ async fn index() -> impl Responder {
 async fn xmltv_route(args: Data<Args>, req: HttpRequest) -> Result<HttpResponse, Box<dyn std::error::Error>> {
     debug!("Get EPG - requesting fresh EPG data");
     
-    // /xmltv 端点始终获取最新的EPG数据，不使用缓存
+    // /xmltv 端点直接从节目单存储生成，不使用XMLTV缓存
     let scheme = req.connection_info().scheme().to_owned();
     let host = req.connection_info().host().to_owned();
-    let extra_xml = match &args.extra_xmltv {
-        Some(u) => parse_extra_xml(u).await.ok(),
-        None => None,
-    };
-    
-    let mapping = args.channel_mapping.as_ref()
-        .map(|s| parse_channel_mapping(s))
-        .unwrap_or_default();
     
-    // 实时获取最新的EPG数据（不使用XMLTV缓存）
-    let channels = get_channels(&args, true, &scheme, &host).await?;
-    let xml = to_xmltv_with_mappings(channels, extra_xml, &mapping).await?;
-    
-    Ok(HttpResponse::Ok().content_type("text/xml").body(xml))
+    // 从节目单存储读取EPG数据（不使用XMLTV缓存），边生成边输出
+    let channels = catalog::channels(&args).await?;
+    let guide = Guide::new(&args, &channels).await;
+    let rules = RuleSet::load(&args);
+    let base = format!("{}://{}", scheme, host);
+    let dummy = DummyEpg::from_args(&args);
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Bytes>(4);
+    tokio::task::spawn_blocking(move || {
+        let out = xmltv_cache::ChunkWriter::new(tx);
+        if let Err(e) = to_xmltv(out, None, &channels, &guide, &rules, &base, dummy.as_ref()) {
+            debug!("XMLTV streaming stopped: {}", e);
+        }
+    });
+    let body = stream! {
+        while let Some(chunk) = rx.recv().await {
+            yield Ok::<_, actix_web::Error>(chunk);
+        }
+    };
+
+    Ok(HttpResponse::Ok().content_type("text/xml").streaming(body))
+}
+
+// 客户端是否接受 gzip
+fn accepts_gzip(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|e| e.trim().split(';').next() == Some("gzip")))
+}
+
+fn empty_xmltv() -> HttpResponse {
+    // 如果没有缓存，返回空XMLTV
+    warn!("No cached EPG data available, returning empty XMLTV");
+    let empty_xmltv = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE tv SYSTEM "xmltv.dtd">
+<tv generator-info-name="iptv-proxy">
+</tv>"#;
+    HttpResponse::Ok()
+        .content_type("text/xml")
+        .body(empty_xmltv.to_string())
 }
 
 #[get("/epg.xml")]
-async fn epg_xml_cached() -> impl Responder {
+async fn epg_xml_cached(req: HttpRequest) -> impl Responder {
     debug!("Get cached EPG XML");
-    
-    // 首先尝试从内存缓存获取
-    if let Ok(cache) = MAPPED_XMLTV_CACHE.try_lock() {
-        if let Some(ref cached_xmltv) = *cache {
-            debug!("Returning cached XMLTV from memory");
-            return HttpResponse::Ok()
-                .content_type("text/xml")
-                .append_header(("Cache-Control", "public, max-age=21600")) // 6小时
-                .body(cached_xmltv.clone());
+
+    // 支持 gzip 时直接返回预先压缩的文件
+    let file = if accepts_gzip(&req) {
+        xmltv_cache::open(true).or_else(|_| xmltv_cache::open(false))
+    } else {
+        xmltv_cache::open(false)
+    };
+    match file {
+        Ok(file) => {
+            let mut res = file.into_response(&req);
+            res.headers_mut().insert(header::VARY, header::HeaderValue::from_static("Accept-Encoding"));
+            res.headers_mut().insert(
+                header::CACHE_CONTROL,
+                header::HeaderValue::from_static("public, max-age=21600"), // 6小时
+            );
+            res
         }
+        Err(_) => empty_xmltv(),
     }
-    
-    // 如果内存没有，尝试从文件缓存加载
-    match load_xmltv_cache() {
-        Ok(Some(file_xmltv)) => {
-            debug!("Loaded XMLTV from file cache");
-            // 更新内存缓存
-            if let Ok(mut cache) = MAPPED_XMLTV_CACHE.try_lock() {
-                *cache = Some(file_xmltv.clone());
+}
+
+#[get("/epg.xml.gz")]
+async fn epg_xml_gz(req: HttpRequest) -> impl Responder {
+    debug!("Get cached EPG XML (gzip)");
+
+    match xmltv_cache::open_gz() {
+        Ok(file) => {
+            let mut res = file.into_response(&req);
+            res.headers_mut().insert(
+                header::CACHE_CONTROL,
+                header::HeaderValue::from_static("public, max-age=21600"), // 6小时
+            );
+            res
+        }
+        Err(_) => HttpResponse::NotFound().body("EPG cache not generated yet"),
+    }
+}
+
+// 从来源获取台标：额外播放列表的频道使用 tvg-logo，其余从上游获取
+async fn fetch_logo(args: &Args, channel_id: &str) -> Result<Vec<u8>> {
+    let extra_logo = match channel_id.parse::<u64>() {
+        Ok(id) if extra_playlist::is_extra_id(id) => catalog::channel(args, id)
+            .await
+            .ok()
+            .flatten()
+            .and_then(|c| c.extra?.get("tvg-logo").cloned()),
+        _ => None,
+    };
+    match extra_logo {
+        Some(url) => extra_playlist::fetch_logo(&url).await,
+        None => get_icon(args, channel_id).await,
+    }
+}
+
+// 远程台标来源，各自按键缓存
+#[derive(Clone)]
+enum RemoteLogo {
+    // 频道自身的台标
+    Channel(String),
+    // --logo-url-template 生成的地址
+    Url(String),
+}
+
+impl RemoteLogo {
+    fn key(&self) -> String {
+        match self {
+            RemoteLogo::Channel(id) => id.clone(),
+            RemoteLogo::Url(url) => format!("url_{:x}", md5::compute(url)),
+        }
+    }
+
+    async fn fetch(&self, args: &Args) -> Result<Vec<u8>> {
+        match self {
+            RemoteLogo::Channel(id) => fetch_logo(args, id).await,
+            RemoteLogo::Url(url) => {
+                let data = extra_playlist::fetch_logo(url).await?;
+                if logo_cache::image_type(&data).is_none() {
+                    return Err(anyhow::anyhow!("Not an image: {}", url));
+                }
+                Ok(data)
             }
-            HttpResponse::Ok()
-                .content_type("text/xml")
-                .append_header(("Cache-Control", "public, max-age=21600")) // 6小时
-                .body(file_xmltv)
-        }
-        Ok(None) => {
-            // 如果没有缓存，返回空XMLTV
-            warn!("No cached EPG data available, returning empty XMLTV");
-            let empty_xmltv = r#"<?xml version="1.0" encoding="UTF-8"?>
-<!DOCTYPE tv SYSTEM "xmltv.dtd">
-<tv generator-info-name="iptv-proxy">
-</tv>"#;
-            HttpResponse::Ok()
-                .content_type("text/xml")
-                .body(empty_xmltv.to_string())
+        }
+    }
+}
+
+// 经缓存获取远程台标，过期的先返回，在后台重新获取
+async fn cached_logo(args: &Data<Args>, remote: RemoteLogo) -> Option<Arc<logo_cache::Logo>> {
+    let key = remote.key();
+    match logo_cache::lookup(&key) {
+        logo_cache::Lookup::Fresh(cached) => Some(cached),
+        logo_cache::Lookup::Stale(cached) => {
+            if logo_cache::begin_refresh(&key) {
+                let args = args.clone();
+                tokio::spawn(async move {
+                    match remote.fetch(&args).await.and_then(|icon| logo_cache::store(&key, icon)) {
+                        Ok(_) => debug!("Refreshed logo {}", key),
+                        Err(e) => debug!("Failed to refresh logo {}: {}", key, e),
+                    }
+                    logo_cache::end_refresh(&key);
+                });
+            }
+            Some(cached)
+        }
+        logo_cache::Lookup::Missing => {
+            if logo_cache::recently_missing(&key) {
+                return None;
+            }
+            debug!("Get logo {} from source", key);
+            match remote.fetch(args).await.and_then(|icon| logo_cache::store(&key, icon)) {
+                Ok(cached) => Some(cached),
+                Err(e) => {
+                    debug!("Failed to get logo {}: {}", key, e);
+                    logo_cache::mark_missing(&key);
+                    None
+                }
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct LogoQuery {
+    // 为 false 时没有台标返回 404，而不是占位图
+    placeholder: Option<bool>,
+    // 缩放到的宽度，高度按 --logo-size 的比例
+    w: Option<u32>,
+}
+
+// 按 --logo-size/--logo-format 和 ?w= 处理台标，结果缓存在内存中
+async fn normalize_logo(args: &Args, original: Arc<logo_cache::Logo>, width: Option<u32>) -> Arc<logo_cache::Logo> {
+    let configured = args.logo_size.as_deref().and_then(|s| logo_image::parse_size(s).ok());
+    let size = match width {
+        Some(w) => Some(logo_image::variant_size(w, configured)),
+        None => configured,
+    };
+    let format = args.logo_format.parse().unwrap_or(LogoFormat::Original);
+    if !logo_image::available() || (size.is_none() && format == LogoFormat::Original) {
+        return original;
+    }
+    let (w, h) = size.unwrap_or((0, 0));
+    let key = format!("variant:{}:{}x{}:{}", original.meta.hash, w, h, args.logo_format);
+    if let Some(variant) = logo_cache::variant(&key) {
+        return variant;
+    }
+    let data = original.data.clone();
+    match tokio::task::spawn_blocking(move || logo_image::process(&data, size, format)).await {
+        Ok(Ok(Some((data, content_type)))) => {
+            let mut processed = logo_cache::Logo::new(data);
+            processed.meta.content_type = content_type.to_string();
+            logo_cache::store_variant(&key, processed)
+        }
+        Ok(Ok(None)) => original,
+        Ok(Err(e)) => {
+            debug!("Failed to process logo {}: {}", original.meta.hash, e);
+            original
         }
         Err(e) => {
-            error!("Failed to load XMLTV cache: {}", e);
-            HttpResponse::InternalServerError().body(format!("Failed to load EPG cache: {}", e))
+            debug!("Logo processing task failed: {}", e);
+            original
         }
     }
 }
 
-async fn parse_extra_playlist(url: &str) -> Result<String> {
-    let client = Client::builder().build()?;
-    let url = reqwest::Url::parse(url)?;
-    let response = client.get(url).send().await?.error_for_status()?;
-    Ok(response
-        .text()
-        .await?
-        .strip_prefix("#EXTM3U")
-        .map_or(String::from(""), |s| s.to_owned()))
+// 解析出的台标来源
+#[derive(Clone)]
+enum LogoSource {
+    Local(std::path::PathBuf),
+    Remote(RemoteLogo),
+    // 没有台标，占位图上的名称
+    Placeholder(String),
+}
+
+// 频道ID -> (解析时间, 台标来源)，期间不再加载频道列表和映射规则
+static LOGO_SOURCES: LazyLock<Mutex<HashMap<String, (std::time::Instant, LogoSource)>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+const LOGO_SOURCE_TTL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+fn remembered_logo_source(channel_id: &str) -> Option<LogoSource> {
+    let sources = LOGO_SOURCES.lock().ok()?;
+    let (at, source) = sources.get(channel_id)?;
+    (at.elapsed() < LOGO_SOURCE_TTL).then(|| source.clone())
+}
+
+fn remember_logo_source(channel_id: &str, source: LogoSource) {
+    if let Ok(mut sources) = LOGO_SOURCES.lock() {
+        sources.insert(channel_id.to_string(), (std::time::Instant::now(), source));
+    }
+}
+
+// 查找频道台标，没有时返回占位图上的名称；先用记住的来源，失效时再重新解析
+async fn find_logo(args: &Data<Args>, channel_id: &str) -> Result<Arc<logo_cache::Logo>, String> {
+    match remembered_logo_source(channel_id) {
+        Some(LogoSource::Local(path)) => {
+            if let Ok(data) = std::fs::read(&path) {
+                return Ok(Arc::new(logo_cache::Logo::new(data)));
+            }
+        }
+        Some(LogoSource::Remote(remote)) => {
+            if let Some(cached) = cached_logo(args, remote).await {
+                return Ok(cached);
+            }
+        }
+        Some(LogoSource::Placeholder(name)) => return Err(name),
+        None => {}
+    }
+
+    // 依次尝试：--logo-dir、--logo-url-template、映射的台标来源频道、频道本身
+    let channels = catalog::channels(args).await.unwrap_or_default();
+    let channel = channel_id.parse::<u64>().ok().and_then(|id| channels.iter().find(|c| c.id == id));
+    let resolved = channel.map(|c| RuleSet::load(args).resolve_one(c, &channels));
+    let lineup_name = channel.and_then(|c| lineup::entries().into_iter().find(|e| e.id == c.id)?.name);
+    let names = logo_sources::candidate_names(
+        lineup_name
+            .as_deref()
+            .into_iter()
+            .chain(resolved.as_ref().map(|r| r.name.as_str()))
+            .chain(channel.map(|c| c.name.as_str())),
+    );
+    // 只记住存在的频道，避免任意ID占用内存
+    let remember = |source: LogoSource| {
+        if channel.is_some() {
+            remember_logo_source(channel_id, source);
+        }
+    };
+
+    if let Some((path, data)) = args.logo_dir.as_deref().and_then(|dir| logo_sources::local(dir, &names)) {
+        remember(LogoSource::Local(path));
+        return Ok(Arc::new(logo_cache::Logo::new(data)));
+    }
+    let mut remotes = Vec::new();
+    if let Some(template) = &args.logo_url_template {
+        remotes.extend(logo_sources::template_urls(template, channel_id, &names).into_iter().map(RemoteLogo::Url));
+    }
+    if let Some(r) = resolved.as_ref().filter(|r| r.logo_id != r.id) {
+        remotes.push(RemoteLogo::Channel(r.logo_id.to_string()));
+    }
+    remotes.push(RemoteLogo::Channel(channel_id.to_string()));
+    for remote in remotes {
+        if let Some(cached) = cached_logo(args, remote.clone()).await {
+            remember(LogoSource::Remote(remote));
+            return Ok(cached);
+        }
+    }
+    let name = names.first().cloned().unwrap_or_else(|| channel_id.to_string());
+    remember(LogoSource::Placeholder(name.clone()));
+    Err(name)
 }
 
+// 上传的台标优先，其次按 find_logo 查找，都没有时生成占位图
 #[get("/logo/{id}.png")]
-async fn logo(args: Data<Args>, path: Path<String>) -> impl Responder {
+async fn logo(args: Data<Args>, path: Path<String>, query: Query<LogoQuery>, req: HttpRequest) -> impl Responder {
     let channel_id = path.into_inner();
-    
-    // 先检查缓存
-    if let Ok(cache) = LOGO_CACHE.try_lock() {
-        if let Some(cached_logo) = cache.get(&channel_id) {
-            debug!("Using cached logo for channel {}", channel_id);
-            return HttpResponse::Ok().content_type("image/png").body(cached_logo.clone());
+    if !logo_cache::valid_key(&channel_id) {
+        return HttpResponse::NotFound().body("Invalid logo id");
+    }
+
+    let found = match logo_sources::custom(&channel_id) {
+        Some(data) => Ok(Arc::new(logo_cache::Logo::new(data))),
+        None => find_logo(&args, &channel_id).await,
+    };
+    let (logo, max_age) = match found {
+        Ok(logo) => (logo, "public, max-age=86400"),
+        Err(_) if query.placeholder == Some(false) => {
+            return HttpResponse::NotFound().body(format!("Logo not found for {}", channel_id));
         }
+        // 占位图只缓存在内存中，有了台标后尽快替换
+        Err(name) => {
+            let key = format!("placeholder:{:x}", md5::compute(&name));
+            let placeholder = match logo_cache::variant(&key) {
+                Some(placeholder) => placeholder,
+                None => match logo_placeholder::render(&name) {
+                    Ok(png) => logo_cache::store_variant(&key, logo_cache::Logo::new(png)),
+                    Err(e) => {
+                        return HttpResponse::InternalServerError().body(format!("Failed to draw placeholder: {}", e));
+                    }
+                },
+            };
+            (placeholder, "public, max-age=3600")
+        }
+    };
+    let logo = normalize_logo(&args, logo, query.w).await;
+
+    let etag = format!("\"{}\"", logo.meta.hash);
+    let not_modified = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|t| t.trim() == etag || t.trim() == "*"));
+    let mut res = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    res.insert_header((header::ETAG, etag))
+        .insert_header((header::CACHE_CONTROL, max_age));
+    if not_modified {
+        return res.finish();
     }
-    
-    debug!("Get logo from server for channel {}", channel_id);
-    match get_icon(&args, &channel_id).await {
-        Ok(icon) => {
-            // 将获取的logo存入缓存
-            if let Ok(mut cache) = LOGO_CACHE.try_lock() {
-                cache.insert(channel_id.clone(), icon.clone());
+    res.content_type(logo.meta.content_type.as_str()).body(logo.data.clone())
+}
+
+// 上传频道台标，优先于其他来源
+#[post("/api/logo/{id}")]
+async fn api_upload_logo(path: Path<String>, body: Bytes) -> impl Responder {
+    let channel_id = path.into_inner();
+    if !logo_cache::valid_key(&channel_id) {
+        return HttpResponse::BadRequest().json("Invalid logo id");
+    }
+    match logo_sources::set_custom(&channel_id, &body) {
+        Ok(()) => {
+            info!("Uploaded logo for channel {}", channel_id);
+            HttpResponse::Ok().json("Logo uploaded")
+        }
+        Err(e) => HttpResponse::BadRequest().json(format!("Failed to save logo: {}", e)),
+    }
+}
+
+#[delete("/api/logo/{id}")]
+async fn api_delete_logo(path: Path<String>) -> impl Responder {
+    let channel_id = path.into_inner();
+    if !logo_cache::valid_key(&channel_id) {
+        return HttpResponse::BadRequest().json("Invalid logo id");
+    }
+    match logo_sources::remove_custom(&channel_id) {
+        Ok(true) => HttpResponse::Ok().json("Logo removed"),
+        Ok(false) => HttpResponse::NotFound().json("No uploaded logo"),
+        Err(e) => HttpResponse::InternalServerError().json(format!("Failed to remove logo: {}", e)),
+    }
+}
+
+// 播放列表中的一个频道，M3U 和 TXT 共用
+struct PlaylistEntry {
+    id: u64,
+    name: String,
+    groups: Vec<String>,
+    chno: u64,
+    catch_up: String,
+    url: String,
+}
+
+// stream 为空时按 --rtsp-proxy/--udp-proxy 生成播放地址
+fn playlist_entries(args: &Args, ch: &[Channel], stream: Option<StreamMode>, base: &str) -> Vec<PlaylistEntry> {
+    let rules = RuleSet::load(args);
+    let catchup_style = args.catchup_style.parse().unwrap_or(CatchupStyle::Append);
+
+    lineup::arrange(&rules, ch)
+        .into_iter()
+        .map(|(c, r)| {
+            // 映射规则或频道表设置的分组替换分类规则的结果
+            let groups = match r.group {
+                Some(group) => vec![group],
+                None if c.groups.is_empty() => vec![c.category.clone()],
+                None => c.groups.clone(),
+            };
+            // 代理模式下使用按频道ID的稳定地址，上游地址变化也不影响播放列表；
+            // 额外播放列表的频道只在 --extra-playlist-proxy 且为 http(s) 地址时经本服务转发
+            let stream_url = match stream {
+                _ if c.extra.is_some() => {
+                    if args.extra_playlist_proxy && stream != Some(StreamMode::Direct) && is_http_url(&c.rtsp) {
+                        format!("{}/channel/{}", base, c.id)
+                    } else {
+                        c.rtsp.clone()
+                    }
+                }
+                None if args.rtsp_proxy || (args.udp_proxy && c.igmp.is_some()) => {
+                    format!("{}/channel/{}", base, c.id)
+                }
+                Some(StreamMode::RtspProxy) => format!("{}/channel/{}/rtsp", base, c.id),
+                Some(StreamMode::UdpProxy) if c.igmp.is_some() => format!("{}/channel/{}/udp", base, c.id),
+                Some(StreamMode::Hls) => format!("{}/channel/{}/hls/index.m3u8", base, c.id),
+                _ => c.rtsp.clone(),
+            };
+            let (catch_up, url) = catchup::playlist_entry(catchup_style, c, base, &stream_url);
+            // 直连的额外频道保留原有的回看属性
+            let catch_up = match &c.extra {
+                Some(attrs) if url == c.rtsp => extra_playlist::catchup_attrs(attrs),
+                _ => catch_up,
+            };
+
+            PlaylistEntry {
+                id: c.id,
+                name: r.name,
+                groups,
+                chno: r.chno.map_or(c.id, u64::from),
+                catch_up,
+                url,
             }
-            HttpResponse::Ok().content_type("image/png").body(icon)
-        },
-        Err(e) => {
-            debug!("Failed to get logo for channel {}: {}", channel_id, e);
-            HttpResponse::NotFound().body(format!("Error getting logo: {}", e))
-        }
-    }
+        })
+        .collect()
+}
+
+// 按播放列表配置筛选和排序，频道取上游原始地址，由配置决定是否代理
+async fn profile_entries(args: &Args, profile: &Profile, base: &str) -> Result<Vec<PlaylistEntry>> {
+    let ch = catalog::channels(args).await?;
+    let filter = profile.filter()?;
+    let mut entries = playlist_entries(args, &ch, profile.stream, base);
+    entries.retain(|e| filter.keep(e.id, &e.name, &e.groups));
+    profile.order.sort(&mut entries, |e| (e.chno, e.name.clone()));
+    Ok(entries)
+}
+
+// 属于多个分组时 group-title 用 ; 分隔
+fn to_m3u(header: &str, entries: &[PlaylistEntry], base: &str) -> String {
+    format!("#EXTM3U{}\n", header)
+        + &entries
+            .iter()
+            .map(|e| {
+                format!(
+                    r#"#EXTINF:-1 tvg-id="{0}" tvg-name="{1}" tvg-chno="{5}"{3} tvg-logo="{4}/logo/{0}.png" group-title="{2}",{1}"#,
+                    e.id, e.name, e.groups.join(";"), e.catch_up, base, e.chno
+                ) + "\n" + &e.url
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
 }
 
 #[get("/playlist")]
@@ -1411,47 +1714,8 @@ async fn playlist(args: Data<Args>, req: HttpRequest) -> impl Responder {
             }
         }
         Ok(ch) => {
-            // 解析频道映射配置
-            let mapping = args.channel_mapping.as_ref()
-                .map(|s| parse_channel_mapping(s))
-                .unwrap_or_default();
-                
-            let playlist = String::from("#EXTM3U\n")
-                + &ch
-                    .iter()
-                    .map(|c| {
-                        let group = if c.name.contains("超高清") || c.name.contains("4K") {
-                            "超清频道"
-                        } else if c.name.contains("高清") || c.name.contains("超清") || c.name.contains("卫视") {
-                            "高清频道"
-                        } else {
-                            "普通频道"
-                        };
-                        let catch_up = format!(r#" catchup="append" catchup-source="{}?playseek=${{(b)yyyyMMddHHmmss}}-${{(e)yyyyMMddHHmmss}}" "#,
-                            c.igmp.as_ref().map(|_| &c.rtsp).unwrap_or(&"".to_string()));
-                        
-                        // 查找映射的频道ID用于 logo 和 EPG
-                        let mapped_id = if let Ok(mappings) = CHANNEL_MAPPINGS.try_lock() {
-                            mappings.get(&c.id).copied().unwrap_or_else(|| {
-                                // 如果没有前端映射，尝试使用传统的名称映射
-                                find_mapped_channel_id(&c.name, &ch, &mapping)
-                            })
-                        } else {
-                            find_mapped_channel_id(&c.name, &ch, &mapping)
-                        };
-                        let logo_id = if mapped_id != 0 { mapped_id } else { c.id };
-                        
-                        format!(
-                            r#"#EXTINF:-1 tvg-id="{0}" tvg-name="{1}" tvg-chno="{0}"{3}tvg-logo="{4}://{5}/logo/{6}.png" group-title="{2}",{1}"#,
-                            c.id, c.name, group, catch_up, scheme, host, logo_id
-                        ) + "\n" + if args.udp_proxy { c.igmp.as_ref().unwrap_or(&c.rtsp) } else { &c.rtsp }
-                    })
-                    .collect::<Vec<_>>()
-                    .join("\n")
-                + &match &args.extra_playlist {
-                    Some(u) => parse_extra_playlist(u).await.unwrap_or(String::from("")),
-                    None => String::from(""),
-                };
+            let base = format!("{}://{}", scheme, host);
+            let playlist = to_m3u("", &playlist_entries(&args, &ch, None, &base), &base);
             if let Ok(mut old_playlist) = OLD_PLAYLIST.try_lock() {
                 *old_playlist = Some(playlist.clone());
             }
@@ -1462,6 +1726,275 @@ async fn playlist(args: Data<Args>, req: HttpRequest) -> impl Responder {
     }
 }
 
+// 按名称的播放列表配置，节目单地址指向对应的 /epg/{name}.xml
+#[get("/playlist/{profile}")]
+async fn playlist_profile(args: Data<Args>, path: Path<String>, req: HttpRequest) -> impl Responder {
+    let name = path.into_inner();
+    debug!("Get playlist of profile {}", name);
+    let Some(profile) = profile::get(&name) else {
+        return HttpResponse::NotFound().body(format!("Profile {} not found", name));
+    };
+    let base = profile.base(req.connection_info().scheme(), req.connection_info().host());
+    match profile_entries(&args, &profile, &base).await {
+        Ok(entries) => HttpResponse::Ok()
+            .content_type("application/vnd.apple.mpegurl")
+            .body(to_m3u(&format!(r#" x-tvg-url="{}/epg/{}.xml""#, base, name), &entries, &base)),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Error getting channels: {}", e)),
+    }
+}
+
+// 播放列表配置对应的节目单，取自缓存的 XMLTV，只包含配置中的频道
+#[get("/epg/{profile}.xml")]
+async fn epg_profile(args: Data<Args>, path: Path<String>, req: HttpRequest) -> impl Responder {
+    let name = path.into_inner();
+    debug!("Get EPG of profile {}", name);
+    let Some(profile) = profile::get(&name) else {
+        return HttpResponse::NotFound().body(format!("Profile {} not found", name));
+    };
+    let Ok(epg) = xmltv_cache::epg() else {
+        return empty_xmltv();
+    };
+    let base = profile.base(req.connection_info().scheme(), req.connection_info().host());
+    let entries = match profile_entries(&args, &profile, &base).await {
+        Ok(entries) => entries,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("Error getting channels: {}", e)),
+    };
+    let mut out = Vec::new();
+    match profile_xmltv(&mut out, &entries, &epg, &base) {
+        Ok(()) => HttpResponse::Ok().content_type("text/xml").body(out),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Error generating EPG: {}", e)),
+    }
+}
+
+fn profile_xmltv<W: Write>(
+    out: W,
+    entries: &[PlaylistEntry],
+    epg: &HashMap<u64, Vec<Program>>,
+    base: &str,
+) -> Result<()> {
+    let mut writer = EmitterConfig::new()
+        .perform_indent(false)
+        .create_writer(out);
+    writer.write(
+        XmlWriteEvent::start_element("tv")
+            .attr("generator-info-name", "iptv-proxy")
+            .attr("source-info-name", "iptv-proxy"),
+    )?;
+    for e in entries {
+        write_channel(&mut writer, e.id, &e.name, base)?;
+    }
+    for e in entries {
+        for programme in epg.get(&e.id).into_iter().flatten() {
+            write_programme(&mut writer, e.id, programme)?;
+        }
+    }
+    writer.write(XmlWriteEvent::end_element())?;
+    writer.into_inner().flush()?;
+    Ok(())
+}
+
+// 频道分组规则，修改后新获取的频道列表生效
+#[get("/api/category-rules")]
+async fn api_get_category_rules() -> impl Responder {
+    HttpResponse::Ok().json(category::config())
+}
+
+#[post("/api/category-rules")]
+async fn api_set_category_rules(config: Json<category::CategoryConfig>) -> impl Responder {
+    match category::set(config.into_inner()) {
+        Ok(()) => HttpResponse::Ok().json("Category rules updated successfully"),
+        Err(e) => HttpResponse::BadRequest().json(format!("Invalid category rules: {}", e)),
+    }
+}
+
+#[get("/api/profiles")]
+async fn api_get_profiles() -> impl Responder {
+    HttpResponse::Ok().json(profile::profiles())
+}
+
+#[post("/api/profiles")]
+async fn api_set_profiles(profiles: Json<Vec<Profile>>) -> impl Responder {
+    match profile::set(profiles.into_inner()) {
+        Ok(()) => HttpResponse::Ok().json("Profiles updated successfully"),
+        Err(e) => HttpResponse::BadRequest().json(format!("Invalid profiles: {}", e)),
+    }
+}
+
+// 向 Plex/Jellyfin 报告的调谐器数量，即同时播放的频道数
+const HDHOMERUN_TUNERS: u32 = 4;
+
+// HDHomeRun 设备信息，Plex/Jellyfin 添加设备时先请求此地址
+#[get("/discover.json")]
+async fn hdhomerun_discover(args: Data<Args>, req: HttpRequest) -> impl Responder {
+    let base = format!("{}://{}", req.connection_info().scheme(), req.connection_info().host());
+    // 设备ID按账号生成，重启后保持不变
+    let device_id = format!("{:x}", md5::compute(args.user.as_bytes()))[..8].to_uppercase();
+    HttpResponse::Ok().json(serde_json::json!({
+        "FriendlyName": "iptv-proxy",
+        "Manufacturer": "Silicondust",
+        "ModelNumber": "HDTC-2US",
+        "FirmwareName": "hdhomeruntc_atsc",
+        "FirmwareVersion": env!("CARGO_PKG_VERSION"),
+        "DeviceID": device_id,
+        "DeviceAuth": "iptv-proxy",
+        "BaseURL": base,
+        "LineupURL": format!("{}/lineup.json", base),
+        "TunerCount": HDHOMERUN_TUNERS,
+    }))
+}
+
+// 频道扫描状态，本服务的频道列表总是可用
+#[get("/lineup_status.json")]
+async fn hdhomerun_lineup_status() -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({
+        "ScanInProgress": 0,
+        "ScanPossible": 1,
+        "Source": "Cable",
+        "SourceList": ["Cable"],
+    }))
+}
+
+// HDHomeRun 格式的频道列表，供 Plex/Jellyfin 等使用
+#[get("/lineup.json")]
+async fn hdhomerun_lineup(args: Data<Args>, req: HttpRequest) -> impl Responder {
+    let scheme = req.connection_info().scheme().to_owned();
+    let host = req.connection_info().host().to_owned();
+    let ch = match get_channels(&args, false, &scheme, &host).await {
+        Ok(ch) => ch,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("Error getting channels: {}", e)),
+    };
+    let lineup = playlist_entries(&args, &ch, None, &format!("{}://{}", scheme, host))
+        .into_iter()
+        .map(|e| {
+            serde_json::json!({
+                "GuideNumber": e.chno.to_string(),
+                "GuideName": e.name,
+                "URL": e.url,
+            })
+        })
+        .collect::<Vec<_>>();
+    HttpResponse::Ok().json(lineup)
+}
+
+// DIYP/TVBox 使用的 TXT 播放列表：分组,#genre# 后接 名称,地址
+#[get("/playlist.txt")]
+async fn playlist_txt(args: Data<Args>, req: HttpRequest) -> impl Responder {
+    debug!("Get TXT playlist");
+    let scheme = req.connection_info().scheme().to_owned();
+    let host = req.connection_info().host().to_owned();
+    let ch = match get_channels(&args, false, &scheme, &host).await {
+        Ok(ch) => ch,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("Error getting channels: {}", e)),
+    };
+
+    // 属于多个分组的频道在每个分组下都列出
+    let entries = playlist_entries(&args, &ch, None, &format!("{}://{}", scheme, host))
+        .into_iter()
+        .flat_map(|e| {
+            let (name, url) = (e.name, e.url);
+            e.groups.into_iter().map(move |g| (g, name.clone(), url.clone()))
+        })
+        .collect::<Vec<_>>();
+
+    // 保持分组首次出现的顺序
+    let mut groups: Vec<(String, Vec<String>)> = Vec::new();
+    for (group, name, url) in entries {
+        let line = format!("{},{}", name, url);
+        match groups.iter_mut().find(|(g, _)| *g == group) {
+            Some((_, lines)) => lines.push(line),
+            None => groups.push((group, vec![line])),
+        }
+    }
+    let body = groups
+        .into_iter()
+        .map(|(group, lines)| format!("{},#genre#\n{}", group, lines.join("\n")))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    HttpResponse::Ok().content_type("text/plain; charset=utf-8").body(body + "\n")
+}
+
+#[derive(Deserialize)]
+struct DiypQuery {
+    ch: String,
+    date: Option<String>,
+}
+
+// DIYP/百川/超级直播 EPG 接口：/diyp?ch=CCTV1&date=2024-01-01
+#[get("/diyp")]
+async fn diyp_epg(args: Data<Args>, query: Query<DiypQuery>) -> impl Responder {
+    let DiypQuery { ch, date } = query.into_inner();
+    let date = date
+        .as_deref()
+        .and_then(diyp::parse_date)
+        .unwrap_or_else(diyp::today);
+    let epg = match get_epg_from_xmltv_cache().await {
+        Ok(epg) => epg,
+        Err(e) => {
+            log::error!("Failed to get EPG from XMLTV cache: {}", e);
+            Default::default()
+        }
+    };
+    let channels = catalog::channels(&args).await.unwrap_or_default();
+
+    let programmes = diyp::find_channel(&channels, &ch, &epg)
+        .and_then(|c| epg.get(&c.id))
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    HttpResponse::Ok().json(diyp::epg_for_day(&ch, date, programmes))
+}
+
+fn get_user_agent(req: &HttpRequest) -> String {
+    req.headers()
+        .get("user-agent")
+        .and_then(|ua| ua.to_str().ok())
+        .unwrap_or("unknown")
+        .to_string()
+}
+
+// 记录一次播放：查询IP位置，写入内存和文件
+async fn record_playback(
+    client_ip: String,
+    user_agent: String,
+    channel_id: String,
+    channel_name: String,
+    rtsp_url: String,
+) {
+    // 获取IP地理位置
+    let ip_location = Some(geoip::location(&client_ip).await);
+
+    let record = PlaybackRecord {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64,
+        client_ip,
+        channel_id,
+        channel_name,
+        user_agent,
+        rtsp_url,
+        ip_location,
+    };
+
+    // 记录到内存
+    if let Ok(mut records) = PLAYBACK_RECORDS.try_lock() {
+        records.push(record.clone());
+
+        // 只保留最近1000条记录
+        if records.len() > 1000 {
+            let excess = records.len() - 1000;
+            records.drain(0..excess);
+        }
+    }
+
+    // 保存到文件
+    if let Err(e) = save_playback_record(&record) {
+        error!("Failed to save playback record: {}", e);
+    }
+
+    info!("📺 播放记录: IP={}, 位置={:?}, 频道={}, UserAgent={}",
+          record.client_ip, record.ip_location, record.channel_name, record.user_agent);
+}
+
 #[get("/rtsp/{tail:.*}")]
 async fn rtsp(
     args: Data<Args>,
@@ -1475,74 +2008,378 @@ async fn rtsp(
     let param = params.next().unwrap_or("".to_string());
     let param = params.fold(param, |o, q| format!("{}&{}", o, q));
     
-    // 记录播放统计
     let client_ip = get_client_ip(&req);
-    let user_agent = req.headers()
-        .get("user-agent")
-        .and_then(|ua| ua.to_str().ok())
-        .unwrap_or("unknown")
-        .to_string();
-    
     let rtsp_url = format!("rtsp://{}?{}", path, param);
-    let channel_id = extract_channel_id_from_rtsp_url(path);
-    
-    // 异步获取频道名称并记录播放统计
-    let args_clone = args.clone();
-    let channel_id_clone = channel_id.clone();
-    let client_ip_clone = client_ip.clone();
-    let user_agent_clone = user_agent.clone();
-    let rtsp_url_clone = rtsp_url.clone();
-    
+
+    // 记录播放统计：先按上游地址精确匹配频道，匹配不到再按ID推测
+    let stats_args = args.clone();
+    let stats_ip = client_ip.clone();
+    let user_agent = get_user_agent(&req);
+    let rtsp_path = path.clone();
+    let stats_url = rtsp_url.clone();
     tokio::spawn(async move {
-        let channel_name = get_channel_name_by_id(&channel_id_clone, &args_clone).await;
-        
-        // 获取IP地理位置
-        let ip_location = get_ip_location(&client_ip_clone).await;
-        
-        let record = PlaybackRecord {
-            timestamp: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_millis() as i64,
-            client_ip: client_ip_clone,
-            channel_id: channel_id_clone,
-            channel_name,
-            user_agent: user_agent_clone,
-            rtsp_url: rtsp_url_clone,
-            ip_location,
-        };
-        
-        // 记录到内存
-        if let Ok(mut records) = PLAYBACK_RECORDS.try_lock() {
-            records.push(record.clone());
-            
-            // 只保留最近1000条记录
-            if records.len() > 1000 {
-                let excess = records.len() - 1000;
-                records.drain(0..excess);
+        let (channel_id, channel_name) = match find_channel_by_rtsp_path(&rtsp_path, &stats_args).await {
+            Some(channel) => (channel.id.to_string(), channel.name),
+            None => {
+                let channel_id = extract_channel_id_from_rtsp_url(&rtsp_path);
+                let channel_name = get_channel_name_by_id(&channel_id, &stats_args).await;
+                (channel_id, channel_name)
             }
-        }
-        
-        // 保存到文件
-        if let Err(e) = save_playback_record(&record) {
-            error!("Failed to save playback record: {}", e);
-        }
-        
-        info!("📺 播放记录: IP={}, 位置={:?}, 频道={}, UserAgent={}", 
-              record.client_ip, record.ip_location, record.channel_name, record.user_agent);
+        };
+        record_playback(stats_ip, user_agent, channel_id, channel_name, stats_url).await;
     });
-    
-    HttpResponse::Ok().streaming(proxy::rtsp(rtsp_url, args.interface.clone()))
+
+    let interface = args.interface.clone();
+    // 回看请求各自有独立的进度，不能共享上游
+    if param.contains("playseek") {
+        return HttpResponse::Ok().streaming(proxy::rtsp(rtsp_url, interface));
+    }
+    HttpResponse::Ok().streaming(hub::subscribe(rtsp_url.clone(), client_ip, move || {
+        proxy::rtsp(rtsp_url, interface)
+    }))
 }
 
 #[get("/udp/{addr}")]
-async fn udp(args: Data<Args>, addr: Path<String>) -> impl Responder {
+async fn udp(args: Data<Args>, addr: Path<String>, req: HttpRequest) -> impl Responder {
     let addr = &*addr;
     let addr = match SocketAddrV4::from_str(addr) {
         Ok(addr) => addr,
         Err(e) => return HttpResponse::BadRequest().body(format!("Error: {}", e)),
     };
-    HttpResponse::Ok().streaming(proxy::udp(addr, args.interface.clone()))
+    let interface = args.interface.clone();
+    HttpResponse::Ok().streaming(hub::subscribe(format!("udp://{}", addr), get_client_ip(&req), move || {
+        proxy::udp(addr, interface)
+    }))
+}
+
+// 频道的上游来源，prefer_multicast 时组播在前
+fn channel_sources(channel: &Channel, prefer_multicast: bool) -> Vec<proxy::Source> {
+    // 不能转发的额外频道没有来源，由调用方重定向到原地址
+    if channel.extra.is_some() {
+        return if is_http_url(&channel.rtsp) {
+            vec![proxy::Source::Http(channel.rtsp.clone())]
+        } else {
+            Vec::new()
+        };
+    }
+    let unicast = proxy::Source::Rtsp(channel.rtsp.clone());
+    let multicast = channel
+        .igmp
+        .as_deref()
+        .and_then(|igmp| igmp.strip_prefix("igmp://"))
+        .and_then(|addr| SocketAddrV4::from_str(addr.trim_end_matches('/')).ok())
+        .map(proxy::Source::Udp);
+    match multicast {
+        Some(multicast) if prefer_multicast => vec![multicast, unicast],
+        Some(multicast) => vec![unicast, multicast],
+        None => vec![unicast],
+    }
+}
+
+// 额外播放列表中只有 http(s) 地址经本服务转发，其他协议（rtsp://、udp:// 等）使用原地址
+fn is_http_url(url: &str) -> bool {
+    let url = url.to_ascii_lowercase();
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+fn redirect(url: &str) -> HttpResponse {
+    HttpResponse::Found().insert_header((header::LOCATION, url)).finish()
+}
+
+#[get("/play/{id}")]
+async fn play(args: Data<Args>, path: Path<u64>, req: HttpRequest) -> impl Responder {
+    let channel_id = path.into_inner();
+    let channel = match catalog::channel(&args, channel_id).await {
+        Ok(Some(channel)) => channel,
+        Ok(None) => return HttpResponse::NotFound().body(format!("Channel {} not found", channel_id)),
+        Err(e) => return HttpResponse::InternalServerError().body(format!("Error getting channels: {}", e)),
+    };
+    let sources = channel_sources(&channel, args.udp_proxy);
+    if sources.is_empty() {
+        return redirect(&channel.rtsp);
+    }
+
+    let client_ip = get_client_ip(&req);
+    let stats_ip = client_ip.clone();
+    let user_agent = get_user_agent(&req);
+    let stats_url = sources[0].key();
+    let stats_name = channel.name.clone();
+    tokio::spawn(async move {
+        record_playback(stats_ip, user_agent, channel.id.to_string(), stats_name, stats_url).await;
+    });
+
+    HttpResponse::Ok().streaming(hub::failover(
+        channel.name,
+        sources,
+        client_ip,
+        args.interface.clone(),
+        std::time::Duration::from_secs(args.failover_timeout.max(1)),
+    ))
+}
+
+#[derive(Deserialize)]
+struct ShiftQuery {
+    utc: Option<String>,
+}
+
+#[get("/channel/{id}")]
+async fn channel_stream(
+    args: Data<Args>,
+    path: Path<u64>,
+    query: Query<ShiftQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    let channel_id = path.into_inner();
+    // shift 方式的回看：播放器在直播地址后追加 ?utc=开始时间
+    if let Some(utc) = &query.utc {
+        let Some(start) = catchup::parse_time(utc) else {
+            return HttpResponse::BadRequest().body(format!("Invalid utc: {}", utc));
+        };
+        return match catalog::channel(&args, channel_id).await {
+            Ok(Some(channel)) => stream_catchup(&args, channel, start, None, &req),
+            Ok(None) => HttpResponse::NotFound().body(format!("Channel {} not found", channel_id)),
+            Err(e) => HttpResponse::InternalServerError().body(format!("Error getting channels: {}", e)),
+        };
+    }
+    channel_live(&args, channel_id, args.udp_proxy, &req).await
+}
+
+async fn channel_live(args: &Args, channel_id: u64, prefer_multicast: bool, req: &HttpRequest) -> HttpResponse {
+    let channel = match catalog::channel(args, channel_id).await {
+        Ok(Some(channel)) => channel,
+        Ok(None) => return HttpResponse::NotFound().body(format!("Channel {} not found", channel_id)),
+        Err(e) => return HttpResponse::InternalServerError().body(format!("Error getting channels: {}", e)),
+    };
+    let Some(source) = channel_sources(&channel, prefer_multicast).into_iter().next() else {
+        return redirect(&channel.rtsp);
+    };
+    debug!("Play channel {} ({}) from {}", channel.name, channel.id, source.key());
+
+    let client_ip = get_client_ip(req);
+    let stats_ip = client_ip.clone();
+    let user_agent = get_user_agent(req);
+    let stats_url = source.key();
+    tokio::spawn(async move {
+        record_playback(stats_ip, user_agent, channel.id.to_string(), channel.name, stats_url).await;
+    });
+
+    let interface = args.interface.clone();
+    HttpResponse::Ok().streaming(hub::subscribe(source.key(), client_ip, move || {
+        source.open(interface)
+    }))
+}
+
+#[derive(Deserialize)]
+struct CatchupQuery {
+    start: String,
+    end: Option<String>,
+}
+
+#[get("/channel/{id}/catchup")]
+async fn channel_catchup(
+    args: Data<Args>,
+    path: Path<u64>,
+    query: Query<CatchupQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    catchup_request(&args, path.into_inner(), &query, &req).await
+}
+
+// 播放列表配置指定转发方式时的回看地址，回看总是经 RTSP
+#[get("/channel/{id}/{source}/catchup")]
+async fn channel_source_catchup(
+    args: Data<Args>,
+    path: Path<(u64, String)>,
+    query: Query<CatchupQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    let (channel_id, source) = path.into_inner();
+    if source != "rtsp" && source != "udp" {
+        return HttpResponse::NotFound().body(format!("Unknown source: {}", source));
+    }
+    catchup_request(&args, channel_id, &query, &req).await
+}
+
+async fn catchup_request(args: &Args, channel_id: u64, query: &CatchupQuery, req: &HttpRequest) -> HttpResponse {
+    let channel = match catalog::channel(args, channel_id).await {
+        Ok(Some(channel)) => channel,
+        Ok(None) => return HttpResponse::NotFound().body(format!("Channel {} not found", channel_id)),
+        Err(e) => return HttpResponse::InternalServerError().body(format!("Error getting channels: {}", e)),
+    };
+
+    let Some(start) = catchup::parse_time(&query.start) else {
+        return HttpResponse::BadRequest().body(format!("Invalid start time: {}", query.start));
+    };
+    let end = match query.end.as_deref().map(catchup::parse_time) {
+        Some(Some(end)) => Some(end),
+        Some(None) => return HttpResponse::BadRequest().body("Invalid end time"),
+        None => None,
+    };
+    stream_catchup(args, channel, start, end, req)
+}
+
+fn stream_catchup(args: &Args, channel: Channel, start: i64, end: Option<i64>, req: &HttpRequest) -> HttpResponse {
+    if !channel.catchup {
+        return HttpResponse::NotFound().body(format!("Channel {} does not support catchup", channel.id));
+    }
+    let rtsp_url = match catchup::playseek_url(&channel.rtsp, start, end) {
+        Ok(url) => url,
+        Err(e) => return HttpResponse::BadRequest().body(format!("Invalid catchup time range: {}", e)),
+    };
+    debug!("Catchup channel {} ({}) from {}", channel.name, channel.id, rtsp_url);
+
+    let client_ip = get_client_ip(req);
+    let user_agent = get_user_agent(req);
+    let stats_url = rtsp_url.clone();
+    tokio::spawn(async move {
+        record_playback(client_ip, user_agent, channel.id.to_string(), channel.name, stats_url).await;
+    });
+
+    HttpResponse::Ok().streaming(proxy::rtsp(rtsp_url, args.interface.clone()))
+}
+
+// Flussonic 方式：/channel/{id}/mpegts 直播，/channel/{id}/timeshift_abs-{start}.ts 回看
+#[get("/channel/{id}/{file}")]
+async fn channel_flussonic(args: Data<Args>, path: Path<(u64, String)>, req: HttpRequest) -> impl Responder {
+    let (channel_id, file) = path.into_inner();
+    // 播放列表配置指定的转发方式：/channel/{id}/rtsp、/channel/{id}/udp
+    match file.as_str() {
+        "mpegts" => return channel_live(&args, channel_id, args.udp_proxy, &req).await,
+        "rtsp" => return channel_live(&args, channel_id, false, &req).await,
+        "udp" => return channel_live(&args, channel_id, true, &req).await,
+        _ => {}
+    }
+    let Some(start) = file
+        .strip_prefix("timeshift_abs-")
+        .and_then(|f| f.strip_suffix(".ts"))
+        .and_then(catchup::parse_time)
+    else {
+        return HttpResponse::NotFound().body(format!("Unknown path: {}", file));
+    };
+    match catalog::channel(&args, channel_id).await {
+        Ok(Some(channel)) => stream_catchup(&args, channel, start, None, &req),
+        Ok(None) => HttpResponse::NotFound().body(format!("Channel {} not found", channel_id)),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Error getting channels: {}", e)),
+    }
+}
+
+// HLS 输出：/channel/{id}/hls/index.m3u8 为播放列表，切片为同目录下的 {序号}.ts
+#[get("/channel/{id}/hls/{file}")]
+async fn channel_hls(args: Data<Args>, path: Path<(u64, String)>, req: HttpRequest) -> impl Responder {
+    let (channel_id, file) = path.into_inner();
+    if let Some(sequence) = file.strip_suffix(".ts").and_then(|s| s.parse::<u64>().ok()) {
+        return match hls::segment(channel_id, sequence) {
+            Some(data) => HttpResponse::Ok().content_type("video/mp2t").body(data),
+            None => HttpResponse::NotFound().body(format!("Segment {} not found", file)),
+        };
+    }
+    if file != "index.m3u8" {
+        return HttpResponse::NotFound().body(format!("Unknown path: {}", file));
+    }
+
+    // 同一频道的 HLS 客户端共用一个切片会话，没有时按 /play 的方式打开上游
+    if !hls::touch(channel_id) {
+        let channel = match catalog::channel(&args, channel_id).await {
+            Ok(Some(channel)) => channel,
+            Ok(None) => return HttpResponse::NotFound().body(format!("Channel {} not found", channel_id)),
+            Err(e) => return HttpResponse::InternalServerError().body(format!("Error getting channels: {}", e)),
+        };
+        let sources = channel_sources(&channel, args.udp_proxy);
+        if sources.is_empty() {
+            return redirect(&channel.rtsp);
+        }
+        let client_ip = get_client_ip(&req);
+        let stats_ip = client_ip.clone();
+        let user_agent = get_user_agent(&req);
+        let stats_url = sources[0].key();
+        let stats_name = channel.name.clone();
+        let interface = args.interface.clone();
+        let timeout = std::time::Duration::from_secs(args.failover_timeout.max(1));
+        let started = hls::start(channel_id, move || {
+            hub::failover(channel.name, sources, client_ip, interface, timeout)
+        });
+        if started {
+            tokio::spawn(async move {
+                record_playback(stats_ip, user_agent, channel_id.to_string(), stats_name, stats_url).await;
+            });
+        }
+    }
+    match hls::playlist(channel_id).await {
+        Some(m3u8) => HttpResponse::Ok()
+            .content_type("application/vnd.apple.mpegurl")
+            .insert_header((header::CACHE_CONTROL, "no-cache"))
+            .body(m3u8),
+        None => HttpResponse::ServiceUnavailable().body(format!("No data from channel {}", channel_id)),
+    }
+}
+
+// Xtream Codes 方式的直播地址
+#[get("/live/{user}/{pass}/{file}")]
+async fn xtream_live(args: Data<Args>, path: Path<(String, String, String)>, req: HttpRequest) -> impl Responder {
+    let (_, _, file) = path.into_inner();
+    match file.trim_end_matches(".ts").parse::<u64>() {
+        Ok(channel_id) => channel_live(&args, channel_id, args.udp_proxy, &req).await,
+        Err(_) => HttpResponse::NotFound().body(format!("Unknown stream: {}", file)),
+    }
+}
+
+// Xtream Codes 方式的回看地址，开始时间为 YYYY-MM-DD:HH-MM，时长单位为分钟
+#[get("/timeshift/{user}/{pass}/{duration}/{start}/{file}")]
+async fn xtream_timeshift(
+    args: Data<Args>,
+    path: Path<(String, String, i64, String, String)>,
+    req: HttpRequest,
+) -> impl Responder {
+    let (_, _, duration, start, file) = path.into_inner();
+    let Ok(channel_id) = file.trim_end_matches(".ts").parse::<u64>() else {
+        return HttpResponse::NotFound().body(format!("Unknown stream: {}", file));
+    };
+    let Some(start) = catchup::parse_xtream_time(&start) else {
+        return HttpResponse::BadRequest().body(format!("Invalid start time: {}", start));
+    };
+    let Some(end) = duration.checked_mul(60_000).and_then(|ms| start.checked_add(ms)) else {
+        return HttpResponse::BadRequest().body(format!("Invalid duration: {}", duration));
+    };
+    match catalog::channel(&args, channel_id).await {
+        Ok(Some(channel)) => stream_catchup(&args, channel, start, Some(end), &req),
+        Ok(None) => HttpResponse::NotFound().body(format!("Channel {} not found", channel_id)),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Error getting channels: {}", e)),
+    }
+}
+
+#[get("/api/stream-info")]
+async fn api_stream_info() -> impl Responder {
+    debug!("Get active stream info");
+    HttpResponse::Ok().json(hub::active_streams())
+}
+
+#[derive(Deserialize)]
+struct ProbeQuery {
+    duration: Option<u64>,
+}
+
+#[get("/api/stream-info/{id}")]
+async fn api_probe_stream(args: Data<Args>, path: Path<u64>, query: Query<ProbeQuery>) -> impl Responder {
+    let channel_id = path.into_inner();
+    // 探测时长，默认5秒，最长30秒
+    let duration = std::time::Duration::from_secs(query.duration.unwrap_or(5).clamp(1, 30));
+    debug!("Probe stream of channel {} for {:?}", channel_id, duration);
+
+    let channel = match catalog::channel(&args, channel_id).await {
+        Ok(Some(channel)) => channel,
+        Ok(None) => return HttpResponse::NotFound().json(format!("Channel {} not found", channel_id)),
+        Err(e) => return HttpResponse::InternalServerError().json(format!("Error getting channels: {}", e)),
+    };
+
+    let Some(source) = channel_sources(&channel, args.udp_proxy).into_iter().next() else {
+        return HttpResponse::BadRequest().json(format!("Channel {} is not proxied", channel_id));
+    };
+    let result = proxy::probe(source.open(args.interface.clone()), duration).await;
+
+    match result {
+        Ok(info) => HttpResponse::Ok().json(info),
+        Err(e) => HttpResponse::InternalServerError().json(format!("Failed to probe channel {}: {}", channel_id, e)),
+    }
 }
 
 #[allow(dead_code)]
@@ -1556,13 +2393,36 @@ Options:
     -m, --mac <MAC>                        MAC address
     -i, --imei <IMEI>                      IMEI [default: ]
     -b, --bind <BIND>                      Bind address:port [default: 0.0.0.0:7878]
+        --public-url <URL>                 Base URL of this server for cached EPG, e.g. http://192.168.1.2:7878
     -a, --address <ADDRESS>                IP address/interface name [default: ]
     -I, --interface <INTERFACE>            Interface to request
-        --extra-playlist <EXTRA_PLAYLIST>  Url to extra m3u
-        --extra-xmltv <EXTRA_XMLTV>        Url to extra xmltv
+        --extra-playlist <EXTRA_PLAYLIST>  Url or file of extra m3u, may be repeated
+        --extra-playlist-include <REGEX>   Only keep extra channels whose name matches
+        --extra-playlist-exclude <REGEX>   Drop extra channels whose name matches
+        --extra-playlist-proxy             Proxy http streams of extra channels
+        --extra-xmltv <EXTRA_XMLTV>        Url or file of extra xmltv, may be repeated (earlier wins)
+        --xmltv-mapping <MAPPING>          Extra xmltv channel mapping (format: "ext1=ours1,ext2=ours2")
         --channel-mapping <MAPPING>        Channel name mapping (format: "from1=to1,from2=to2")
         --udp-proxy                        Use UDP proxy
         --rtsp-proxy                       Use rtsp proxy
+        --failover-timeout <SECONDS>       Seconds without data before /play switches source [default: 5]
+        --catchup-style <STYLE>            append, default, shift, flussonic or xtream [default: append]
+        --catchup-days <DAYS>              Catch-up days when the provider does not report it [default: 7]
+        --timezone <OFFSET>                Time zone of playseek and XMLTV times [default: +08:00]
+        --epg-history-days <DAYS>          Days of past programmes kept in the EPG store [default: 7]
+        --epg-concurrency <N>              Channels fetched in parallel by EPG jobs [default: 2]
+        --epg-rate <N>                     Max EPG requests per second [default: 5]
+        --epg-gap-fill <TITLE>             Fill gaps between programmes with placeholders of this title
+        --dummy-epg                        Generate placeholder programmes for channels without EPG
+        --dummy-epg-minutes <MINUTES>      Length of each placeholder programme [default: 60]
+        --dummy-epg-title <TEMPLATE>       Placeholder title, {{name}} and {{id}} are replaced [default: {{name}}]
+        --logo-cache-mb <MB>               Memory used by cached logos, 0 for unlimited [default: 16]
+        --logo-dir <DIR>                   Directory of {{name}}.png logos used before upstream icons
+        --logo-url-template <URL>          Remote logo URL, {{name}} and {{id}} are replaced
+        --logo-size <WxH>                  Resize logos into this box with transparent padding, e.g. 256x256
+        --logo-format <FORMAT>             Logo output format: original, png or webp [default: original]
+        --geoip-db <FILE>                  Local GeoIP database, ip2region .xdb or MaxMind .mmdb
+        --geoip-online                     Look up client locations on ip-api.com when not found locally
     -h, --help                             Print help
 "#,
         cmd
@@ -1613,11 +2473,20 @@ where
         // 检查是否是需要保持开放的 IPTV API 端点
         let open_paths = [
             "/playlist",
+            "/lineup.json",
+            "/lineup_status.json",
+            "/discover.json",
+            "/diyp",
             "/epg.xml", 
+            "/epg/",
             "/xmltv",
             "/logo/",
             "/rtsp/",
             "/udp/",
+            "/play/",
+            "/channel/",
+            "/live/",
+            "/timeshift/",
         ];
         
         let is_open_path = open_paths.iter().any(|&open_path| {
@@ -1685,12 +2554,44 @@ async fn main() -> std::io::Result<()> {
     // 使用 argh 直接从环境解析参数
     let args: Args = argh::from_env();
 
+    if let Err(e) = catchup::init(&args.timezone) {
+        log::error!("{}", e);
+        exit(1);
+    }
+    if let Err(e) = args.catchup_style.parse::<CatchupStyle>() {
+        log::error!("{}", e);
+        exit(1);
+    }
+    if let Err(e) = geoip::init(args.geoip_db.as_deref(), args.geoip_online) {
+        log::error!("Failed to load GeoIP database: {}", e);
+        exit(1);
+    }
+
+    logo_cache::set_memory_limit(args.logo_cache_mb * 1024 * 1024);
+    if let Err(e) = args.logo_format.parse::<LogoFormat>() {
+        log::error!("{}", e);
+        exit(1);
+    }
+    if let Some(Err(e)) = args.logo_size.as_deref().map(logo_image::parse_size) {
+        log::error!("{}", e);
+        exit(1);
+    }
+    if !logo_image::available() && (args.logo_size.is_some() || args.logo_format != "original") {
+        warn!("Built without the logo-processing feature, logos are served unchanged");
+    }
+
     // 加载映射配置
-    if let Ok(file_mappings) = load_mappings_from_file() {
-        if let Ok(mut mappings) = CHANNEL_MAPPINGS.try_lock() {
-            *mappings = file_mappings;
-            log::info!("Loaded {} channel mappings from file", mappings.len());
-        }
+    if let Err(e) = mapping::load() {
+        log::error!("Failed to load channel mappings: {}", e);
+    }
+    if let Err(e) = lineup::load() {
+        log::error!("Failed to load lineup: {}", e);
+    }
+    if let Err(e) = category::load() {
+        log::error!("Failed to load category rules: {}", e);
+    }
+    if let Err(e) = profile::load() {
+        log::error!("Failed to load playlist profiles: {}", e);
     }
     
     // 加载播放统计记录
@@ -1706,24 +2607,27 @@ async fn main() -> std::io::Result<()> {
         }
     }
     
-    // 加载XMLTV缓存
-    let mut has_cache = false;
-    if let Ok(Some(xmltv_cache)) = load_xmltv_cache() {
-        if let Ok(mut cache) = MAPPED_XMLTV_CACHE.try_lock() {
-            *cache = Some(xmltv_cache);
-            log::info!("Loaded XMLTV cache from file");
-            has_cache = true;
-        }
-    }
+    // 检查XMLTV缓存
+    let has_cache = xmltv_cache::exists();
       
+    // 加载节目单存储，首次运行时从XMLTV缓存导入
+    epg_store::init(xmltv_cache::load_epg);
+
+    // 继续上次异常退出时未完成的EPG任务
+    let resumed = epg_crawler::resume(&args);
+
     // 如果没有XMLTV缓存，立即生成一个
     if !has_cache {
         log::info!("No XMLTV cache found, generating initial cache...");
-        let scheme = "http";
-        let host = &args.bind;
-        
-        // 获取频道数据（包含EPG）
-        let channels = match get_channels(&args, true, scheme, host).await {
+
+        // 先获取节目单
+        let (job_id, handle) = resumed.unwrap_or_else(|| epg_crawler::submit(args.clone(), "startup"));
+        if !matches!(handle.await, Ok(Ok(()))) {
+            log::warn!("EPG job {} did not finish, initial XMLTV may lack programmes", job_id);
+        }
+
+        // 获取频道，节目单在生成时从节目单存储读取
+        let channels = match catalog::channels(&args).await {
             Ok(channels) => channels,
             Err(e) => {
                 log::error!("Failed to get channels for initial XMLTV: {}", e);
@@ -1731,23 +2635,9 @@ async fn main() -> std::io::Result<()> {
             }
         };
         
-        let mapping = args.channel_mapping.as_ref()
-            .map(|s| parse_channel_mapping(s))
-            .unwrap_or_default();
-        
-            
-        match to_xmltv(channels, None::<EventReader<Cursor<String>>>, &mapping) {
-            Ok(xmltv) => {
-                if let Ok(mut cache) = MAPPED_XMLTV_CACHE.try_lock() {
-                    *cache = Some(xmltv.clone());
-                    
-                    // 保存到文件
-                    if let Err(e) = save_xmltv_cache(&xmltv) {
-                        log::error!("Failed to save XMLTV cache: {}", e);
-                    } else {
-                        log::info!("Initial XMLTV cache generated and saved");
-                    }
-                }
+        match save_xmltv(&args, channels).await {
+            Ok(()) => {
+                log::info!("Initial XMLTV cache generated and saved");
             }
             Err(e) => {
                 log::error!("Failed to generate initial XMLTV: {}", e);
@@ -1776,23 +2666,66 @@ async fn main() -> std::io::Result<()> {
             .wrap(AuthMiddleware)
             .service(index)
             .service(api_channels)
+            .service(api_get_lineup)
+            .service(api_set_lineup)
+            .service(hdhomerun_discover)
+            .service(hdhomerun_lineup_status)
+            .service(hdhomerun_lineup)
+            .service(playlist_profile)
+            .service(epg_profile)
+            .service(api_get_profiles)
+            .service(api_get_category_rules)
+            .service(api_set_category_rules)
+            .service(api_set_profiles)
             .service(api_channels_with_epg)
             .service(api_channel_epg)
+            .service(api_now)
+            .service(api_epg_search)
             .service(api_set_channel_mappings)
             .service(api_get_channel_mappings)
+            .service(api_suggest_channel_mappings)
+            .service(api_accept_channel_mappings)
+            .service(api_add_channel_mappings)
+            .service(api_remove_channel_mappings)
+            .service(api_export_channel_mappings)
+            .service(api_import_channel_mappings)
+            .service(api_channel_mappings_history)
+            .service(api_channel_mappings_revision)
+            .service(api_rollback_channel_mappings)
+            .service(api_get_mapping_rules)
+            .service(api_set_mapping_rules)
+            .service(api_mapping_dry_run)
+            .service(api_mapping_dry_run_with)
             .service(api_cache_status)
             .service(api_fetch_epg)
+            .service(api_epg_jobs)
+            .service(api_epg_job)
             .service(api_clear_logo_cache)
             .service(api_regenerate_xmltv)
             .service(api_playback_stats)
             .service(api_playback_summary)
             .service(api_clear_stats)
+            .service(api_stream_info)
+            .service(api_probe_stream)
             .service(xmltv_route)
             .service(epg_xml_cached)
+            .service(epg_xml_gz)
             .service(playlist)
+            .service(playlist_txt)
+            .service(diyp_epg)
             .service(logo)
+            .service(api_upload_logo)
+            .service(api_delete_logo)
             .service(rtsp)
             .service(udp)
+            .service(play)
+            .service(channel_stream)
+            .service(channel_catchup)
+            .service(channel_source_catchup)
+            .service(channel_hls)
+            .service(channel_flussonic)
+            .service(xtream_live)
+            .service(xtream_timeshift)
             .service(fs::Files::new("/static", "/static").show_files_listing())
             .app_data(args)
     })
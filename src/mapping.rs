@@ -0,0 +1,542 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{File, OpenOptions},
+    path::Path,
+    sync::{LazyLock, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use log::warn;
+use regex_lite::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::args::Args;
+use crate::iptv::Channel;
+use crate::xmltv_sources::normalize_name;
+
+const MAPPINGS_FILE: &str = "channel_mappings.json";
+const RULES_FILE: &str = "mapping_rules.json";
+const HISTORY_FILE: &str = "channel_mappings_history.json";
+// 保留的映射版本数
+const MAX_REVISIONS: usize = 20;
+
+// Web 界面设置的 频道ID -> 节目单/台标来源频道ID
+pub(crate) static CHANNEL_MAPPINGS: LazyLock<Mutex<HashMap<u64, u64>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+// 通过 /api/mapping-rules 设置的规则
+static CUSTOM_RULES: LazyLock<Mutex<Vec<MappingRule>>> = LazyLock::new(|| Mutex::new(Vec::new()));
+// 按ID映射的历史版本，旧的在前
+static HISTORY: LazyLock<Mutex<Vec<Revision>>> = LazyLock::new(|| Mutex::new(Vec::new()));
+
+// 规则匹配方式：{"id": 123}、{"name": "CCTV-1"}、{"regex": "^CCTV-?(\\d+)"}
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Match {
+    Id(u64),
+    Name(String),
+    Regex(String),
+}
+
+// 引用另一个频道，ID 或名称
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub(crate) enum ChannelRef {
+    Id(u64),
+    Name(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct MappingRule {
+    #[serde(rename = "match")]
+    pub(crate) matcher: Match,
+    // 节目单来源频道
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) epg: Option<ChannelRef>,
+    // 台标来源频道
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) logo: Option<ChannelRef>,
+    // 显示名称，正则规则中可以用 $1 引用分组，如 CCTV$1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) group: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) chno: Option<u32>,
+}
+
+// 规则来源，同一匹配方式下靠前的优先
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum RuleSource {
+    Ui,
+    Rules,
+    Cli,
+}
+
+// 频道应用规则后的结果
+#[derive(Serialize, Clone)]
+pub(crate) struct Resolved {
+    pub(crate) id: u64,
+    pub(crate) name: String,
+    pub(crate) epg_id: u64,
+    pub(crate) logo_id: u64,
+    pub(crate) group: Option<String>,
+    pub(crate) chno: Option<u32>,
+    // 字段 -> 生效的规则
+    pub(crate) applied: BTreeMap<&'static str, String>,
+}
+
+struct CompiledRule {
+    rule: MappingRule,
+    source: RuleSource,
+    index: usize,
+    regex: Option<Regex>,
+}
+
+impl CompiledRule {
+    fn specificity(&self) -> u8 {
+        match self.rule.matcher {
+            Match::Id(_) => 0,
+            Match::Name(_) => 1,
+            Match::Regex(_) => 2,
+        }
+    }
+
+    fn matches(&self, channel: &Channel) -> bool {
+        match &self.rule.matcher {
+            Match::Id(id) => channel.id == *id,
+            Match::Name(name) => channel.name == *name,
+            Match::Regex(_) => self.regex.as_ref().is_some_and(|re| re.is_match(&channel.name)),
+        }
+    }
+
+    fn describe(&self) -> String {
+        let matcher = match &self.rule.matcher {
+            Match::Id(id) => format!("id={}", id),
+            Match::Name(name) => format!("name={}", name),
+            Match::Regex(pattern) => format!("regex={}", pattern),
+        };
+        let source = match self.source {
+            RuleSource::Ui => "ui",
+            RuleSource::Rules => "rules",
+            RuleSource::Cli => "cli",
+        };
+        format!("{}#{} {}", source, self.index, matcher)
+    }
+}
+
+// 所有来源的规则，按 匹配方式（ID > 名称 > 正则）、来源、定义顺序 排列
+pub(crate) struct RuleSet {
+    rules: Vec<CompiledRule>,
+}
+
+impl RuleSet {
+    // 当前生效的规则
+    pub(crate) fn load(args: &Args) -> Self {
+        let custom = CUSTOM_RULES.lock().map(|r| r.clone()).unwrap_or_default();
+        Self::with_custom(args, custom).unwrap_or_else(|e| {
+            warn!("Invalid mapping rules: {}", e);
+            Self::with_custom(args, vec![]).unwrap_or(Self { rules: vec![] })
+        })
+    }
+
+    // 用给定的自定义规则替换已保存的规则，用于预览
+    pub(crate) fn with_custom(args: &Args, custom: Vec<MappingRule>) -> Result<Self> {
+        let mut ui = CHANNEL_MAPPINGS
+            .lock()
+            .map(|m| m.iter().map(|(&from, &to)| (from, to)).collect::<Vec<_>>())
+            .unwrap_or_default();
+        ui.sort();
+        let ui = ui.into_iter().map(|(from, to)| MappingRule {
+            matcher: Match::Id(from),
+            epg: Some(ChannelRef::Id(to)),
+            logo: Some(ChannelRef::Id(to)),
+            name: None,
+            group: None,
+            chno: None,
+        });
+        // --channel-mapping "名称=名称"，按出现顺序
+        let cli = args
+            .channel_mapping
+            .as_deref()
+            .map(parse_mapping_pairs)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(from, to)| MappingRule {
+                matcher: Match::Name(from),
+                epg: Some(ChannelRef::Name(to.clone())),
+                logo: Some(ChannelRef::Name(to)),
+                name: None,
+                group: None,
+                chno: None,
+            });
+
+        let mut rules = Vec::new();
+        for (source, list) in [
+            (RuleSource::Ui, ui.collect::<Vec<_>>()),
+            (RuleSource::Rules, custom),
+            (RuleSource::Cli, cli.collect()),
+        ] {
+            for (index, rule) in list.into_iter().enumerate() {
+                let regex = match &rule.matcher {
+                    Match::Regex(pattern) => Some(
+                        Regex::new(pattern).map_err(|e| anyhow!("Invalid regex '{}': {}", pattern, e))?,
+                    ),
+                    _ => None,
+                };
+                rules.push(CompiledRule { rule, source, index, regex });
+            }
+        }
+        // 稳定排序，保持定义顺序
+        rules.sort_by_key(|r| (r.specificity(), r.source));
+        Ok(Self { rules })
+    }
+
+    // 每个字段取第一条设置了该字段且能解析的规则
+    pub(crate) fn resolve_one(&self, channel: &Channel, channels: &[Channel]) -> Resolved {
+        let mut resolved = Resolved {
+            id: channel.id,
+            name: channel.name.clone(),
+            epg_id: channel.id,
+            logo_id: channel.id,
+            group: None,
+            chno: None,
+            applied: BTreeMap::new(),
+        };
+        for rule in self.rules.iter().filter(|r| r.matches(channel)) {
+            let mut apply = |field: &'static str| {
+                if resolved.applied.contains_key(field) {
+                    return false;
+                }
+                resolved.applied.insert(field, rule.describe());
+                true
+            };
+            if let Some(id) = rule.rule.epg.as_ref().and_then(|r| find_ref(r, channels)) {
+                if apply("epg") {
+                    resolved.epg_id = id;
+                }
+            }
+            if let Some(id) = rule.rule.logo.as_ref().and_then(|r| find_ref(r, channels)) {
+                if apply("logo") {
+                    resolved.logo_id = id;
+                }
+            }
+            if let Some(name) = &rule.rule.name {
+                if apply("name") {
+                    resolved.name = match &rule.regex {
+                        Some(re) => re
+                            .captures(&channel.name)
+                            .map(|caps| {
+                                let mut expanded = String::new();
+                                caps.expand(name, &mut expanded);
+                                expanded
+                            })
+                            .unwrap_or_else(|| name.clone()),
+                        None => name.clone(),
+                    };
+                }
+            }
+            if let Some(group) = &rule.rule.group {
+                if apply("group") {
+                    resolved.group = Some(group.clone());
+                }
+            }
+            if let Some(chno) = rule.rule.chno {
+                if apply("chno") {
+                    resolved.chno = Some(chno);
+                }
+            }
+        }
+        resolved
+    }
+
+    // 按频道顺序返回所有频道的结果
+    pub(crate) fn resolve(&self, channels: &[Channel]) -> Vec<Resolved> {
+        channels.iter().map(|c| self.resolve_one(c, channels)).collect()
+    }
+}
+
+// 建议的映射：同一频道的不同清晰度版本，复用节目单最全的版本
+#[derive(Serialize)]
+pub(crate) struct Suggestion {
+    pub(crate) key: String,
+    pub(crate) from_id: u64,
+    pub(crate) from_name: String,
+    pub(crate) from_coverage: i64,
+    pub(crate) to_id: u64,
+    pub(crate) to_name: String,
+    pub(crate) to_coverage: i64,
+    // 已有的映射目标
+    pub(crate) current: Option<u64>,
+}
+
+// coverage 返回频道节目单覆盖的时长
+pub(crate) fn suggest(channels: &[Channel], coverage: impl Fn(u64) -> i64) -> Vec<Suggestion> {
+    let mut groups: BTreeMap<String, Vec<(&Channel, i64)>> = BTreeMap::new();
+    for channel in channels {
+        let key = normalize_name(&channel.name);
+        if !key.is_empty() {
+            groups.entry(key).or_default().push((channel, coverage(channel.id)));
+        }
+    }
+    let current = CHANNEL_MAPPINGS.lock().map(|m| m.clone()).unwrap_or_default();
+
+    let mut suggestions = Vec::new();
+    for (key, variants) in groups.into_iter().filter(|(_, v)| v.len() > 1) {
+        // 覆盖最多的版本，相同时取ID小的
+        let Some(&(best, best_coverage)) = variants
+            .iter()
+            .max_by_key(|(c, coverage)| (*coverage, std::cmp::Reverse(c.id)))
+        else {
+            continue;
+        };
+        if best_coverage == 0 {
+            continue;
+        }
+        for &(channel, channel_coverage) in variants.iter() {
+            let mapped = current.get(&channel.id).copied();
+            if channel.id == best.id || channel_coverage >= best_coverage || mapped == Some(best.id) {
+                continue;
+            }
+            suggestions.push(Suggestion {
+                key: key.clone(),
+                from_id: channel.id,
+                from_name: channel.name.clone(),
+                from_coverage: channel_coverage,
+                to_id: best.id,
+                to_name: best.name.clone(),
+                to_coverage: best_coverage,
+                current: mapped,
+            });
+        }
+    }
+    suggestions
+}
+
+// 映射的一个版本，保存修改后的完整映射
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct Revision {
+    pub(crate) id: u64,
+    pub(crate) timestamp: i64,
+    pub(crate) reason: String,
+    pub(crate) mappings: BTreeMap<u64, u64>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct RevisionSummary {
+    id: u64,
+    timestamp: i64,
+    reason: String,
+    count: usize,
+}
+
+#[derive(Serialize, Default)]
+pub(crate) struct MappingDiff {
+    pub(crate) added: Vec<(u64, u64)>,
+    pub(crate) removed: Vec<(u64, u64)>,
+    // (频道, 原目标, 新目标)
+    pub(crate) changed: Vec<(u64, u64, u64)>,
+}
+
+fn diff(old: &BTreeMap<u64, u64>, new: &BTreeMap<u64, u64>) -> MappingDiff {
+    let mut diff = MappingDiff::default();
+    for (&from, &to) in new {
+        match old.get(&from) {
+            None => diff.added.push((from, to)),
+            Some(&old_to) if old_to != to => diff.changed.push((from, old_to, to)),
+            _ => {}
+        }
+    }
+    diff.removed = old
+        .iter()
+        .filter(|(from, _)| !new.contains_key(from))
+        .map(|(&from, &to)| (from, to))
+        .collect();
+    diff
+}
+
+pub(crate) fn mappings() -> HashMap<u64, u64> {
+    CHANNEL_MAPPINGS.lock().map(|m| m.clone()).unwrap_or_default()
+}
+
+// 修改按ID的映射：保存文件并记录一个版本，没有变化时返回 None
+pub(crate) fn commit(reason: &str, update: impl FnOnce(&mut HashMap<u64, u64>)) -> Result<Option<u64>> {
+    let mut current = CHANNEL_MAPPINGS.lock().map_err(|_| anyhow!("Channel mappings lock poisoned"))?;
+    let mut updated = current.clone();
+    update(&mut updated);
+    updated.retain(|from, to| from != to);
+    if updated == *current {
+        return Ok(None);
+    }
+    save_json(MAPPINGS_FILE, &updated)?;
+    *current = updated;
+
+    let mut history = HISTORY.lock().map_err(|_| anyhow!("Mapping history lock poisoned"))?;
+    let id = history.last().map_or(1, |r| r.id + 1);
+    history.push(Revision {
+        id,
+        timestamp: chrono::Utc::now().timestamp_millis(),
+        reason: reason.to_string(),
+        mappings: current.iter().map(|(&from, &to)| (from, to)).collect(),
+    });
+    if history.len() > MAX_REVISIONS {
+        let excess = history.len() - MAX_REVISIONS;
+        history.drain(..excess);
+    }
+    if let Err(e) = save_json(HISTORY_FILE, &*history) {
+        warn!("Failed to save mapping history: {}", e);
+    }
+    Ok(Some(id))
+}
+
+// 合并映射，不清除已有的，返回新增或修改的数量
+pub(crate) fn add_mappings(reason: &str, mappings: impl IntoIterator<Item = (u64, u64)>) -> Result<usize> {
+    let mut added = 0;
+    commit(reason, |current| {
+        for (from, to) in mappings {
+            if from != to && current.insert(from, to) != Some(to) {
+                added += 1;
+            }
+        }
+    })?;
+    Ok(added)
+}
+
+pub(crate) fn remove_mappings(from_ids: &[u64]) -> Result<usize> {
+    let mut removed = 0;
+    commit("remove", |current| {
+        removed = from_ids.iter().filter(|id| current.remove(id).is_some()).count();
+    })?;
+    Ok(removed)
+}
+
+// 最近的版本，新的在前
+pub(crate) fn history() -> Vec<RevisionSummary> {
+    HISTORY
+        .lock()
+        .map(|h| {
+            h.iter()
+                .rev()
+                .map(|r| RevisionSummary {
+                    id: r.id,
+                    timestamp: r.timestamp,
+                    reason: r.reason.clone(),
+                    count: r.mappings.len(),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// 与上一个版本的差异；最早的版本与空映射比较
+pub(crate) fn revision_diff(id: u64) -> Option<MappingDiff> {
+    let history = HISTORY.lock().ok()?;
+    let index = history.iter().position(|r| r.id == id)?;
+    let previous = index
+        .checked_sub(1)
+        .map(|i| history[i].mappings.clone())
+        .unwrap_or_default();
+    Some(diff(&previous, &history[index].mappings))
+}
+
+// 恢复到指定版本，恢复本身也记录为新版本
+pub(crate) fn rollback(id: u64) -> Result<Option<u64>> {
+    let mappings = HISTORY
+        .lock()
+        .map_err(|_| anyhow!("Mapping history lock poisoned"))?
+        .iter()
+        .find(|r| r.id == id)
+        .map(|r| r.mappings.clone())
+        .ok_or_else(|| anyhow!("Revision {} not found", id))?;
+    commit(&format!("rollback to #{}", id), |current| {
+        *current = mappings.into_iter().collect();
+    })
+}
+
+// 按名称引用时频道必须存在；数字名称也可以作为ID
+fn find_ref(target: &ChannelRef, channels: &[Channel]) -> Option<u64> {
+    match target {
+        ChannelRef::Id(id) => Some(*id),
+        ChannelRef::Name(name) => channels
+            .iter()
+            .find(|c| c.name == *name)
+            .map(|c| c.id)
+            .or_else(|| {
+                let id = name.parse::<u64>().ok()?;
+                channels.iter().any(|c| c.id == id).then_some(id)
+            }),
+    }
+}
+
+// 解析 "from1=to1,from2=to2"，保持顺序
+pub(crate) fn parse_mapping_pairs(mapping_str: &str) -> Vec<(String, String)> {
+    mapping_str
+        .split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(from, to)| (from.trim().to_string(), to.trim().to_string()))
+        .filter(|(from, to)| !from.is_empty() && !to.is_empty())
+        .collect()
+}
+
+pub(crate) fn parse_channel_mapping(mapping_str: &str) -> HashMap<String, String> {
+    parse_mapping_pairs(mapping_str).into_iter().collect()
+}
+
+pub(crate) fn custom_rules() -> Vec<MappingRule> {
+    CUSTOM_RULES.lock().map(|r| r.clone()).unwrap_or_default()
+}
+
+pub(crate) fn set_custom_rules(args: &Args, rules: Vec<MappingRule>) -> Result<()> {
+    // 先校验正则
+    RuleSet::with_custom(args, rules.clone())?;
+    save_json(RULES_FILE, &rules)?;
+    if let Ok(mut custom) = CUSTOM_RULES.lock() {
+        *custom = rules;
+    }
+    Ok(())
+}
+
+// 启动时加载映射和规则
+pub(crate) fn load() -> Result<()> {
+    if Path::new(MAPPINGS_FILE).exists() {
+        let mappings: HashMap<u64, u64> = serde_json::from_reader(File::open(MAPPINGS_FILE)?)?;
+        log::info!("Loaded {} channel mappings from file", mappings.len());
+        if let Ok(mut current) = CHANNEL_MAPPINGS.lock() {
+            *current = mappings;
+        }
+    }
+    if Path::new(RULES_FILE).exists() {
+        let rules: Vec<MappingRule> = serde_json::from_reader(File::open(RULES_FILE)?)?;
+        log::info!("Loaded {} mapping rules from file", rules.len());
+        if let Ok(mut custom) = CUSTOM_RULES.lock() {
+            *custom = rules;
+        }
+    }
+    if let Ok(mut history) = HISTORY.lock() {
+        if Path::new(HISTORY_FILE).exists() {
+            *history = serde_json::from_reader(File::open(HISTORY_FILE)?)?;
+        }
+        // 没有历史时以当前映射作为第一个版本
+        let current = mappings().into_iter().collect::<BTreeMap<_, _>>();
+        if history.last().is_none_or(|r| r.mappings != current) && !current.is_empty() {
+            let id = history.last().map_or(1, |r| r.id + 1);
+            history.push(Revision {
+                id,
+                timestamp: chrono::Utc::now().timestamp_millis(),
+                reason: "loaded".to_string(),
+                mappings: current,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn save_json<T: Serialize + ?Sized>(path: &str, value: &T) -> Result<()> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    serde_json::to_writer_pretty(file, value)?;
+    Ok(())
+}
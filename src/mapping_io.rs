@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::iptv::Channel;
+
+const CSV_HEADER: [&str; 4] = ["from_id", "from_name", "to_id", "to_name"];
+
+// 导出/导入的一条映射，同时记录ID和名称，频道ID变化后仍可按名称导入
+#[derive(Serialize, Deserialize, Default)]
+pub(crate) struct MappingEntry {
+    #[serde(default)]
+    pub(crate) from_id: Option<u64>,
+    #[serde(default)]
+    pub(crate) from_name: Option<String>,
+    #[serde(default)]
+    pub(crate) to_id: Option<u64>,
+    #[serde(default)]
+    pub(crate) to_name: Option<String>,
+}
+
+pub(crate) fn export(channels: &[Channel], mappings: &HashMap<u64, u64>) -> Vec<MappingEntry> {
+    let name_of = |id: u64| channels.iter().find(|c| c.id == id).map(|c| c.name.clone());
+    let mut entries = mappings
+        .iter()
+        .map(|(&from, &to)| MappingEntry {
+            from_id: Some(from),
+            from_name: name_of(from),
+            to_id: Some(to),
+            to_name: name_of(to),
+        })
+        .collect::<Vec<_>>();
+    entries.sort_by_key(|e| e.from_id);
+    entries
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub(crate) fn to_csv(entries: &[MappingEntry]) -> String {
+    let mut csv = CSV_HEADER.join(",") + "\n";
+    for e in entries {
+        let fields = [
+            e.from_id.map(|id| id.to_string()).unwrap_or_default(),
+            csv_field(e.from_name.as_deref().unwrap_or_default()),
+            e.to_id.map(|id| id.to_string()).unwrap_or_default(),
+            csv_field(e.to_name.as_deref().unwrap_or_default()),
+        ];
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+// 拆分一行CSV，支持引号和 "" 转义
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            _ => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields.into_iter().map(|f| f.trim().to_string()).collect()
+}
+
+// 第一行为表头，列顺序不限，缺少的列留空
+pub(crate) fn parse_csv(body: &str) -> Result<Vec<MappingEntry>> {
+    let mut lines = body.lines().map(|l| l.trim_start_matches('\u{feff}')).filter(|l| !l.trim().is_empty());
+    let header = split_csv_line(lines.next().ok_or_else(|| anyhow!("Empty CSV"))?);
+    let column = |name: &str| header.iter().position(|h| h.eq_ignore_ascii_case(name));
+    let (from_id, from_name, to_id, to_name) =
+        (column("from_id"), column("from_name"), column("to_id"), column("to_name"));
+    if from_id.is_none() && from_name.is_none() || to_id.is_none() && to_name.is_none() {
+        return Err(anyhow!("CSV header must contain from_id/from_name and to_id/to_name"));
+    }
+
+    lines
+        .enumerate()
+        .map(|(i, line)| {
+            let fields = split_csv_line(line);
+            let text = |col: Option<usize>| {
+                col.and_then(|c| fields.get(c)).filter(|f| !f.is_empty()).cloned()
+            };
+            let id = |col: Option<usize>| {
+                text(col)
+                    .map(|f| f.parse::<u64>().map_err(|_| anyhow!("Line {}: invalid id '{}'", i + 2, f)))
+                    .transpose()
+            };
+            Ok(MappingEntry {
+                from_id: id(from_id)?,
+                from_name: text(from_name),
+                to_id: id(to_id)?,
+                to_name: text(to_name),
+            })
+        })
+        .collect()
+}
+
+// 名称优先，找不到时使用ID；频道列表为空时直接使用ID
+fn find_channel(id: Option<u64>, name: Option<&str>, channels: &[Channel]) -> Option<u64> {
+    name.and_then(|name| channels.iter().find(|c| c.name == name).map(|c| c.id))
+        .or_else(|| id.filter(|id| channels.is_empty() || channels.iter().any(|c| c.id == *id)))
+}
+
+// 解析为 频道ID -> 目标ID，返回无法对应到频道的条目说明
+pub(crate) fn resolve(entries: &[MappingEntry], channels: &[Channel]) -> (Vec<(u64, u64)>, Vec<String>) {
+    let mut mappings = Vec::new();
+    let mut skipped = Vec::new();
+    for e in entries {
+        let from = find_channel(e.from_id, e.from_name.as_deref(), channels);
+        let to = find_channel(e.to_id, e.to_name.as_deref(), channels);
+        match (from, to) {
+            (Some(from), Some(to)) => mappings.push((from, to)),
+            _ => skipped.push(format!(
+                "{}({}) -> {}({})",
+                e.from_name.as_deref().unwrap_or_default(),
+                e.from_id.map(|id| id.to_string()).unwrap_or_default(),
+                e.to_name.as_deref().unwrap_or_default(),
+                e.to_id.map(|id| id.to_string()).unwrap_or_default(),
+            )),
+        }
+    }
+    (mappings, skipped)
+}
@@ -0,0 +1,192 @@
+use std::{
+    collections::HashSet,
+    fs::{File, OpenOptions},
+    path::Path,
+    sync::{LazyLock, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use regex_lite::Regex;
+use serde::{Deserialize, Serialize};
+
+const PROFILES_FILE: &str = "playlist_profiles.json";
+
+static PROFILES: LazyLock<Mutex<Vec<Profile>>> = LazyLock::new(|| Mutex::new(Vec::new()));
+
+// 频道筛选条件：{"id": 1}、{"name": "CCTV-1"}、{"regex": "少儿|卡通"}、{"group": "央视频道"}
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Filter {
+    Id(u64),
+    Name(String),
+    Regex(String),
+    Group(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Order {
+    // 频道表顺序
+    #[default]
+    Lineup,
+    Number,
+    Name,
+    // 4K/超高清频道在前，其余保持频道表顺序
+    UhdFirst,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum StreamMode {
+    // 经本服务转发 RTSP
+    RtspProxy,
+    // 有组播地址的频道经本服务转发组播，其余直连 RTSP
+    UdpProxy,
+    // 直连上游 RTSP
+    Direct,
+    // 经本服务切片为 HLS
+    Hls,
+}
+
+// 播放列表配置，/playlist/{name} 和 /epg/{name}.xml
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub(crate) struct Profile {
+    pub(crate) name: String,
+    // 为空时包含全部频道
+    #[serde(default)]
+    pub(crate) include: Vec<Filter>,
+    #[serde(default)]
+    pub(crate) exclude: Vec<Filter>,
+    #[serde(default)]
+    pub(crate) order: Order,
+    // 不设置时按 --rtsp-proxy/--udp-proxy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) stream: Option<StreamMode>,
+    // 生成地址使用的协议和主机，不设置时取请求的
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) scheme: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) host: Option<String>,
+}
+
+enum Compiled {
+    Id(u64),
+    Name(String),
+    Regex(Regex),
+    Group(String),
+}
+
+impl Compiled {
+    fn new(filter: &Filter) -> Result<Self> {
+        Ok(match filter {
+            Filter::Id(id) => Self::Id(*id),
+            Filter::Name(name) => Self::Name(name.clone()),
+            Filter::Regex(re) => Self::Regex(Regex::new(re).map_err(|e| anyhow!("Invalid regex '{}': {}", re, e))?),
+            Filter::Group(group) => Self::Group(group.clone()),
+        })
+    }
+
+    fn matches(&self, id: u64, name: &str, groups: &[String]) -> bool {
+        match self {
+            Self::Id(i) => *i == id,
+            Self::Name(n) => n == name,
+            Self::Regex(re) => re.is_match(name),
+            Self::Group(g) => groups.contains(g),
+        }
+    }
+}
+
+pub(crate) struct ChannelFilter {
+    include: Vec<Compiled>,
+    exclude: Vec<Compiled>,
+}
+
+impl ChannelFilter {
+    pub(crate) fn keep(&self, id: u64, name: &str, groups: &[String]) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|f| f.matches(id, name, groups)))
+            && !self.exclude.iter().any(|f| f.matches(id, name, groups))
+    }
+}
+
+impl Profile {
+    pub(crate) fn filter(&self) -> Result<ChannelFilter> {
+        Ok(ChannelFilter {
+            include: self.include.iter().map(Compiled::new).collect::<Result<_>>()?,
+            exclude: self.exclude.iter().map(Compiled::new).collect::<Result<_>>()?,
+        })
+    }
+
+    pub(crate) fn base(&self, scheme: &str, host: &str) -> String {
+        format!(
+            "{}://{}",
+            self.scheme.as_deref().unwrap_or(scheme),
+            self.host.as_deref().unwrap_or(host)
+        )
+    }
+}
+
+impl Order {
+    // key 返回 (频道号, 名称)，排序是稳定的
+    pub(crate) fn sort<T>(self, items: &mut [T], key: impl Fn(&T) -> (u64, String)) {
+        match self {
+            Order::Lineup => {}
+            Order::Number => items.sort_by_cached_key(|t| key(t).0),
+            Order::Name => items.sort_by_cached_key(|t| key(t).1),
+            Order::UhdFirst => items.sort_by_cached_key(|t| {
+                let name = key(t).1;
+                !(name.contains("4K") || name.contains("8K") || name.contains("超高清"))
+            }),
+        }
+    }
+}
+
+pub(crate) fn profiles() -> Vec<Profile> {
+    PROFILES.lock().map(|p| p.clone()).unwrap_or_default()
+}
+
+pub(crate) fn get(name: &str) -> Option<Profile> {
+    PROFILES.lock().ok()?.iter().find(|p| p.name == name).cloned()
+}
+
+// 名称用在地址中，只允许字母、数字、- 和 _
+fn validate(profiles: &[Profile]) -> Result<()> {
+    let mut names = HashSet::new();
+    for profile in profiles {
+        if profile.name.is_empty()
+            || !profile.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(anyhow!("Invalid profile name '{}'", profile.name));
+        }
+        if !names.insert(profile.name.as_str()) {
+            return Err(anyhow!("Duplicate profile name '{}'", profile.name));
+        }
+        profile.filter()?;
+    }
+    Ok(())
+}
+
+pub(crate) fn set(profiles: Vec<Profile>) -> Result<()> {
+    validate(&profiles)?;
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(PROFILES_FILE)?;
+    serde_json::to_writer_pretty(file, &profiles)?;
+    if let Ok(mut current) = PROFILES.lock() {
+        *current = profiles;
+    }
+    Ok(())
+}
+
+pub(crate) fn load() -> Result<()> {
+    if Path::new(PROFILES_FILE).exists() {
+        let profiles: Vec<Profile> = serde_json::from_reader(File::open(PROFILES_FILE)?)?;
+        validate(&profiles)?;
+        log::info!("Loaded {} playlist profiles", profiles.len());
+        if let Ok(mut current) = PROFILES.lock() {
+            *current = profiles;
+        }
+    }
+    Ok(())
+}
@@ -1,10 +1,13 @@
-use std::net::{IpAddr, Ipv4Addr, SocketAddrV4};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddrV4},
+    time::Duration,
+};
 
 use actix_web::web::Bytes;
 use anyhow::Result;
 use async_stream::stream;
 use futures_core::stream::Stream;
-use futures_util::stream::StreamExt;
+use futures_util::stream::{BoxStream, StreamExt};
 use local_ip_address::list_afinet_netifas;
 use log::{error, info};
 use reqwest::Url;
@@ -15,6 +18,54 @@ use tokio_util::bytes::Buf;
 use tokio_util::codec::BytesCodec;
 use tokio_util::udp::UdpFramed;
 
+use crate::ts::{StreamInfo, TsAnalyzer};
+
+// 频道的一个上游来源
+#[derive(Clone)]
+pub(crate) enum Source {
+    Rtsp(String),
+    Udp(SocketAddrV4),
+    // 额外播放列表中的 HTTP 流
+    Http(String),
+}
+
+impl Source {
+    // 作为stream hub中的键
+    pub(crate) fn key(&self) -> String {
+        match self {
+            Source::Rtsp(url) => url.clone(),
+            Source::Udp(addr) => format!("udp://{}", addr),
+            Source::Http(url) => url.clone(),
+        }
+    }
+
+    pub(crate) fn open(&self, if_name: Option<String>) -> BoxStream<'static, Result<Bytes>> {
+        match self {
+            Source::Rtsp(url) => rtsp(url.clone(), if_name).boxed(),
+            Source::Udp(addr) => udp(*addr, if_name).boxed(),
+            Source::Http(url) => http(url.clone()).boxed(),
+        }
+    }
+}
+
+// 拉取一段时间的流并返回分析结果
+pub(crate) async fn probe<S>(upstream: S, duration: Duration) -> Result<StreamInfo>
+where
+    S: Stream<Item = Result<Bytes>>,
+{
+    let mut analyzer = TsAnalyzer::new();
+    let mut upstream = std::pin::pin!(upstream);
+    let deadline = tokio::time::Instant::now() + duration;
+    loop {
+        match tokio::time::timeout_at(deadline, upstream.next()).await {
+            Ok(Some(Ok(bytes))) => analyzer.push(&bytes),
+            Ok(Some(Err(e))) => return Err(e),
+            Ok(None) | Err(_) => break,
+        }
+    }
+    Ok(analyzer.info())
+}
+
 fn filter_reordered_seq(seq: &mut u16, next: u16) -> bool {
     let valid = seq.wrapping_add(3000);
     if *seq == 0
@@ -90,6 +141,19 @@ pub(crate) fn rtsp(url: String, if_name: Option<String>) -> impl Stream<Item = R
     }
 }
 
+// 额外播放列表的流一般不在 IPTV 网络中，不绑定网卡；只限制连接超时，流本身不超时
+pub(crate) fn http(url: String) -> impl Stream<Item = Result<Bytes>> {
+    stream! {
+        let client = reqwest::Client::builder().connect_timeout(Duration::from_secs(10)).build()?;
+        let mut response = client.get(&url).send().await?.error_for_status()?;
+        info!("HTTP stream connected: {}", url);
+        while let Some(chunk) = response.chunk().await? {
+            yield Ok(chunk);
+        }
+        info!("HTTP stream ended: {}", url);
+    }
+}
+
 pub(crate) fn udp(
     multi_addr: SocketAddrV4,
     if_name: Option<String>,
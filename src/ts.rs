@@ -0,0 +1,858 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+pub(crate) const TS_PACKET_SIZE: usize = 188;
+pub(crate) const TS_SYNC_BYTE: u8 = 0x47;
+const PID_PAT: u16 = 0x0000;
+const PID_SDT: u16 = 0x0011;
+const PID_NULL: u16 = 0x1fff;
+// 视频PES只缓存开头这么多字节用于查找SPS
+const PES_SCAN_LIMIT: usize = 64 * 1024;
+
+#[derive(Serialize, Clone)]
+pub(crate) struct ElementaryStream {
+    pub(crate) pid: u16,
+    pub(crate) stream_type: u8,
+    pub(crate) codec: String,
+    pub(crate) language: Option<String>,
+    pub(crate) width: Option<u32>,
+    pub(crate) height: Option<u32>,
+}
+
+#[derive(Serialize, Clone)]
+pub(crate) struct ProgramInfo {
+    pub(crate) program_number: u16,
+    pub(crate) pmt_pid: u16,
+    pub(crate) pcr_pid: Option<u16>,
+    pub(crate) service_name: Option<String>,
+    pub(crate) provider_name: Option<String>,
+    pub(crate) streams: Vec<ElementaryStream>,
+}
+
+#[derive(Serialize, Clone)]
+pub(crate) struct StreamInfo {
+    pub(crate) programs: Vec<ProgramInfo>,
+    pub(crate) packets: u64,
+    pub(crate) bytes: u64,
+    pub(crate) bitrate_kbps: u64,
+    pub(crate) average_bitrate_kbps: u64,
+    pub(crate) cc_errors: u64,
+    pub(crate) sync_errors: u64,
+    pub(crate) duration_ms: u64,
+    pub(crate) last_packet_at: Option<i64>,
+}
+
+// 单个GOP缓存的上限，超过后放弃缓存直到下一个关键帧
+const GOP_CACHE_LIMIT: usize = 16 * 1024 * 1024;
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum PacketKind {
+    Pat,
+    Pmt(u16),
+    Keyframe,
+    Other,
+}
+
+#[derive(Default)]
+struct SectionBuffer {
+    data: Vec<u8>,
+    started: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum VideoCodec {
+    H264,
+    Hevc,
+}
+
+pub(crate) struct TsAnalyzer {
+    remainder: Vec<u8>,
+    pat: BTreeMap<u16, u16>,
+    programs: BTreeMap<u16, ProgramInfo>,
+    services: HashMap<u16, (Option<String>, Option<String>)>,
+    sections: HashMap<u16, SectionBuffer>,
+    continuity: HashMap<u16, u8>,
+    video_pes: HashMap<u16, Vec<u8>>,
+    started: Instant,
+    window_started: Instant,
+    window_bytes: u64,
+    bitrate_kbps: u64,
+    packets: u64,
+    bytes: u64,
+    cc_errors: u64,
+    sync_errors: u64,
+    last_packet_at: Option<i64>,
+}
+
+impl Default for TsAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TsAnalyzer {
+    pub(crate) fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            remainder: Vec::new(),
+            pat: BTreeMap::new(),
+            programs: BTreeMap::new(),
+            services: HashMap::new(),
+            sections: HashMap::new(),
+            continuity: HashMap::new(),
+            video_pes: HashMap::new(),
+            started: now,
+            window_started: now,
+            window_bytes: 0,
+            bitrate_kbps: 0,
+            packets: 0,
+            bytes: 0,
+            cc_errors: 0,
+            sync_errors: 0,
+            last_packet_at: None,
+        }
+    }
+
+    // 输入一段RTP负载（通常是7个TS包），不要求按包边界对齐
+    pub(crate) fn push(&mut self, data: &[u8]) {
+        self.bytes += data.len() as u64;
+        self.window_bytes += data.len() as u64;
+        self.last_packet_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|d| d.as_millis() as i64);
+
+        let elapsed = self.window_started.elapsed();
+        if elapsed.as_millis() >= 1000 {
+            self.bitrate_kbps = self.window_bytes * 8 / elapsed.as_millis() as u64;
+            self.window_started = Instant::now();
+            self.window_bytes = 0;
+        }
+
+        let mut buf = std::mem::take(&mut self.remainder);
+        buf.extend_from_slice(data);
+
+        let mut offset = 0;
+        while buf.len() - offset >= TS_PACKET_SIZE {
+            if buf[offset] != TS_SYNC_BYTE {
+                self.sync_errors += 1;
+                match buf[offset..].iter().position(|&b| b == TS_SYNC_BYTE) {
+                    Some(pos) => {
+                        offset += pos;
+                        continue;
+                    }
+                    None => {
+                        offset = buf.len();
+                        break;
+                    }
+                }
+            }
+            let packet: [u8; TS_PACKET_SIZE] = buf[offset..offset + TS_PACKET_SIZE]
+                .try_into()
+                .unwrap_or([0; TS_PACKET_SIZE]);
+            self.handle_packet(&packet);
+            offset += TS_PACKET_SIZE;
+        }
+        buf.drain(..offset);
+        self.remainder = buf;
+    }
+
+    pub(crate) fn info(&self) -> StreamInfo {
+        let elapsed_ms = self.started.elapsed().as_millis() as u64;
+        let mut programs: Vec<ProgramInfo> = self.programs.values().cloned().collect();
+        for program in programs.iter_mut() {
+            if let Some((provider, name)) = self.services.get(&program.program_number) {
+                program.provider_name = provider.clone();
+                program.service_name = name.clone();
+            }
+        }
+        StreamInfo {
+            programs,
+            packets: self.packets,
+            bytes: self.bytes,
+            bitrate_kbps: self.bitrate_kbps,
+            average_bitrate_kbps: (self.bytes * 8).checked_div(elapsed_ms).unwrap_or(0),
+            cc_errors: self.cc_errors,
+            sync_errors: self.sync_errors,
+            duration_ms: elapsed_ms,
+            last_packet_at: self.last_packet_at,
+        }
+    }
+
+    // 判断单个TS包的类型，供GOP缓存使用
+    pub(crate) fn classify(&self, packet: &[u8; TS_PACKET_SIZE]) -> PacketKind {
+        let Some(header) = PacketHeader::parse(packet) else {
+            return PacketKind::Other;
+        };
+        if header.pid == PID_PAT {
+            return PacketKind::Pat;
+        }
+        if self.pat.values().any(|&p| p == header.pid) {
+            return PacketKind::Pmt(header.pid);
+        }
+        if !header.payload_unit_start {
+            return PacketKind::Other;
+        }
+        let Some(codec) = self.video_codec(header.pid) else {
+            return PacketKind::Other;
+        };
+        if header.random_access {
+            return PacketKind::Keyframe;
+        }
+        let Some(pes) = header.payload(packet) else {
+            return PacketKind::Other;
+        };
+        if pes.len() < 9 || pes[..3] != [0, 0, 1] {
+            return PacketKind::Other;
+        }
+        let es = &pes[(9 + pes[8] as usize).min(pes.len())..];
+        let keyframe = nal_units(es).any(|nal| match codec {
+            // IDR或SPS
+            VideoCodec::H264 => nal.first().is_some_and(|b| matches!(b & 0x1f, 5 | 7)),
+            // IRAP或VPS/SPS
+            VideoCodec::Hevc => nal
+                .first()
+                .is_some_and(|b| matches!((b >> 1) & 0x3f, 16..=21 | 32 | 33)),
+        });
+        if keyframe {
+            PacketKind::Keyframe
+        } else {
+            PacketKind::Other
+        }
+    }
+
+    fn handle_packet(&mut self, packet: &[u8; TS_PACKET_SIZE]) {
+        self.packets += 1;
+        let Some(header) = PacketHeader::parse(packet) else {
+            return;
+        };
+        if header.pid == PID_NULL {
+            return;
+        }
+
+        if header.has_payload {
+            if let Some(&last) = self.continuity.get(&header.pid) {
+                let expected = (last + 1) & 0x0f;
+                if header.continuity_counter != expected
+                    && header.continuity_counter != last
+                    && !header.discontinuity
+                {
+                    self.cc_errors += 1;
+                }
+            }
+            self.continuity.insert(header.pid, header.continuity_counter);
+        }
+
+        let Some(payload) = header.payload(packet) else {
+            return;
+        };
+
+        if header.pid == PID_PAT || header.pid == PID_SDT || self.pat.values().any(|&p| p == header.pid) {
+            self.handle_section_payload(header.pid, header.payload_unit_start, payload);
+        } else if let Some(codec) = self.video_codec(header.pid) {
+            self.handle_video_payload(header.pid, codec, header.payload_unit_start, payload);
+        }
+    }
+
+    fn handle_section_payload(&mut self, pid: u16, unit_start: bool, payload: &[u8]) {
+        let mut completed = Vec::new();
+        {
+            let buffer = self.sections.entry(pid).or_default();
+            let mut rest = payload;
+            if unit_start {
+                let Some((&pointer, tail)) = rest.split_first() else {
+                    return;
+                };
+                let pointer = pointer as usize;
+                if pointer > tail.len() {
+                    buffer.data.clear();
+                    buffer.started = false;
+                    return;
+                }
+                if buffer.started {
+                    buffer.data.extend_from_slice(&tail[..pointer]);
+                    if let Some(section) = take_section(&mut buffer.data) {
+                        completed.push(section);
+                    }
+                }
+                buffer.data.clear();
+                buffer.started = true;
+                rest = &tail[pointer..];
+            } else if !buffer.started {
+                return;
+            }
+
+            buffer.data.extend_from_slice(rest);
+            // 同一个包里可能连续放了多个section
+            while let Some(section) = take_section(&mut buffer.data) {
+                completed.push(section);
+                if buffer.data.first().is_none_or(|&b| b == 0xff) {
+                    buffer.data.clear();
+                    buffer.started = false;
+                    break;
+                }
+            }
+        }
+
+        for section in completed {
+            self.handle_section(pid, &section);
+        }
+    }
+
+    fn handle_section(&mut self, pid: u16, section: &[u8]) {
+        if section.len() < 12 {
+            return;
+        }
+        // 最后4字节是CRC
+        let body = &section[..section.len() - 4];
+        match (pid, section[0]) {
+            (PID_PAT, 0x00) => self.parse_pat(body),
+            (PID_SDT, 0x42) => self.parse_sdt(body),
+            (_, 0x02) => self.parse_pmt(pid, body),
+            _ => {}
+        }
+    }
+
+    fn parse_pat(&mut self, body: &[u8]) {
+        if body.len() < 8 {
+            return;
+        }
+        let mut pat = BTreeMap::new();
+        for entry in body[8..].chunks_exact(4) {
+            let program_number = u16::from_be_bytes([entry[0], entry[1]]);
+            let pmt_pid = u16::from_be_bytes([entry[2], entry[3]]) & 0x1fff;
+            // 节目号0是NIT
+            if program_number != 0 {
+                pat.insert(program_number, pmt_pid);
+            }
+        }
+        if pat != self.pat {
+            self.programs.retain(|number, _| pat.contains_key(number));
+            self.pat = pat;
+        }
+    }
+
+    fn parse_pmt(&mut self, pid: u16, body: &[u8]) {
+        // 截断的PMT，上游数据不可信
+        if body.len() < 12 {
+            return;
+        }
+        let program_number = u16::from_be_bytes([body[3], body[4]]);
+        let pcr_pid = u16::from_be_bytes([body[8], body[9]]) & 0x1fff;
+        let program_info_length = (u16::from_be_bytes([body[10], body[11]]) & 0x0fff) as usize;
+
+        let old_streams = self
+            .programs
+            .get(&program_number)
+            .map(|p| p.streams.clone())
+            .unwrap_or_default();
+
+        let mut streams = Vec::new();
+        let mut offset = 12 + program_info_length;
+        while offset + 5 <= body.len() {
+            let stream_type = body[offset];
+            let es_pid = u16::from_be_bytes([body[offset + 1], body[offset + 2]]) & 0x1fff;
+            let es_info_length =
+                (u16::from_be_bytes([body[offset + 3], body[offset + 4]]) & 0x0fff) as usize;
+            let end = (offset + 5 + es_info_length).min(body.len());
+            let descriptors = &body[offset + 5..end];
+            offset = end;
+
+            let previous = old_streams.iter().find(|s| s.pid == es_pid && s.stream_type == stream_type);
+            streams.push(ElementaryStream {
+                pid: es_pid,
+                stream_type,
+                codec: codec_name(stream_type, descriptors).to_string(),
+                language: language_from_descriptors(descriptors),
+                width: previous.and_then(|s| s.width),
+                height: previous.and_then(|s| s.height),
+            });
+        }
+
+        self.programs.insert(
+            program_number,
+            ProgramInfo {
+                program_number,
+                pmt_pid: pid,
+                pcr_pid: (pcr_pid != PID_NULL).then_some(pcr_pid),
+                service_name: None,
+                provider_name: None,
+                streams,
+            },
+        );
+    }
+
+    fn parse_sdt(&mut self, body: &[u8]) {
+        let mut offset = 11;
+        while offset + 5 <= body.len() {
+            let service_id = u16::from_be_bytes([body[offset], body[offset + 1]]);
+            let loop_length =
+                (u16::from_be_bytes([body[offset + 3], body[offset + 4]]) & 0x0fff) as usize;
+            let end = (offset + 5 + loop_length).min(body.len());
+            let mut descriptors = &body[offset + 5..end];
+            offset = end;
+
+            while descriptors.len() >= 2 {
+                let tag = descriptors[0];
+                let length = (descriptors[1] as usize).min(descriptors.len() - 2);
+                let data = &descriptors[2..2 + length];
+                descriptors = &descriptors[2 + length..];
+                // service_descriptor
+                if tag != 0x48 || data.len() < 3 {
+                    continue;
+                }
+                let provider_length = data[1] as usize;
+                let Some(provider) = data.get(2..2 + provider_length) else {
+                    continue;
+                };
+                let name = data
+                    .get(2 + provider_length)
+                    .and_then(|&len| data.get(3 + provider_length..3 + provider_length + len as usize));
+                self.services.insert(
+                    service_id,
+                    (dvb_string(provider), name.and_then(dvb_string)),
+                );
+            }
+        }
+    }
+
+    fn video_codec(&self, pid: u16) -> Option<VideoCodec> {
+        self.programs
+            .values()
+            .flat_map(|p| p.streams.iter())
+            .find(|s| s.pid == pid)
+            .and_then(|s| match s.stream_type {
+                0x1b => Some(VideoCodec::H264),
+                0x24 => Some(VideoCodec::Hevc),
+                _ => None,
+            })
+    }
+
+    fn handle_video_payload(&mut self, pid: u16, codec: VideoCodec, unit_start: bool, payload: &[u8]) {
+        let resolved = self
+            .programs
+            .values()
+            .flat_map(|p| p.streams.iter())
+            .any(|s| s.pid == pid && s.width.is_some());
+        if resolved {
+            self.video_pes.remove(&pid);
+            return;
+        }
+
+        if unit_start {
+            if let Some(pes) = self.video_pes.remove(&pid) {
+                self.scan_sps(pid, codec, &pes);
+            }
+            self.video_pes.insert(pid, payload.to_vec());
+        } else if let Some(pes) = self.video_pes.get_mut(&pid) {
+            if pes.len() < PES_SCAN_LIMIT {
+                pes.extend_from_slice(payload);
+            }
+        }
+    }
+
+    fn scan_sps(&mut self, pid: u16, codec: VideoCodec, pes: &[u8]) {
+        // PES头：00 00 01 stream_id len(2) flags(2) header_data_length
+        if pes.len() < 9 || pes[..3] != [0, 0, 1] {
+            return;
+        }
+        let es = &pes[(9 + pes[8] as usize).min(pes.len())..];
+        let Some((width, height)) = nal_units(es).find_map(|nal| match codec {
+            VideoCodec::H264 if nal.first().map(|b| b & 0x1f) == Some(7) => parse_h264_sps(nal),
+            VideoCodec::Hevc if nal.first().map(|b| (b >> 1) & 0x3f) == Some(33) => parse_hevc_sps(nal),
+            _ => None,
+        }) else {
+            return;
+        };
+        for stream in self.programs.values_mut().flat_map(|p| p.streams.iter_mut()) {
+            if stream.pid == pid {
+                stream.width = Some(width);
+                stream.height = Some(height);
+            }
+        }
+    }
+}
+
+struct PacketHeader {
+    pid: u16,
+    payload_unit_start: bool,
+    has_payload: bool,
+    has_adaptation: bool,
+    discontinuity: bool,
+    random_access: bool,
+    continuity_counter: u8,
+}
+
+impl PacketHeader {
+    fn parse(packet: &[u8; TS_PACKET_SIZE]) -> Option<Self> {
+        // transport_error_indicator
+        if packet[1] & 0x80 != 0 {
+            return None;
+        }
+        let has_adaptation = packet[3] & 0x20 != 0;
+        Some(Self {
+            pid: u16::from_be_bytes([packet[1], packet[2]]) & 0x1fff,
+            payload_unit_start: packet[1] & 0x40 != 0,
+            has_payload: packet[3] & 0x10 != 0,
+            has_adaptation,
+            discontinuity: has_adaptation && packet[4] > 0 && packet[5] & 0x80 != 0,
+            random_access: has_adaptation && packet[4] > 0 && packet[5] & 0x40 != 0,
+            continuity_counter: packet[3] & 0x0f,
+        })
+    }
+
+    fn payload<'a>(&self, packet: &'a [u8; TS_PACKET_SIZE]) -> Option<&'a [u8]> {
+        if !self.has_payload {
+            return None;
+        }
+        let start = if self.has_adaptation {
+            5 + packet[4] as usize
+        } else {
+            4
+        };
+        packet.get(start..)
+    }
+}
+
+// 缓存最近的PAT/PMT和最后一个关键帧以来的数据，新客户端连接时先发送，实现快速换台
+#[derive(Default)]
+pub(crate) struct GopCache {
+    pat: Option<Vec<u8>>,
+    pmt: BTreeMap<u16, Vec<u8>>,
+    gop: Vec<u8>,
+    keyframe_seen: bool,
+}
+
+impl GopCache {
+    pub(crate) fn push(&mut self, analyzer: &TsAnalyzer, data: &[u8]) {
+        if !data.len().is_multiple_of(TS_PACKET_SIZE) || data.first() != Some(&TS_SYNC_BYTE) {
+            if self.keyframe_seen {
+                self.gop.extend_from_slice(data);
+            }
+            return;
+        }
+        for packet in data.chunks_exact(TS_PACKET_SIZE) {
+            let Ok(packet) = <&[u8; TS_PACKET_SIZE]>::try_from(packet) else {
+                continue;
+            };
+            match analyzer.classify(packet) {
+                PacketKind::Pat => self.pat = Some(packet.to_vec()),
+                PacketKind::Pmt(pid) => {
+                    self.pmt.insert(pid, packet.to_vec());
+                }
+                PacketKind::Keyframe => {
+                    self.gop.clear();
+                    self.keyframe_seen = true;
+                    self.gop.extend_from_slice(packet);
+                }
+                PacketKind::Other if self.keyframe_seen => self.gop.extend_from_slice(packet),
+                PacketKind::Other => {}
+            }
+        }
+        if self.gop.len() > GOP_CACHE_LIMIT {
+            self.gop = Vec::new();
+            self.keyframe_seen = false;
+        }
+    }
+
+    // 新订阅者需要先收到的数据：PAT、PMT、最近一个GOP
+    pub(crate) fn burst(&self) -> Option<Vec<u8>> {
+        if !self.keyframe_seen {
+            return None;
+        }
+        let pat = self.pat.as_ref()?;
+        let mut burst = Vec::with_capacity(pat.len() + self.pmt.len() * TS_PACKET_SIZE + self.gop.len());
+        burst.extend_from_slice(pat);
+        for pmt in self.pmt.values() {
+            burst.extend_from_slice(pmt);
+        }
+        burst.extend_from_slice(&self.gop);
+        Some(burst)
+    }
+}
+
+// 从缓冲区头部取出一个完整的section
+fn take_section(buf: &mut Vec<u8>) -> Option<Vec<u8>> {
+    if buf.len() < 3 || buf[0] == 0xff {
+        return None;
+    }
+    let length = 3 + (u16::from_be_bytes([buf[1], buf[2]]) & 0x0fff) as usize;
+    if buf.len() < length {
+        return None;
+    }
+    Some(buf.drain(..length).collect())
+}
+
+fn codec_name(stream_type: u8, descriptors: &[u8]) -> &'static str {
+    match stream_type {
+        0x01 => "MPEG-1 Video",
+        0x02 => "MPEG-2 Video",
+        0x03 | 0x04 => "MP2",
+        0x0f => "AAC",
+        0x11 => "AAC-LATM",
+        0x1b => "H.264",
+        0x24 => "HEVC",
+        0x42 => "AVS+",
+        0x81 => "AC-3",
+        0x87 => "E-AC-3",
+        0x06 => {
+            let mut rest = descriptors;
+            while rest.len() >= 2 {
+                let length = (rest[1] as usize).min(rest.len() - 2);
+                match rest[0] {
+                    0x6a => return "AC-3",
+                    0x7a => return "E-AC-3",
+                    0x05 if &rest[2..2 + length] == b"AC-3" => return "AC-3",
+                    0x05 if &rest[2..2 + length] == b"EAC3" => return "E-AC-3",
+                    0x59 => return "DVB Subtitle",
+                    0x56 => return "Teletext",
+                    _ => {}
+                }
+                rest = &rest[2 + length..];
+            }
+            "Private"
+        }
+        _ => "Unknown",
+    }
+}
+
+fn language_from_descriptors(mut descriptors: &[u8]) -> Option<String> {
+    while descriptors.len() >= 2 {
+        let length = (descriptors[1] as usize).min(descriptors.len() - 2);
+        // ISO_639_language_descriptor
+        if descriptors[0] == 0x0a && length >= 3 {
+            return Some(String::from_utf8_lossy(&descriptors[2..5]).to_string());
+        }
+        descriptors = &descriptors[2 + length..];
+    }
+    None
+}
+
+fn dvb_string(data: &[u8]) -> Option<String> {
+    // 跳过DVB字符集选择字节
+    let data = match data.first() {
+        Some(0x10) => data.get(3..).unwrap_or_default(),
+        Some(0x1f) => data.get(2..).unwrap_or_default(),
+        Some(&b) if b < 0x20 => &data[1..],
+        _ => data,
+    };
+    let s = String::from_utf8_lossy(data).trim().to_string();
+    (!s.is_empty()).then_some(s)
+}
+
+// 按起始码切分Annex B格式的NAL单元
+pub(crate) fn nal_units(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut starts = Vec::new();
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            starts.push(i + 3);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    let ends: Vec<usize> = starts
+        .iter()
+        .skip(1)
+        .map(|&s| {
+            let mut end = s - 3;
+            while end > 0 && data[end - 1] == 0 {
+                end -= 1;
+            }
+            end
+        })
+        .chain(std::iter::once(data.len()))
+        .collect();
+    starts
+        .into_iter()
+        .zip(ends)
+        .filter(|(s, e)| s < e)
+        .map(move |(s, e)| &data[s..e])
+}
+
+struct BitReader {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl BitReader {
+    // 去掉防竞争字节 00 00 03
+    fn new(nal: &[u8]) -> Self {
+        let mut data = Vec::with_capacity(nal.len());
+        let mut zeros = 0;
+        for &b in nal {
+            if zeros >= 2 && b == 3 {
+                zeros = 0;
+                continue;
+            }
+            zeros = if b == 0 { zeros + 1 } else { 0 };
+            data.push(b);
+        }
+        Self { data, pos: 0 }
+    }
+
+    fn bit(&mut self) -> Option<u32> {
+        let byte = *self.data.get(self.pos / 8)?;
+        let bit = (byte >> (7 - self.pos % 8)) & 1;
+        self.pos += 1;
+        Some(bit as u32)
+    }
+
+    fn bits(&mut self, n: usize) -> Option<u32> {
+        let mut value = 0;
+        for _ in 0..n {
+            value = (value << 1) | self.bit()?;
+        }
+        Some(value)
+    }
+
+    fn skip(&mut self, n: usize) -> Option<()> {
+        self.pos += n;
+        (self.pos <= self.data.len() * 8).then_some(())
+    }
+
+    fn ue(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        while self.bit()? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return None;
+            }
+        }
+        Some((1u32 << zeros) - 1 + self.bits(zeros)?)
+    }
+
+    fn se(&mut self) -> Option<i32> {
+        let v = self.ue()?;
+        Some(if v % 2 == 1 { v.div_ceil(2) as i32 } else { -((v / 2) as i32) })
+    }
+}
+
+fn parse_h264_sps(nal: &[u8]) -> Option<(u32, u32)> {
+    let mut r = BitReader::new(nal);
+    r.skip(8)?;
+    let profile_idc = r.bits(8)?;
+    r.skip(16)?;
+    r.ue()?;
+    let mut chroma_format_idc = 1;
+    if matches!(profile_idc, 100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135) {
+        chroma_format_idc = r.ue()?;
+        if chroma_format_idc == 3 {
+            r.skip(1)?;
+        }
+        r.ue()?;
+        r.ue()?;
+        r.skip(1)?;
+        if r.bit()? == 1 {
+            let count = if chroma_format_idc == 3 { 12 } else { 8 };
+            for i in 0..count {
+                if r.bit()? == 1 {
+                    let size = if i < 6 { 16 } else { 64 };
+                    let (mut last, mut next) = (8i32, 8i32);
+                    for _ in 0..size {
+                        if next != 0 {
+                            next = last.wrapping_add(r.se()?).rem_euclid(256);
+                        }
+                        if next != 0 {
+                            last = next;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    r.ue()?;
+    match r.ue()? {
+        0 => {
+            r.ue()?;
+        }
+        1 => {
+            r.skip(1)?;
+            r.se()?;
+            r.se()?;
+            for _ in 0..r.ue()? {
+                r.se()?;
+            }
+        }
+        _ => {}
+    }
+    r.ue()?;
+    r.skip(1)?;
+    let width_mbs = r.ue()? + 1;
+    let height_units = r.ue()? + 1;
+    let frame_mbs_only = r.bit()?;
+    if frame_mbs_only == 0 {
+        r.skip(1)?;
+    }
+    r.skip(1)?;
+    let (mut left, mut right, mut top, mut bottom) = (0, 0, 0, 0);
+    if r.bit()? == 1 {
+        left = r.ue()?;
+        right = r.ue()?;
+        top = r.ue()?;
+        bottom = r.ue()?;
+    }
+    let (crop_x, crop_y): (u32, u32) = match chroma_format_idc {
+        0 => (1, 2 - frame_mbs_only),
+        1 => (2, 2 * (2 - frame_mbs_only)),
+        2 => (2, 2 - frame_mbs_only),
+        _ => (1, 2 - frame_mbs_only),
+    };
+    // 数值来自码流，异常时放弃而不是溢出
+    let width = width_mbs
+        .checked_mul(16)?
+        .checked_sub(crop_x.checked_mul(left.saturating_add(right))?)?;
+    let height = (2 - frame_mbs_only)
+        .checked_mul(height_units)?
+        .checked_mul(16)?
+        .checked_sub(crop_y.checked_mul(top.saturating_add(bottom))?)?;
+    Some((width, height))
+}
+
+fn parse_hevc_sps(nal: &[u8]) -> Option<(u32, u32)> {
+    let mut r = BitReader::new(nal);
+    r.skip(16)?;
+    r.skip(4)?;
+    let max_sub_layers_minus1 = r.bits(3)? as usize;
+    r.skip(1)?;
+    // profile_tier_level中的general部分
+    r.skip(96)?;
+    let mut sub_layer_flags = Vec::with_capacity(max_sub_layers_minus1);
+    for _ in 0..max_sub_layers_minus1 {
+        sub_layer_flags.push((r.bit()?, r.bit()?));
+    }
+    if max_sub_layers_minus1 > 0 {
+        r.skip(2 * (8 - max_sub_layers_minus1))?;
+    }
+    for (profile_present, level_present) in sub_layer_flags {
+        if profile_present == 1 {
+            r.skip(88)?;
+        }
+        if level_present == 1 {
+            r.skip(8)?;
+        }
+    }
+    r.ue()?;
+    let chroma_format_idc = r.ue()?;
+    if chroma_format_idc == 3 {
+        r.skip(1)?;
+    }
+    let mut width = r.ue()?;
+    let mut height = r.ue()?;
+    if r.bit()? == 1 {
+        let (sub_width, sub_height): (u32, u32) = match chroma_format_idc {
+            1 => (2, 2),
+            2 => (2, 1),
+            _ => (1, 1),
+        };
+        let (left, right, top, bottom) = (r.ue()?, r.ue()?, r.ue()?, r.ue()?);
+        width = width.checked_sub(sub_width.checked_mul(left.saturating_add(right))?)?;
+        height = height.checked_sub(sub_height.checked_mul(top.saturating_add(bottom))?)?;
+    }
+    Some((width, height))
+}
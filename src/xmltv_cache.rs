@@ -0,0 +1,147 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use actix_files::NamedFile;
+use actix_web::{http::header::ContentEncoding, mime, web::Bytes};
+use anyhow::Result;
+use flate2::{write::GzEncoder, Compression};
+use tokio::sync::mpsc;
+
+use crate::iptv::Program;
+use crate::xmltv_parser::parse_epg_from_xmltv;
+
+// 包含隐藏频道的完整节目单，供节目单接口解析
+const XMLTV_CACHE_FILE: &str = "xmltv_cache.xml";
+// 去掉隐藏频道的节目单，/epg.xml 和 /epg.xml.gz 直接返回
+const XMLTV_VISIBLE_FILE: &str = "xmltv_visible.xml";
+const XMLTV_GZ_FILE: &str = "xmltv_visible.xml.gz";
+// 流式输出时每块的大小
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// 从缓存文件解析出的节目单（含映射和外部源），缓存更新时清空
+type ParsedEpg = Arc<HashMap<u64, Vec<Program>>>;
+static PARSED_EPG: LazyLock<Mutex<Option<ParsedEpg>>> = LazyLock::new(|| Mutex::new(None));
+
+// 同时写入两个输出
+struct Tee<A, B>(A, B);
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write_all(buf)?;
+        self.1.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()?;
+        self.1.flush()
+    }
+}
+
+// 按块发送到响应体，用于边生成边输出
+pub(crate) struct ChunkWriter {
+    tx: mpsc::Sender<Bytes>,
+    buf: Vec<u8>,
+}
+
+impl ChunkWriter {
+    pub(crate) fn new(tx: mpsc::Sender<Bytes>) -> Self {
+        Self { tx, buf: Vec::with_capacity(CHUNK_SIZE) }
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::replace(&mut self.buf, Vec::with_capacity(CHUNK_SIZE)));
+        // 客户端断开时停止生成
+        self.tx
+            .blocking_send(chunk)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client disconnected"))
+    }
+}
+
+impl Drop for ChunkWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+pub(crate) fn exists() -> bool {
+    Path::new(XMLTV_CACHE_FILE).exists() && Path::new(XMLTV_VISIBLE_FILE).exists()
+}
+
+// 生成XMLTV缓存：generate 的第一个输出为可见频道的版本（同时写出 gzip），第二个为完整版本；
+// 先写临时文件，完成后替换
+pub(crate) fn save(generate: impl FnOnce(&mut dyn Write, &mut dyn Write) -> Result<()>) -> Result<()> {
+    let tmp_full = format!("{}.tmp", XMLTV_CACHE_FILE);
+    let tmp_xml = format!("{}.tmp", XMLTV_VISIBLE_FILE);
+    let tmp_gz = format!("{}.tmp", XMLTV_GZ_FILE);
+
+    let mut full = BufWriter::new(File::create(&tmp_full)?);
+    let xml = BufWriter::new(File::create(&tmp_xml)?);
+    let gz = GzEncoder::new(BufWriter::new(File::create(&tmp_gz)?), Compression::default());
+    let mut tee = Tee(xml, gz);
+    generate(&mut tee, &mut full)?;
+    let Tee(mut xml, gz) = tee;
+    xml.flush()?;
+    gz.finish()?.flush()?;
+    full.flush()?;
+
+    fs::rename(tmp_full, XMLTV_CACHE_FILE)?;
+    fs::rename(tmp_xml, XMLTV_VISIBLE_FILE)?;
+    fs::rename(tmp_gz, XMLTV_GZ_FILE)?;
+    if let Ok(mut parsed) = PARSED_EPG.lock() {
+        *parsed = None;
+    }
+    Ok(())
+}
+
+// 可见频道的缓存文件，gzip 为 true 时返回压缩版本；ETag/Last-Modified 由 NamedFile 处理
+pub(crate) fn open(gzip: bool) -> io::Result<NamedFile> {
+    let file = if gzip {
+        NamedFile::open(XMLTV_GZ_FILE)?.set_content_encoding(ContentEncoding::Gzip)
+    } else {
+        NamedFile::open(XMLTV_VISIBLE_FILE)?
+    };
+    Ok(file.set_content_type(mime::TEXT_XML).disable_content_disposition())
+}
+
+// 压缩文件本身，用于 /epg.xml.gz
+pub(crate) fn open_gz() -> io::Result<NamedFile> {
+    Ok(NamedFile::open(XMLTV_GZ_FILE)?.set_content_type("application/gzip".parse().unwrap()))
+}
+
+pub(crate) fn load_epg() -> Result<HashMap<u64, Vec<Program>>> {
+    if !exists() {
+        return Ok(HashMap::new());
+    }
+    parse_epg_from_xmltv(BufReader::new(File::open(XMLTV_CACHE_FILE)?))
+}
+
+// 缓存的节目单，首次使用时解析
+pub(crate) fn epg() -> Result<ParsedEpg> {
+    if let Some(epg) = PARSED_EPG.lock().ok().and_then(|p| p.clone()) {
+        return Ok(epg);
+    }
+    let epg = Arc::new(load_epg()?);
+    if let Ok(mut parsed) = PARSED_EPG.lock() {
+        *parsed = Some(epg.clone());
+    }
+    Ok(epg)
+}
@@ -1,129 +1,130 @@
 use std::collections::HashMap;
-use std::io::Cursor;
+use std::io::Read;
 use xml::{reader::XmlEvent as XmlReadEvent, EventReader};
 use anyhow::{anyhow, Result};
 use log::debug;
 use chrono::TimeZone;
 use crate::Program;
 
+// 解析后的XMLTV文档，频道ID保持原样
+#[derive(Default)]
+pub struct XmltvDocument {
+    // 频道ID -> display-name 列表
+    pub channels: Vec<(String, Vec<String>)>,
+    pub programmes: HashMap<String, Vec<Program>>,
+}
+
 // 从XMLTV缓存中解析EPG数据
-pub fn parse_epg_from_xmltv(xmltv_content: &str) -> Result<HashMap<u64, Vec<Program>>> {
-    debug!("Parsing XMLTV content, length: {} bytes", xmltv_content.len());
-    if xmltv_content.len() < 100 {
-        debug!("XMLTV content preview: {}", xmltv_content);
-    }
-    
-    let reader = Cursor::new(xmltv_content);
+pub fn parse_epg_from_xmltv<R: Read>(reader: R) -> Result<HashMap<u64, Vec<Program>>> {
+    let epg_data = parse_xmltv(reader)?
+        .programmes
+        .into_iter()
+        // 去掉引号
+        .filter_map(|(id, programmes)| id.trim_matches('"').parse::<u64>().ok().map(|id| (id, programmes)))
+        .collect::<HashMap<_, _>>();
+    debug!("Parsed EPG data for {} channels", epg_data.len());
+    Ok(epg_data)
+}
+
+pub fn parse_xmltv<R: Read>(reader: R) -> Result<XmltvDocument> {
     let parser = EventReader::new(reader);
     
-    let mut epg_data = HashMap::new();
+    let mut document = XmltvDocument::default();
     let mut programme_count = 0;
-    let mut current_channel_id = None;
-    let mut current_program = None;
-    let mut in_title = false;
-    let mut in_desc = false;
-    let mut title = String::new();
-    let mut desc = String::new();
-    
+    // 正在解析的节目及当前文本元素
+    let mut current: Option<(String, Program)> = None;
+    let mut channel_open = false;
+    let mut element = String::new();
+    let mut text = String::new();
+    let mut in_rating = false;
+
     for event in parser {
         match event {
             Ok(XmlReadEvent::StartElement { name, attributes, .. }) => {
+                let attr = |key: &str| {
+                    attributes.iter()
+                        .find(|attr| attr.name.local_name == key)
+                        .map(|attr| attr.value.clone())
+                };
                 match name.local_name.as_str() {
+                    "channel" => {
+                        if let Some(id) = attr("id") {
+                            document.channels.push((id, vec![]));
+                        }
+                        channel_open = true;
+                    }
                     "programme" => {
                         programme_count += 1;
-                        // 解析频道ID
-                        let channel_attr = attributes.iter()
-                            .find(|attr| attr.name.local_name == "channel")
-                            .map(|attr| &attr.value);
-                        
-                        if let Some(channel_str) = channel_attr {
-                            // 去掉引号
-                            let channel_id = channel_str.trim_matches('"')
-                                .parse::<u64>()
-                                .unwrap_or(0);
-                            
-                            let start = attributes.iter()
-                                .find(|attr| attr.name.local_name == "start")
-                                .and_then(|attr| parse_xmltv_time(&attr.value).ok())
-                                .unwrap_or(0);
-                            
-                            let stop = attributes.iter()
-                                .find(|attr| attr.name.local_name == "stop")
-                                .and_then(|attr| parse_xmltv_time(&attr.value).ok())
-                                .unwrap_or(0);
-                            
-                            if channel_id > 0 && start > 0 && stop > 0 {
-                                current_channel_id = Some(channel_id);
-                                current_program = Some((start, stop));
-                                title.clear();
-                                desc.clear();
-                            } else {
-                                if programme_count <= 5 {
-                                    debug!("Skipping programme #{}: channel_id={}, start={}, stop={}", programme_count, channel_id, start, stop);
-                                }
-                            }
+                        let channel_id = attr("channel").unwrap_or_default();
+                        let start = attr("start").and_then(|t| parse_xmltv_time(&t).ok()).unwrap_or(0);
+                        let stop = attr("stop").and_then(|t| parse_xmltv_time(&t).ok()).unwrap_or(0);
+
+                        if !channel_id.is_empty() && start > 0 && stop > 0 {
+                            current = Some((channel_id, Program { start, stop, ..Default::default() }));
+                        } else if programme_count <= 5 {
+                            debug!("Skipping programme #{}: channel_id={}, start={}, stop={}", programme_count, channel_id, start, stop);
                         }
                     }
-                    "title" => {
-                        in_title = true;
-                        title.clear();
+                    "icon" => {
+                        if let Some((_, program)) = current.as_mut() {
+                            program.icon = attr("src");
+                        }
                     }
-                    "desc" => {
-                        in_desc = true;
-                        desc.clear();
+                    "rating" => in_rating = true,
+                    "episode-num" => {
+                        // onscreen 优先，xmltv_ns 转为从1开始的集数
+                        element = match attr("system").as_deref() {
+                            Some("xmltv_ns") => "episode-ns".to_string(),
+                            _ => "episode-num".to_string(),
+                        };
+                        text.clear();
+                    }
+                    local_name => {
+                        element = local_name.to_string();
+                        text.clear();
                     }
-                    _ => {}
                 }
             }
             Ok(XmlReadEvent::Characters(content)) => {
-                if in_title {
-                    title.push_str(&content);
-                } else if in_desc {
-                    desc.push_str(&content);
-                }
+                text.push_str(&content);
             }
             Ok(XmlReadEvent::EndElement { name }) => {
-                match name.local_name.as_str() {
-                    "title" => {
-                        in_title = false;
-                    }
-                    "desc" => {
-                        in_desc = false;
-                        // desc结束，保存节目
-                        if let Some((start, stop)) = current_program {
-                            if let Some(channel_id) = current_channel_id {
-                                let program = Program {
-                                    start,
-                                    stop,
-                                    title: title.clone(),
-                                    desc: if desc.is_empty() { title.clone() } else { desc.clone() },
-                                };
-                                epg_data.entry(channel_id).or_insert_with(Vec::new).push(program);
-                            }
+                let value = text.trim().to_string();
+                match (name.local_name.as_str(), current.as_mut()) {
+                    ("channel", _) => channel_open = false,
+                    ("display-name", None) if channel_open && !value.is_empty() => {
+                        if let Some((_, names)) = document.channels.last_mut() {
+                            names.push(value);
                         }
-                        current_program = None;
-                        current_channel_id = None;
                     }
-                    "programme" => {
-                        // 如果没有desc，只在title结束后保存
-                        if current_program.is_some() && !title.is_empty() {
-                            if let Some((start, stop)) = current_program {
-                                if let Some(channel_id) = current_channel_id {
-                                    let program = Program {
-                                        start,
-                                        stop,
-                                        title: title.clone(),
-                                        desc: title.clone(),
-                                    };
-                                    epg_data.entry(channel_id).or_insert_with(Vec::new).push(program);
-                                }
+                    ("programme", _) => {
+                        if let Some((channel_id, program)) = current.take() {
+                            if !program.title.is_empty() {
+                                document.programmes.entry(channel_id).or_insert_with(Vec::new).push(program);
                             }
                         }
-                        current_program = None;
-                        current_channel_id = None;
+                    }
+                    ("rating", _) => in_rating = false,
+                    (_, None) => {}
+                    (_, Some(_)) if value.is_empty() => {}
+                    ("title", Some((_, program))) if program.title.is_empty() => program.title = value,
+                    ("desc", Some((_, program))) => program.desc = value,
+                    ("category", Some((_, program))) => program.categories.push(value),
+                    ("value", Some((_, program))) if in_rating => program.rating = Some(value),
+                    ("episode-num", Some((_, program))) if element == "episode-num" => {
+                        program.episode = Some(value);
+                    }
+                    ("episode-num", Some((_, program))) if program.episode.is_none() => {
+                        program.episode = value
+                            .split('.')
+                            .nth(1)
+                            .and_then(|e| e.split('/').next())
+                            .and_then(|e| e.trim().parse::<u32>().ok())
+                            .map(|e| (e + 1).to_string());
                     }
                     _ => {}
                 }
+                text.clear();
             }
             Err(e) => {
                 debug!("XML parsing error: {}", e);
@@ -132,28 +133,31 @@ pub fn parse_epg_from_xmltv(xmltv_content: &str) -> Result<HashMap<u64, Vec<Prog
         }
     }
     
-    debug!("Parsed EPG data: {} programmes for {} channels", programme_count, epg_data.len());
-    Ok(epg_data)
+    debug!("Parsed XMLTV: {} programmes for {} channels", programme_count, document.programmes.len());
+    Ok(document)
 }
 
-// 解析XMLTV时间格式
+// 解析XMLTV时间：yyyyMMddHHmmss 后接可选时区（+0800、-05:00、Z、UTC），
+// 末尾的时分秒可以省略；没有时区时按配置的本地时区处理
 fn parse_xmltv_time(time_str: &str) -> Result<i64> {
-    // 去掉时区信息
-    let time_str = time_str.trim_end_matches(" +0800").trim();
-    
-    let format = "%Y%m%d%H%M%S";
-    match chrono::NaiveDateTime::parse_from_str(time_str, format) {
-        Ok(naive_dt) => {
-            // 假定为北京时间 (UTC+8)
-            let beijing_tz = chrono::FixedOffset::east_opt(8 * 3600).unwrap();
-            let dt = beijing_tz.from_local_datetime(&naive_dt).single()
-                .ok_or_else(|| anyhow!("Ambiguous local time"))?;
-            let timestamp_ms = dt.timestamp_millis();
-            Ok(timestamp_ms)
-        }
-        Err(e) => {
-            debug!("Failed to parse time '{}': {}", time_str, e);
-            Err(anyhow!("Failed to parse time '{}': {}", time_str, e))
-        }
+    let time_str = time_str.trim();
+    let split = time_str.find(|c: char| !c.is_ascii_digit()).unwrap_or(time_str.len());
+    let (digits, zone) = time_str.split_at(split);
+    if !(8..=14).contains(&digits.len()) {
+        return Err(anyhow!("Failed to parse time '{}'", time_str));
     }
-}
\ No newline at end of file
+    let naive_dt = chrono::NaiveDateTime::parse_from_str(&format!("{:0<14}", digits), "%Y%m%d%H%M%S")
+        .map_err(|e| anyhow!("Failed to parse time '{}': {}", time_str, e))?;
+
+    let zone = zone.trim();
+    let offset = if zone.is_empty() {
+        crate::catchup::local_offset()
+    } else if zone.eq_ignore_ascii_case("gmt") {
+        chrono::FixedOffset::east_opt(0).unwrap()
+    } else {
+        crate::catchup::parse_offset(zone).ok_or_else(|| anyhow!("Invalid time zone in '{}'", time_str))?
+    };
+    let dt = offset.from_local_datetime(&naive_dt).single()
+        .ok_or_else(|| anyhow!("Ambiguous local time"))?;
+    Ok(dt.timestamp_millis())
+}
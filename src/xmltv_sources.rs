@@ -0,0 +1,209 @@
+use std::{
+    collections::HashMap,
+    io::Read,
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use flate2::read::GzDecoder;
+use log::{debug, info, warn};
+use regex_lite::Regex;
+use reqwest::Client;
+
+use crate::args::Args;
+use crate::iptv::{Channel, Program};
+use crate::xmltv_parser::{parse_xmltv, XmltvDocument};
+
+const SOURCE_TTL: Duration = Duration::from_secs(3600);
+
+// 名称中去掉的清晰度后缀，长的在前
+const NAME_SUFFIXES: &[&str] = &["超高清", "高清", "超清", "标清", "频道", "FHD", "UHD", "HD", "4K", "8K"];
+
+// 外部XMLTV缓存，按 --extra-xmltv 的顺序（优先级）排列
+type Sources = (Instant, Arc<Vec<XmltvDocument>>);
+static SOURCES: LazyLock<Mutex<Option<Sources>>> = LazyLock::new(|| Mutex::new(None));
+
+static CCTV_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^CCTV(\d+K|\d+\+?)").unwrap());
+
+// 全角字符转半角
+fn to_half_width(c: char) -> char {
+    match c {
+        '\u{3000}' => ' ',
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+        _ => c,
+    }
+}
+
+// 规范化频道名，用于匹配外部XMLTV和同一频道的不同清晰度：CCTV-1综合高清、ＣＣＴＶ１ -> CCTV1
+pub(crate) fn normalize_name(name: &str) -> String {
+    let mut name = name
+        .chars()
+        .map(to_half_width)
+        .flat_map(char::to_uppercase)
+        // 保留 + 区分 CCTV5 和 CCTV5+
+        .filter(|c| c.is_alphanumeric() || *c == '+')
+        .collect::<String>();
+    if let Some(cap) = CCTV_RE.captures(&name) {
+        return format!("CCTV{}", &cap[1]);
+    }
+    for suffix in NAME_SUFFIXES {
+        name = name.replace(suffix, "");
+    }
+    name
+}
+
+async fn load_source(source: &str) -> Result<XmltvDocument> {
+    let bytes = if source.starts_with("http://") || source.starts_with("https://") {
+        // 不响应的来源不能让合并一直等待
+        let client = Client::builder()
+            .connect_timeout(Duration::from_secs(10))
+            .timeout(Duration::from_secs(120))
+            .build()?;
+        let response = client.get(source).send().await?.error_for_status()?;
+        response.bytes().await?.to_vec()
+    } else {
+        std::fs::read(source)?
+    };
+    // gzip 压缩的源
+    let bytes = if bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut decoded = Vec::new();
+        GzDecoder::new(bytes.as_slice()).read_to_end(&mut decoded)?;
+        decoded
+    } else {
+        bytes
+    };
+    parse_xmltv(bytes.as_slice())
+}
+
+async fn sources(args: &Args) -> Arc<Vec<XmltvDocument>> {
+    if let Ok(cache) = SOURCES.lock() {
+        if let Some((loaded_at, sources)) = cache.as_ref() {
+            if loaded_at.elapsed() < SOURCE_TTL {
+                return sources.clone();
+            }
+        }
+    }
+
+    let mut documents = Vec::new();
+    for source in args.extra_xmltv.iter() {
+        match load_source(source).await {
+            Ok(document) => {
+                info!("Loaded XMLTV source {}: {} channels", source, document.programmes.len());
+                documents.push(document);
+            }
+            Err(e) => {
+                warn!("Failed to load XMLTV source {}: {}", source, e);
+                documents.push(XmltvDocument::default());
+            }
+        }
+    }
+    let documents = Arc::new(documents);
+    if let Ok(mut cache) = SOURCES.lock() {
+        *cache = Some((Instant::now(), documents.clone()));
+    }
+    documents
+}
+
+// 把节目补到没有节目的时段，已有节目的时段保持不变
+fn fill_gaps(epg: &mut Vec<Program>, extra: &[Program]) -> usize {
+    epg.sort_by_key(|p| p.start);
+    let mut added = 0;
+    for programme in extra {
+        if programme.stop <= programme.start {
+            continue;
+        }
+        let idx = epg.partition_point(|p| p.start < programme.stop);
+        if idx > 0 && epg[idx - 1].stop > programme.start {
+            continue;
+        }
+        epg.insert(idx, programme.clone());
+        added += 1;
+    }
+    added
+}
+
+fn find_channels(channels: &[Channel], target: &str) -> Vec<usize> {
+    channels
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.name == target || c.id.to_string() == target)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+// 外部XMLTV源与本地频道的对应关系，生成XMLTV时按频道补充节目单
+#[derive(Default)]
+pub(crate) struct ExternalEpg {
+    sources: Arc<Vec<XmltvDocument>>,
+    // 本地频道ID -> (源序号, 外部频道ID)，按源的顺序排列
+    targets: HashMap<u64, Vec<(usize, String)>>,
+}
+
+impl ExternalEpg {
+    // 用外部节目补充频道节目单，上游节目单优先，其次按源的顺序；返回补充的节目数
+    pub(crate) fn fill(&self, channel_id: u64, epg: &mut Vec<Program>) -> usize {
+        self.targets
+            .get(&channel_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|(source, ext_id)| self.sources.get(*source)?.programmes.get(ext_id))
+            .map(|programmes| fill_gaps(epg, programmes))
+            .sum()
+    }
+}
+
+// 匹配外部XMLTV源中的频道，节目在生成时才按频道补充
+pub(crate) async fn external(args: &Args, channels: &[Channel]) -> ExternalEpg {
+    if args.extra_xmltv.is_empty() {
+        return ExternalEpg::default();
+    }
+    let sources = sources(args).await;
+
+    // 外部频道ID或名称 -> 本地频道名或ID
+    let explicit = args
+        .xmltv_mapping
+        .as_deref()
+        .map(crate::mapping::parse_channel_mapping)
+        .unwrap_or_default();
+    let mut by_name: HashMap<String, Vec<usize>> = HashMap::new();
+    // 额外播放列表频道的 tvg-id
+    let mut by_tvg_id: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, channel) in channels.iter().enumerate() {
+        by_name.entry(normalize_name(&channel.name)).or_default().push(i);
+        if let Some(tvg_id) = channel.extra.as_ref().and_then(|attrs| attrs.get("tvg-id")) {
+            by_tvg_id.entry(tvg_id.clone()).or_default().push(i);
+        }
+    }
+
+    let mut targets: HashMap<u64, Vec<(usize, String)>> = HashMap::new();
+    for (index, (source, document)) in args.extra_xmltv.iter().zip(sources.iter()).enumerate() {
+        let names = document.channels.iter().cloned().collect::<HashMap<_, _>>();
+        let mut matched = 0;
+        for ext_id in document.programmes.keys() {
+            let ext_names = names.get(ext_id).cloned().unwrap_or_default();
+            let found = std::iter::once(ext_id)
+                .chain(ext_names.iter())
+                .find_map(|key| explicit.get(key))
+                .map(|target| find_channels(channels, target))
+                .or_else(|| by_tvg_id.get(ext_id).cloned())
+                .or_else(|| {
+                    ext_names
+                        .iter()
+                        .chain(std::iter::once(ext_id))
+                        .find_map(|name| by_name.get(&normalize_name(name)).cloned())
+                })
+                .unwrap_or_default();
+            if found.is_empty() {
+                debug!("XMLTV source {}: channel {} ({:?}) not matched", source, ext_id, ext_names);
+                continue;
+            }
+            matched += 1;
+            for i in found {
+                targets.entry(channels[i].id).or_default().push((index, ext_id.clone()));
+            }
+        }
+        info!("XMLTV source {}: matched {} channels", source, matched);
+    }
+    ExternalEpg { sources, targets }
+}